@@ -0,0 +1,4 @@
+fn main() {
+    // the sqlite migrations are embedded at compile time, rebuild when they change
+    println!("cargo:rerun-if-changed=migrations");
+}
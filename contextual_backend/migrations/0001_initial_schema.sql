@@ -0,0 +1,99 @@
+CREATE TABLE IF NOT EXISTS notes (
+    id TEXT PRIMARY KEY NOT NULL,
+    filename TEXT NOT NULL,
+    project_dir TEXT NOT NULL,
+    selection TEXT NOT NULL,
+    content TEXT NOT NULL,
+    -- JSON array
+    tags TEXT NOT NULL DEFAULT '[]',
+    -- Where the selection was last found, used to re-anchor the note when the
+    -- file changes. The context lines are JSON arrays.
+    start_line INTEGER,
+    end_line INTEGER,
+    context_before TEXT NOT NULL DEFAULT '[]',
+    context_after TEXT NOT NULL DEFAULT '[]',
+    orphaned INTEGER NOT NULL DEFAULT 0,
+    -- Columns of the selection, counted in the encoding the client used.
+    start_column INTEGER,
+    end_column INTEGER,
+    position_encoding TEXT NOT NULL DEFAULT 'utf-16',
+    -- The repository state the note was created in, as a JSON object. NULL
+    -- outside of a git repository.
+    git TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_notes_project_dir ON notes (project_dir);
+CREATE INDEX IF NOT EXISTS idx_notes_filename ON notes (filename);
+
+CREATE TABLE IF NOT EXISTS todos (
+    id TEXT PRIMARY KEY NOT NULL,
+    hash TEXT NOT NULL,
+    project_dir TEXT NOT NULL,
+    branch TEXT NOT NULL,
+    file_path TEXT NOT NULL,
+    line_number INTEGER NOT NULL,
+    -- The last line of a todo continuing over several comment lines.
+    end_line_number INTEGER NOT NULL,
+    content TEXT NOT NULL,
+    -- The content in lowercase, as SQLite's lower() only folds ASCII.
+    content_lower TEXT NOT NULL,
+    -- What marks the todo, e.g. FIXME or todo!.
+    kind TEXT NOT NULL,
+    -- Owner, issue, priority and due date written with the marker, as a JSON
+    -- object.
+    marker TEXT NOT NULL,
+    -- RFC 3339 in UTC with nanoseconds, which sorts like the times.
+    created_at TEXT NOT NULL,
+    deleted_at TEXT,
+    -- JSON arrays of every tag and of the tags added apart from the content.
+    tags TEXT NOT NULL,
+    added_tags TEXT NOT NULL,
+    -- The repository state the todo was created in, as a JSON object. NULL
+    -- outside of a git repository.
+    git TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_todos_branch ON todos (branch);
+CREATE INDEX IF NOT EXISTS idx_todos_file_path ON todos (file_path);
+CREATE INDEX IF NOT EXISTS idx_todos_project_branch ON todos (project_dir, branch);
+CREATE INDEX IF NOT EXISTS idx_todos_hash ON todos (hash);
+
+-- Full-text index over notes and active todos, kept in sync by triggers.
+CREATE VIRTUAL TABLE search_index USING fts5 (
+    kind UNINDEXED,
+    id UNINDEXED,
+    project_dir UNINDEXED,
+    content,
+    selection,
+    tokenize = 'unicode61'
+);
+
+CREATE TRIGGER notes_search_insert AFTER INSERT ON notes BEGIN
+    INSERT INTO search_index (kind, id, project_dir, content, selection)
+    VALUES ('note', new.id, new.project_dir, new.content, new.selection);
+END;
+
+CREATE TRIGGER notes_search_update AFTER UPDATE ON notes BEGIN
+    DELETE FROM search_index WHERE kind = 'note' AND id = old.id;
+    INSERT INTO search_index (kind, id, project_dir, content, selection)
+    VALUES ('note', new.id, new.project_dir, new.content, new.selection);
+END;
+
+CREATE TRIGGER notes_search_delete AFTER DELETE ON notes BEGIN
+    DELETE FROM search_index WHERE kind = 'note' AND id = old.id;
+END;
+
+CREATE TRIGGER todos_search_insert AFTER INSERT ON todos WHEN new.deleted_at IS NULL BEGIN
+    INSERT INTO search_index (kind, id, project_dir, content, selection)
+    VALUES ('todo', new.id, new.project_dir, new.content, '');
+END;
+
+CREATE TRIGGER todos_search_update AFTER UPDATE ON todos BEGIN
+    DELETE FROM search_index WHERE kind = 'todo' AND id = old.id;
+    INSERT INTO search_index (kind, id, project_dir, content, selection)
+    SELECT 'todo', new.id, new.project_dir, new.content, '' WHERE new.deleted_at IS NULL;
+END;
+
+CREATE TRIGGER todos_search_delete AFTER DELETE ON todos BEGIN
+    DELETE FROM search_index WHERE kind = 'todo' AND id = old.id;
+END;
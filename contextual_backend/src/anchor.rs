@@ -0,0 +1,355 @@
+//! Locating a note's selection again after its file changed.
+//!
+//! A note remembers its selection, the lines it spanned and a few lines of
+//! context on either side. Re-anchoring first looks for the selection
+//! verbatim; when it was edited, the run of lines most similar to the
+//! selection and its context is taken instead, as long as it is similar
+//! enough.
+
+use std::collections::HashMap;
+
+use crate::types::{AnchorStatus, NoteContext};
+
+/// Lines of context remembered on each side of a selection.
+pub const CONTEXT_LINES: usize = 3;
+
+/// Minimum score of a fuzzy match, a note with no better match is orphaned.
+const FUZZY_THRESHOLD: f64 = 0.6;
+/// Minimum similarity of the selection itself, so matching context alone
+/// can't anchor a note to unrelated code.
+const MIN_SELECTION_SIMILARITY: f64 = 0.5;
+/// Share of the fuzzy score given to the selection, the rest goes to the
+/// context.
+const SELECTION_WEIGHT: f64 = 0.7;
+
+/// Where a selection was found, lines are zero-based and `end_line` is
+/// inclusive.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Located {
+    pub status: AnchorStatus,
+    pub confidence: f64,
+    pub start_line: u64,
+    pub end_line: u64,
+    /// Byte offsets of the selection within its start and end line,
+    /// `end_offset` exclusive.
+    pub start_offset: usize,
+    pub end_offset: usize,
+}
+
+/// Find the selection of `context` in `contents`, `None` when the note is
+/// orphaned.
+pub fn locate(context: &NoteContext, contents: &str) -> Option<Located> {
+    let lines: Vec<&str> = contents.lines().collect();
+
+    locate_exact(context, contents, &lines).or_else(|| locate_fuzzy(context, &lines))
+}
+
+/// The context of a note found at `located`: its new position, with columns in
+/// the note's position encoding, and the lines around it. A fuzzy match also
+/// replaces the selection with the matched lines, so the next re-anchoring can
+/// match it exactly.
+pub fn anchor(context: &NoteContext, contents: &str, located: &Located) -> NoteContext {
+    let lines: Vec<&str> = contents.lines().collect();
+    let start = (located.start_line as usize).min(lines.len());
+    let end = (located.end_line as usize + 1).clamp(start, lines.len());
+
+    let selection = match located.status {
+        AnchorStatus::Fuzzy => lines[start..end].join("\n"),
+        _ => context.selection.clone(),
+    };
+    let column = |line: u64, offset: usize| {
+        let line = lines.get(line as usize).copied().unwrap_or_default();
+        let prefix = line.get(..offset).unwrap_or(line);
+        context.position_encoding.column(prefix)
+    };
+
+    NoteContext {
+        filename: context.filename.clone(),
+        project_dir: context.project_dir.clone(),
+        selection,
+        start_line: Some(located.start_line),
+        end_line: Some(located.end_line),
+        start_column: Some(column(located.start_line, located.start_offset)),
+        end_column: Some(column(located.end_line, located.end_offset)),
+        position_encoding: context.position_encoding,
+        context_before: to_owned(&lines[start.saturating_sub(CONTEXT_LINES)..start]),
+        context_after: to_owned(&lines[end..(end + CONTEXT_LINES).min(lines.len())]),
+    }
+}
+
+fn to_owned(lines: &[&str]) -> Vec<String> {
+    lines.iter().map(|line| line.to_string()).collect()
+}
+
+/// Every verbatim occurrence of the selection, ranked by how well its
+/// surroundings match the stored context and then by how close it is to the
+/// stored position.
+fn locate_exact(context: &NoteContext, contents: &str, lines: &[&str]) -> Option<Located> {
+    if context.selection.trim().is_empty() {
+        return None;
+    }
+    let selection = context.selection.trim_end_matches('\n');
+    let span = selection.matches('\n').count();
+    // offset of the selection's end within its last line
+    let last_line_len = selection.len() - selection.rfind('\n').map_or(0, |i| i + 1);
+
+    let mut candidates: Vec<(usize, usize, Option<f64>)> = contents
+        .match_indices(context.selection.as_str())
+        .map(|(offset, _)| {
+            let start = contents[..offset].matches('\n').count();
+            let start_offset = offset - contents[..offset].rfind('\n').map_or(0, |i| i + 1);
+            (
+                start,
+                start_offset,
+                context_similarity(context, lines, start, start + span),
+            )
+        })
+        .collect();
+    candidates.sort_by(|(a, _, a_score), (b, _, b_score)| {
+        b_score
+            .unwrap_or(0.0)
+            .total_cmp(&a_score.unwrap_or(0.0))
+            .then(distance(context, *a).cmp(&distance(context, *b)))
+    });
+
+    let (start, start_offset, score) = *candidates.first()?;
+    let confidence = match candidates.get(1) {
+        None => 1.0,
+        // the context tells the occurrences apart
+        Some((_, _, runner_up)) if score.unwrap_or(0.0) > runner_up.unwrap_or(0.0) => 0.95,
+        // only the previous position does
+        Some(_) => 0.8,
+    };
+
+    Some(Located {
+        status: AnchorStatus::Exact,
+        confidence,
+        start_line: start as u64,
+        end_line: (start + span) as u64,
+        start_offset,
+        end_offset: if span == 0 {
+            start_offset + last_line_len
+        } else {
+            last_line_len
+        },
+    })
+}
+
+/// The most similar run of lines, which may have grown or shrunk by a line.
+fn locate_fuzzy(context: &NoteContext, lines: &[&str]) -> Option<Located> {
+    let selection = Bigrams::new(&context.selection);
+    if selection.normalised.is_empty() {
+        return None;
+    }
+    let span = context
+        .selection
+        .trim_end_matches('\n')
+        .lines()
+        .count()
+        .max(1);
+
+    let mut best: Option<(f64, usize, usize)> = None;
+    for len in [span, span + 1, span - 1] {
+        if len == 0 || len > lines.len() {
+            continue;
+        }
+        for start in 0..=lines.len() - len {
+            let end = start + len - 1;
+            let similarity = selection.similarity(&Bigrams::new(&lines[start..=end].join("\n")));
+            if similarity < MIN_SELECTION_SIMILARITY {
+                continue;
+            }
+            let score = match context_similarity(context, lines, start, end) {
+                Some(context) => SELECTION_WEIGHT * similarity + (1.0 - SELECTION_WEIGHT) * context,
+                None => similarity,
+            };
+
+            let is_better = best.is_none_or(|(best_score, best_start, _)| {
+                score > best_score
+                    || (score == best_score
+                        && distance(context, start) < distance(context, best_start))
+            });
+            if is_better {
+                best = Some((score, start, end));
+            }
+        }
+    }
+
+    let (score, start, end) = best.filter(|(score, _, _)| *score >= FUZZY_THRESHOLD)?;
+
+    Some(Located {
+        status: AnchorStatus::Fuzzy,
+        // only an exact match is certain
+        confidence: score.min(0.99),
+        start_line: start as u64,
+        end_line: end as u64,
+        start_offset: 0,
+        end_offset: lines[end].len(),
+    })
+}
+
+/// How well the lines around `start..=end` match the stored context, `None`
+/// when the note has no stored context.
+fn context_similarity(
+    context: &NoteContext,
+    lines: &[&str],
+    start: usize,
+    end: usize,
+) -> Option<f64> {
+    let mut scores = Vec::new();
+    if !context.context_before.is_empty() {
+        let from = start.saturating_sub(context.context_before.len());
+        let found = lines.get(from..start).unwrap_or_default().join("\n");
+        scores.push(
+            Bigrams::new(&context.context_before.join("\n")).similarity(&Bigrams::new(&found)),
+        );
+    }
+    if !context.context_after.is_empty() {
+        let from = (end + 1).min(lines.len());
+        let to = (from + context.context_after.len()).min(lines.len());
+        let found = lines[from..to].join("\n");
+        scores.push(
+            Bigrams::new(&context.context_after.join("\n")).similarity(&Bigrams::new(&found)),
+        );
+    }
+
+    (!scores.is_empty()).then(|| scores.iter().sum::<f64>() / scores.len() as f64)
+}
+
+/// Lines between `start` and the stored position, candidates without a stored
+/// position are all equally close.
+fn distance(context: &NoteContext, start: usize) -> u64 {
+    context
+        .start_line
+        .map_or(0, |line| line.abs_diff(start as u64))
+}
+
+/// The character bigrams of whitespace-normalised text, compared with the
+/// Sørensen–Dice coefficient.
+struct Bigrams {
+    normalised: String,
+    counts: HashMap<(char, char), usize>,
+    total: usize,
+}
+
+impl Bigrams {
+    fn new(text: &str) -> Self {
+        let normalised = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        let chars: Vec<char> = normalised.chars().collect();
+        let mut counts = HashMap::new();
+        for pair in chars.windows(2) {
+            *counts.entry((pair[0], pair[1])).or_insert(0) += 1;
+        }
+
+        Self {
+            total: chars.len().saturating_sub(1),
+            normalised,
+            counts,
+        }
+    }
+
+    fn similarity(&self, other: &Bigrams) -> f64 {
+        if self.total == 0 || other.total == 0 {
+            return if self.normalised == other.normalised {
+                1.0
+            } else {
+                0.0
+            };
+        }
+
+        let shared: usize = self
+            .counts
+            .iter()
+            .map(|(bigram, count)| (*count).min(other.counts.get(bigram).copied().unwrap_or(0)))
+            .sum();
+
+        2.0 * shared as f64 / (self.total + other.total) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        anchor::{anchor, locate},
+        types::{AnchorStatus, NoteContext, position::PositionEncoding},
+    };
+
+    fn context(selection: &str) -> NoteContext {
+        NoteContext {
+            filename: "src/lib.rs".to_string(),
+            project_dir: "/project".to_string(),
+            selection: selection.to_string(),
+            ..Default::default()
+        }
+    }
+
+    const ORIGINAL: &str = "use std::io;\n\nfn parse(input: &str) -> Config {\n    let mut config = Config::default();\n    config\n}\n\nfn main() {\n    run();\n}\n";
+
+    #[test]
+    fn moved_selections_are_found_exactly() {
+        let note = context("fn parse(input: &str) -> Config {");
+        let located = locate(&note, ORIGINAL).unwrap();
+        assert_eq!((located.start_line, located.end_line), (2, 2));
+        let note = anchor(&note, ORIGINAL, &located);
+        assert_eq!(note.context_before, vec!["use std::io;", ""]);
+
+        let moved = format!("// header\n// more header\n{ORIGINAL}");
+        let located = locate(&note, &moved).unwrap();
+
+        assert_eq!(located.status, AnchorStatus::Exact);
+        assert_eq!(located.confidence, 1.0);
+        assert_eq!((located.start_line, located.end_line), (4, 4));
+    }
+
+    #[test]
+    fn columns_follow_the_position_encoding() {
+        let contents = "// café\nlet crab = \"🦀\"; let total = 1;\n";
+        let mut note = context("let total");
+        for (encoding, columns) in [
+            (PositionEncoding::Utf8, (19, 28)),
+            (PositionEncoding::Utf16, (17, 26)),
+            (PositionEncoding::Utf32, (16, 25)),
+        ] {
+            note.position_encoding = encoding;
+            let located = locate(&note, contents).unwrap();
+            let anchored = anchor(&note, contents, &located);
+
+            assert_eq!(anchored.start_line, Some(1));
+            assert_eq!(
+                (anchored.start_column, anchored.end_column),
+                (Some(columns.0), Some(columns.1))
+            );
+        }
+    }
+
+    #[test]
+    fn repeated_selections_are_told_apart_by_context() {
+        let contents = "fn a() {\n    retry();\n}\n\nfn b() {\n    retry();\n}\n";
+        let mut note = context("    retry();");
+        note.context_before = vec!["fn b() {".to_string()];
+        note.context_after = vec!["}".to_string()];
+
+        let located = locate(&note, contents).unwrap();
+
+        assert_eq!(located.start_line, 5);
+        assert_eq!(located.confidence, 0.95);
+    }
+
+    #[test]
+    fn edited_selections_are_found_fuzzily_or_orphaned() {
+        let note =
+            context("fn parse(input: &str) -> Config {\n    let mut config = Config::default();");
+        let located = locate(&note, ORIGINAL).unwrap();
+        let note = anchor(&note, ORIGINAL, &located);
+
+        let edited = ORIGINAL.replace("fn parse(input: &str)", "fn parse_config(input: &str)");
+        let located = locate(&note, &edited).unwrap();
+        assert_eq!(located.status, AnchorStatus::Fuzzy);
+        assert_eq!((located.start_line, located.end_line), (2, 3));
+        assert!(located.confidence > 0.6 && located.confidence < 1.0);
+        let reanchored = anchor(&note, &edited, &located);
+        assert!(reanchored.selection.starts_with("fn parse_config"));
+
+        assert_eq!(locate(&note, "fn main() {\n    run();\n}\n"), None);
+    }
+}
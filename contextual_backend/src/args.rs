@@ -1,8 +1,15 @@
-use clap::{Error, Parser, ValueEnum, error::ErrorKind};
+use std::path::PathBuf;
+
+use clap::{Error, Parser, Subcommand, ValueEnum, error::ErrorKind};
+
+use crate::migrate::StorageLocation;
 
 #[derive(Parser)]
 #[command(name = "Contextual Backend")]
 pub struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Transport type (unix, tcp, stdio)
     #[arg(
         short = 't',
@@ -22,6 +29,91 @@ pub struct Args {
 
     /// Unix socket path (required when transport is unix)
     socket: Option<String>,
+
+    /// Directory for persisted data [default: <user data dir>/contextual]
+    #[arg(long, value_name = "DIR", env = "CONTEXTUAL_DATA_DIR")]
+    data_dir: Option<PathBuf>,
+
+    /// Storage backend (file, sqlite, memory)
+    #[arg(
+        long,
+        value_name = "BACKEND",
+        env = "CONTEXTUAL_STORAGE",
+        default_value_t = StorageBackend::File
+    )]
+    storage: StorageBackend,
+
+    /// Rewrite file records stored in an older format when they are read
+    #[arg(long, env = "CONTEXTUAL_REWRITE_UPGRADED")]
+    rewrite_upgraded: bool,
+
+    /// Configuration file [default: <user config dir>/contextual/config.toml]
+    #[arg(long, value_name = "FILE", env = "CONTEXTUAL_CONFIG")]
+    config: Option<PathBuf>,
+}
+
+/// Subcommands run instead of the server.
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Copy all notes and todos from one store to another, keeping ids and
+    /// timestamps
+    Migrate {
+        /// Store to read from, as file:<dir> or sqlite:<path>
+        #[arg(long, value_name = "BACKEND:PATH")]
+        from: StorageLocation,
+
+        /// Store to write to, as file:<dir> or sqlite:<path>
+        #[arg(long, value_name = "BACKEND:PATH")]
+        to: StorageLocation,
+
+        /// Only report what would be copied
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Scan a project for todos and print them as JSON
+    ScanTodos {
+        /// Project to scan
+        #[arg(value_name = "DIR", default_value = ".")]
+        project_dir: PathBuf,
+
+        /// Branch to record outside of a git repository
+        #[arg(long, value_name = "BRANCH")]
+        branch: Option<String>,
+
+        /// Marker starting a todo, may be repeated [default: TODO, FIXME, HACK]
+        #[arg(short = 'k', long = "keyword", value_name = "KEYWORD")]
+        keywords: Vec<String>,
+
+        /// Also report placeholders in code, like todo!() and unimplemented!()
+        #[arg(long)]
+        placeholders: bool,
+
+        /// Reconcile the scan with the stored todos, like contextual/sync_todos
+        #[arg(long)]
+        sync: bool,
+    },
+}
+
+/// Where notes and todos are stored.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum StorageBackend {
+    /// One JSON file per record in the data directory
+    File,
+    /// A single SQLite database in the data directory
+    Sqlite,
+    /// Nothing is persisted, all data is lost when the backend exits
+    Memory,
+}
+
+impl std::fmt::Display for StorageBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let backend = match self {
+            StorageBackend::File => "file",
+            StorageBackend::Sqlite => "sqlite",
+            StorageBackend::Memory => "memory",
+        };
+        write!(f, "{}", backend)
+    }
 }
 
 #[derive(Debug, Clone, ValueEnum)]
@@ -64,6 +156,11 @@ impl Args {
                 },
                 Transport::Stdio => TransportType::Stdio,
             },
+            data_dir: args.data_dir,
+            storage: args.storage,
+            rewrite_upgraded: args.rewrite_upgraded,
+            config: args.config,
+            command: args.command,
         }
     }
 
@@ -102,6 +199,15 @@ impl Args {
 
 pub struct ValidatedArgs {
     pub transport: TransportType,
+    /// Explicitly configured data directory, see
+    /// [crate::database::default_data_dir] for the fallback.
+    pub data_dir: Option<PathBuf>,
+    pub storage: StorageBackend,
+    pub rewrite_upgraded: bool,
+    /// Explicitly given configuration file, see
+    /// [crate::config::default_config_path] for the fallback.
+    pub config: Option<PathBuf>,
+    pub command: Option<Command>,
 }
 
 pub enum TransportType {
@@ -0,0 +1,136 @@
+//! The optional configuration file, `config.toml` in the user's config
+//! directory unless given with `--config`.
+//!
+//! ```toml
+//! [scan]
+//! keywords = ["TODO", "FIXME", "XXX"]
+//!
+//! [[scan.languages]]
+//! name = "fennel"
+//! extensions = ["fnl"]
+//! line_comments = [";"]
+//!
+//! [watch]
+//! enabled = true
+//! debounce_ms = 300
+//! ```
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::scan::{ScanConfig, ScanOptions};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub scan: ScanConfig,
+    pub watch: WatchConfig,
+}
+
+/// Whether clients may have projects watched for changes, see
+/// [crate::watch::Watcher].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WatchConfig {
+    pub enabled: bool,
+    /// How long a burst of changes has to settle before it is rescanned.
+    pub debounce_ms: u64,
+}
+
+impl WatchConfig {
+    pub fn debounce(&self) -> Duration {
+        Duration::from_millis(self.debounce_ms)
+    }
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            debounce_ms: 300,
+        }
+    }
+}
+
+impl Config {
+    /// Load the configuration from `path`, or from the default location when
+    /// there is no `path`. Only the default file may be missing.
+    pub fn load(path: Option<&Path>) -> Result<Self, anyhow::Error> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match default_config_path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Self::default());
+            }
+            Err(e) => return Err(e).with_context(|| format!("cannot read {}", path.display())),
+        };
+
+        Self::parse(&text).with_context(|| format!("invalid config {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, anyhow::Error> {
+        let config: Config = toml::from_str(text)?;
+        config.scan.validate()?;
+
+        Ok(config)
+    }
+
+    /// The options of scans, before a request overrides any of them.
+    pub fn scan_options(&self) -> ScanOptions {
+        ScanOptions::default().apply(self.scan.clone())
+    }
+}
+
+/// `<user config dir>/contextual/config.toml`.
+pub fn default_config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("contextual").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{config::Config, scan::scan_text};
+
+    #[test]
+    fn languages_are_extended_and_overridden() {
+        let config = Config::parse(
+            r#"
+            [scan]
+            keywords = ["XXX"]
+
+            [[scan.languages]]
+            name = "fennel"
+            extensions = ["fnl"]
+            line_comments = [";"]
+
+            [[scan.languages]]
+            name = "lua"
+            extensions = ["lua"]
+            line_comments = ["--"]
+            "#,
+        )
+        .unwrap();
+        let options = config.scan_options();
+
+        let fennel = options.language_of(Path::new("init.fnl")).unwrap();
+        let found = scan_text("(print \"XXX\") ; XXX: fix", fennel, &options);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].kind, "XXX");
+        let lua = options.language_of(Path::new("init.lua")).unwrap();
+        assert!(lua.block_comments.is_empty());
+        assert!(Config::parse("[scan]\nkeyword = [\"XXX\"]\n").is_err());
+    }
+}
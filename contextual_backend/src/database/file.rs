@@ -1,138 +1,1068 @@
-use std::path::PathBuf;
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
 
 use anyhow::Context;
-use serde::Serialize;
+use chrono::Utc;
 use uuid::Uuid;
 
 use crate::{
-    database::{NoteStorage, TodoStorage},
+    database::{
+        NoteStorage, SearchStorage, StorageError, TodoChanges, TodoStorage, find_duplicate,
+        paginate_todos,
+        record::{self, DecodeError, Decoded, Record},
+    },
+    search::SearchIndex,
     types::{
-        NewNote, Note,
-        todo::{NewTodoItem, TodoItem},
+        NewNote, Note, NotePatch,
+        search::{DocKind, SearchHit, SearchQuery},
+        todo::{NewTodoItem, TodoItem, TodoPage, TodoPatch, TodoQuery},
     },
 };
 
+/// Advisory lock file in the data root, held while reading or writing records
+/// so several backend processes can share one store.
+const LOCK_FILE: &str = ".lock";
+/// Directory in the data root unreadable records are moved to.
+const QUARANTINE_DIR: &str = "quarantine";
+/// File in the data root counting the writes to the store.
+const GENERATION_FILE: &str = "generation";
+
+/// Stores each note as `notes/<id>` and each todo as `<id>` in the data root,
+/// serialised as versioned JSON, see [record].
+///
+/// Records are written to a temporary file which is synced and then renamed
+/// over the record, so a crash never leaves a half written record behind.
+/// Every operation holds an advisory lock on [LOCK_FILE], shared for reads and
+/// exclusive for writes.
+///
+/// The search index is built on the first search and then kept up to date by
+/// this process' writes. Writes from other processes are noticed through
+/// [GENERATION_FILE], which every write bumps, and the modification times of
+/// the record directories, which makes the next search rebuild the index.
 #[derive(Clone)]
 pub struct FileDatabase {
     dir: PathBuf,
+    rewrite_upgraded: bool,
+    /// Neither write records nor move unreadable ones to the quarantine.
+    read_only: bool,
+    index: Arc<Mutex<Option<CachedIndex>>>,
+    /// Changes reads found necessary, made under the exclusive lock once the
+    /// read is done.
+    deferred: Arc<Mutex<Vec<Deferred>>>,
 }
 
-impl FileDatabase {
-    pub async fn init() -> Self {
-        // TODO: error handling
-        let dir = dirs::data_dir()
-            .expect("unable to determine 'data' directory")
-            .join("contextual");
+/// A change to a record a read found necessary, such as moving it to the
+/// quarantine. It checks again that the change is still needed and reports
+/// its own failure.
+type Deferred = Box<dyn FnOnce(&FileDatabase) + Send>;
+
+/// A record which can't be read, see [FileDatabase::unreadable_records].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnreadableRecord {
+    pub kind: DocKind,
+    pub id: Uuid,
+    pub reason: String,
+}
+
+/// The write generation and the modification times of the todo and note
+/// directories. The generation catches writes within the resolution of the
+/// modification times.
+type Stamp = (u64, Option<SystemTime>, Option<SystemTime>);
+
+struct CachedIndex {
+    index: SearchIndex,
+    /// The state of the directories the index reflects.
+    stamp: Stamp,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum LockMode {
+    Shared,
+    Exclusive,
+}
+
+/// Outcome of loading a single record from disk.
+enum Loaded<D> {
+    Record(D),
+    Missing,
+    /// The record could not be parsed, it is moved to the quarantine after
+    /// the read unless the store is read-only.
+    Unreadable,
+    /// The record was written by a newer version and is left untouched.
+    Unsupported,
+}
 
+impl FileDatabase {
+    /// Use `dir` for storage, creating it if it doesn't exist yet.
+    pub async fn init(dir: impl Into<PathBuf>) -> Result<Self, anyhow::Error> {
+        let dir = dir.into();
         if !tokio::fs::try_exists(&dir)
             .await
-            .expect("could not check for existing directory")
+            .with_context(|| format!("could not check for directory {}", dir.display()))?
         {
             tokio::fs::create_dir_all(&dir)
                 .await
-                .expect("failed to create directory for file based storage");
+                .with_context(|| format!("failed to create storage directory {}", dir.display()))?;
+        }
+
+        Ok(Self {
+            dir,
+            rewrite_upgraded: false,
+            read_only: false,
+            index: Arc::default(),
+            deferred: Arc::default(),
+        })
+    }
+
+    /// Use the existing store in `dir` without ever changing it, not even to
+    /// quarantine unreadable records. Writes fail.
+    pub async fn open_read_only(dir: impl Into<PathBuf>) -> Result<Self, anyhow::Error> {
+        let dir = dir.into();
+        let metadata = tokio::fs::metadata(&dir)
+            .await
+            .with_context(|| format!("cannot open storage directory {}", dir.display()))?;
+        if !metadata.is_dir() {
+            anyhow::bail!("{} is not a directory", dir.display());
+        }
+
+        Ok(Self {
+            dir,
+            rewrite_upgraded: false,
+            read_only: true,
+            index: Arc::default(),
+            deferred: Arc::default(),
+        })
+    }
+
+    /// Write records stored in an older format back in the current format
+    /// when they are read, instead of upgrading them on every read.
+    pub fn rewrite_upgraded(mut self, rewrite: bool) -> Self {
+        self.rewrite_upgraded = rewrite;
+        self
+    }
+
+    /// Run `op` on a blocking thread while holding the store's lock.
+    async fn with_lock<T, F>(&self, mode: LockMode, op: F) -> Result<T, StorageError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&FileDatabase) -> Result<T, StorageError> + Send + 'static,
+    {
+        if self.read_only && matches!(mode, LockMode::Exclusive) {
+            return Err(StorageError::Invalid(format!(
+                "{} is opened read-only",
+                self.dir.display()
+            )));
+        }
+
+        let db = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let result = db.locked(mode, op);
+            // a read leaves the changes it found necessary to a write
+            if matches!(mode, LockMode::Shared)
+                && db.has_deferred()
+                && let Err(e) = db.locked(LockMode::Exclusive, |_| Ok(()))
+            {
+                log::warn!(
+                    "Failed to lock {} for changes found while reading: {e}",
+                    db.dir.display()
+                );
+            }
+            result
+        })
+        .await?
+    }
+
+    /// Run `op` while holding the store's lock, followed by the deferred
+    /// changes when the lock is exclusive.
+    fn locked<T>(
+        &self,
+        mode: LockMode,
+        op: impl FnOnce(&FileDatabase) -> Result<T, StorageError>,
+    ) -> Result<T, StorageError> {
+        let lock_path = self.dir.join(LOCK_FILE);
+        let lock = match self.read_only {
+            true => std::fs::File::open(&lock_path),
+            false => std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&lock_path)
+                // a read-only directory can still be locked for reading
+                .or_else(|_| std::fs::File::open(&lock_path)),
+        };
+        let lock = match (lock, mode) {
+            (Ok(lock), LockMode::Shared) => Some(lock.lock_shared().map(|_| lock)),
+            (Ok(lock), LockMode::Exclusive) => Some(lock.lock().map(|_| lock)),
+            // nobody can write to a store without a lock file, so reading
+            // it unlocked is safe
+            (Err(e), LockMode::Shared) if e.kind() == std::io::ErrorKind::NotFound => None,
+            (Err(e), _) => Some(Err(e)),
+        }
+        .transpose()?;
+
+        let exclusive = matches!(mode, LockMode::Exclusive);
+        // the index can only follow our writes if nobody else wrote since
+        // it was last synced
+        let index_was_current = exclusive && self.index_is_current();
+        let mut result = op(self);
+        if exclusive {
+            self.run_deferred();
+            // even a failed write may have changed some records
+            let bumped = self.bump_generation();
+            self.sync_index_stamp(index_was_current && result.is_ok() && bumped.is_ok());
+            result = result.and_then(|value| bumped.map(|_| value));
+        }
+        drop(lock);
+        result
+    }
+
+    fn defer(&self, change: impl FnOnce(&FileDatabase) + Send + 'static) {
+        self.deferred
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(Box::new(change));
+    }
+
+    fn has_deferred(&self) -> bool {
+        !self
+            .deferred
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .is_empty()
+    }
+
+    /// Make the deferred changes, only called under the exclusive lock.
+    fn run_deferred(&self) {
+        let deferred =
+            std::mem::take(&mut *self.deferred.lock().unwrap_or_else(|e| e.into_inner()));
+        for change in deferred {
+            change(self);
+        }
+    }
+
+    fn cached_index(&self) -> std::sync::MutexGuard<'_, Option<CachedIndex>> {
+        // the index is only replaced or updated in place, a panic can't leave
+        // it half updated
+        self.index.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn stamp(&self) -> Stamp {
+        let modified = |dir: &Path| std::fs::metadata(dir).and_then(|m| m.modified()).ok();
+        (
+            self.generation(),
+            modified(&self.dir),
+            modified(&self.note_dir()),
+        )
+    }
+
+    fn generation(&self) -> u64 {
+        std::fs::read_to_string(self.dir.join(GENERATION_FILE))
+            .ok()
+            .and_then(|generation| generation.trim().parse().ok())
+            .unwrap_or(0)
+    }
+
+    /// Count a write, only called under the exclusive lock.
+    fn bump_generation(&self) -> Result<(), StorageError> {
+        let generation = self.generation().wrapping_add(1);
+        std::fs::write(self.dir.join(GENERATION_FILE), generation.to_string())?;
+        Ok(())
+    }
+
+    fn index_is_current(&self) -> bool {
+        let stamp = self.stamp();
+        self.cached_index()
+            .as_ref()
+            .is_some_and(|cached| cached.stamp == stamp)
+    }
+
+    /// After a write, either record that the index reflects the directories
+    /// again or drop it to have it rebuilt.
+    fn sync_index_stamp(&self, keep: bool) {
+        let stamp = self.stamp();
+        let mut cached = self.cached_index();
+        match cached.as_mut() {
+            Some(cached) if keep => cached.stamp = stamp,
+            _ => *cached = None,
+        }
+    }
+
+    fn update_index(&self, update: impl FnOnce(&mut SearchIndex)) {
+        if let Some(cached) = self.cached_index().as_mut() {
+            update(&mut cached.index);
+        }
+    }
+
+    fn write_note(&self, note: &Note) -> Result<(), StorageError> {
+        std::fs::create_dir_all(self.note_dir())?;
+        write_file(&self.note_file(note.id), note)?;
+        self.update_index(|index| index.index_note(note));
+        Ok(())
+    }
+
+    fn write_todo(&self, todo_item: &TodoItem) -> Result<(), StorageError> {
+        write_file(&self.todo_file(todo_item.id), todo_item)?;
+        self.update_index(|index| index.index_todo(todo_item));
+        Ok(())
+    }
+
+    fn note_dir(&self) -> PathBuf {
+        self.dir.join("notes")
+    }
+
+    fn note_file(&self, note_id: Uuid) -> PathBuf {
+        self.note_dir().join(note_id.to_string())
+    }
+
+    fn todo_file(&self, todo_id: Uuid) -> PathBuf {
+        self.dir.join(todo_id.to_string())
+    }
+
+    fn read_note(&self, note_id: Uuid) -> Result<Note, StorageError> {
+        match self.read_file(&self.note_file(note_id))? {
+            Loaded::Record(note) => Ok(note),
+            Loaded::Missing => Err(StorageError::note_not_found(note_id)),
+            Loaded::Unreadable => Err(StorageError::Corrupt(format!(
+                "note {note_id} is unreadable"
+            ))),
+            Loaded::Unsupported => Err(StorageError::Corrupt(format!(
+                "note {note_id} was written by a newer version"
+            ))),
+        }
+    }
+
+    fn read_todo(&self, todo_id: Uuid) -> Result<TodoItem, StorageError> {
+        match self.read_file(&self.todo_file(todo_id))? {
+            Loaded::Record(todo_item) => Ok(todo_item),
+            Loaded::Missing => Err(StorageError::todo_not_found(todo_id)),
+            Loaded::Unreadable => Err(StorageError::Corrupt(format!(
+                "todo {todo_id} is unreadable"
+            ))),
+            Loaded::Unsupported => Err(StorageError::Corrupt(format!(
+                "todo {todo_id} was written by a newer version"
+            ))),
+        }
+    }
+
+    fn read_notes(&self) -> Result<Vec<Note>, StorageError> {
+        self.read_records(&self.note_dir())
+    }
+
+    fn read_todos(&self) -> Result<Vec<TodoItem>, StorageError> {
+        self.read_records(&self.dir)
+    }
+
+    /// Read every record in `dir`, skipping (and quarantining) unreadable ones
+    /// as well as records from a newer version.
+    fn read_records<R: Record + Send + 'static>(&self, dir: &Path) -> Result<Vec<R>, StorageError> {
+        let mut records = Vec::new();
+        for (_, path) in record_paths(dir)? {
+            if let Loaded::Record(record) = self.read_file(&path)? {
+                records.push(record);
+            }
         }
 
-        Self { dir }
+        Ok(records)
+    }
+
+    /// The records which reads skip, because they are unreadable or were
+    /// written by a newer version.
+    pub async fn unreadable_records(&self) -> Result<Vec<UnreadableRecord>, StorageError> {
+        self.with_lock(LockMode::Shared, |db| {
+            let mut unreadable = Vec::new();
+            for (kind, dir) in [
+                (DocKind::Note, db.note_dir()),
+                (DocKind::Todo, db.dir.clone()),
+            ] {
+                for (id, path) in record_paths(&dir)? {
+                    let error = match kind {
+                        DocKind::Note => decode_file::<Note>(&path)?.and_then(Result::err),
+                        DocKind::Todo => decode_file::<TodoItem>(&path)?.and_then(Result::err),
+                    };
+                    if let Some(e) = error {
+                        unreadable.push(UnreadableRecord {
+                            kind,
+                            id,
+                            reason: e.to_string(),
+                        });
+                    }
+                }
+            }
+
+            Ok(unreadable)
+        })
+        .await
+    }
+
+    /// Read the record at `path`. Rewriting an upgraded record and moving an
+    /// unreadable one to the quarantine are deferred to the exclusive lock.
+    fn read_file<R: Record + Send + 'static>(
+        &self,
+        path: &Path,
+    ) -> Result<Loaded<R>, StorageError> {
+        let Some(decoded) = decode_file(path)? else {
+            return Ok(Loaded::Missing);
+        };
+
+        match decoded {
+            Ok(Decoded { record, upgraded }) => {
+                if upgraded && self.rewrite_upgraded && !self.read_only {
+                    let path = path.to_path_buf();
+                    self.defer(move |_| {
+                        // unless it was rewritten or removed in the meantime
+                        let result = match decode_file::<R>(&path) {
+                            Ok(Some(Ok(Decoded {
+                                record,
+                                upgraded: true,
+                            }))) => write_file(&path, &record),
+                            Ok(_) => Ok(()),
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = result {
+                            log::warn!("Failed to rewrite upgraded {}: {e}", path.display());
+                        }
+                    });
+                }
+                Ok(Loaded::Record(record))
+            }
+            Err(e @ DecodeError::Unsupported { .. }) => {
+                log::warn!("Skipping record {}: {e}", path.display());
+                Ok(Loaded::Unsupported)
+            }
+            Err(e @ DecodeError::Malformed(_)) => {
+                log::warn!("Unreadable record {}: {e}", path.display());
+                if !self.read_only {
+                    let path = path.to_path_buf();
+                    self.defer(move |db| {
+                        // unless it was replaced or removed in the meantime
+                        let result = match decode_file::<R>(&path) {
+                            Ok(Some(Err(DecodeError::Malformed(_)))) => db.quarantine(&path),
+                            Ok(_) => Ok(()),
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = result {
+                            log::warn!("Failed to quarantine {}: {e}", path.display());
+                        }
+                    });
+                }
+                Ok(Loaded::Unreadable)
+            }
+        }
+    }
+
+    /// Move an unreadable record out of the way so it no longer fails every
+    /// read, while keeping it around for manual recovery.
+    fn quarantine(&self, path: &Path) -> Result<(), StorageError> {
+        let quarantine_dir = self.dir.join(QUARANTINE_DIR);
+        std::fs::create_dir_all(&quarantine_dir)?;
+
+        let relative = path.strip_prefix(&self.dir).unwrap_or(path);
+        let name = format!(
+            "{}.{}",
+            relative
+                .to_string_lossy()
+                .replace(std::path::MAIN_SEPARATOR, "-"),
+            Utc::now().format("%Y%m%dT%H%M%S%.f")
+        );
+        match std::fs::rename(path, quarantine_dir.join(&name)) {
+            Ok(()) => {
+                log::info!("Moved {} to quarantine as {name}", path.display());
+                Ok(())
+            }
+            // somebody else quarantined it first
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
     }
 }
 
 #[async_trait::async_trait]
 impl NoteStorage for FileDatabase {
-    async fn save_note(&self, new_note: NewNote) -> Result<Uuid, anyhow::Error> {
-        let note_dir = self.dir.join("notes");
-        if !tokio::fs::try_exists(&note_dir).await? {
-            tokio::fs::create_dir_all(&note_dir).await?;
-        }
-
+    async fn save_note(&self, new_note: NewNote) -> Result<Uuid, StorageError> {
         let note = Note::new(new_note);
-        let note_file = note_dir.join(note.id.to_string());
         let note_id = note.id;
-        write_file(note_file, note).await?;
+
+        self.with_lock(LockMode::Exclusive, move |db| db.write_note(&note))
+            .await?;
 
         Ok(note_id)
     }
 
-    async fn get_note(&self, note_id: Uuid) -> Result<Note, anyhow::Error> {
-        let note_file = self.dir.join("notes").join(note_id.to_string());
-        let file = std::fs::File::open(note_file)?;
-        let note = serde_json::from_reader(file)?;
+    async fn insert_note(&self, note: Note) -> Result<(), StorageError> {
+        self.with_lock(LockMode::Exclusive, move |db| {
+            let path = db.note_file(note.id);
+            if path.try_exists()? {
+                return Err(StorageError::Conflict(format!(
+                    "note {} already exists",
+                    note.id
+                )));
+            }
+            db.write_note(&note)
+        })
+        .await
+    }
 
-        Ok(note)
+    async fn get_note(&self, note_id: Uuid) -> Result<Note, StorageError> {
+        self.with_lock(LockMode::Shared, move |db| db.read_note(note_id))
+            .await
     }
 
-    async fn get_notes(&self) -> Result<Vec<String>, anyhow::Error> {
-        todo!()
+    async fn get_notes(&self) -> Result<Vec<Note>, StorageError> {
+        self.with_lock(LockMode::Shared, |db| db.read_notes()).await
     }
 
-    async fn update_note(&self, _note_id: u64, _updated_note: String) -> Result<(), anyhow::Error> {
-        todo!()
+    async fn update_note(&self, note_id: Uuid, patch: NotePatch) -> Result<Note, StorageError> {
+        self.with_lock(LockMode::Exclusive, move |db| {
+            let mut note = db.read_note(note_id)?;
+            patch.apply(&mut note);
+            db.write_note(&note)?;
+            Ok(note)
+        })
+        .await
     }
 
-    async fn delete_note(&self, _note_id: u64) -> Result<(), anyhow::Error> {
-        todo!()
+    async fn delete_note(&self, note_id: Uuid) -> Result<(), StorageError> {
+        self.with_lock(LockMode::Exclusive, move |db| {
+            match std::fs::remove_file(db.note_file(note_id)) {
+                Ok(()) => {
+                    db.update_index(|index| index.remove(DocKind::Note, note_id));
+                    Ok(())
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    Err(StorageError::note_not_found(note_id))
+                }
+                Err(e) => Err(e.into()),
+            }
+        })
+        .await
     }
 }
 
 #[async_trait::async_trait]
 impl TodoStorage for FileDatabase {
-    async fn save_todo(&self, new_todo: NewTodoItem) -> Result<Uuid, anyhow::Error> {
-        if !tokio::fs::try_exists(&self.dir).await? {
-            tokio::fs::create_dir_all(&self.dir).await?;
-        }
-
+    /// Same as the provided implementation, but the lookup and the write
+    /// happen under one lock so concurrent saves can't both insert.
+    async fn save_todo(&self, new_todo: NewTodoItem) -> Result<Uuid, StorageError> {
         let todo_item = TodoItem::new(new_todo);
-        let todo_file = self.dir.join(todo_item.id.to_string());
-        let todo_id = todo_item.id;
-        write_file(todo_file, todo_item).await?;
 
-        Ok(todo_id)
+        self.with_lock(LockMode::Exclusive, move |db| {
+            let existing: Vec<_> = db
+                .read_todos()?
+                .into_iter()
+                .filter(|todo| {
+                    todo.deleted_at.is_none()
+                        && todo.hash == todo_item.hash
+                        && todo.project_dir == todo_item.project_dir
+                        && todo.branch == todo_item.branch
+                })
+                .collect();
+
+            match find_duplicate(&existing, &todo_item) {
+                Some(todo) => Ok(todo.id),
+                None => {
+                    db.write_todo(&todo_item)?;
+                    Ok(todo_item.id)
+                }
+            }
+        })
+        .await
+    }
+
+    async fn insert_todo(&self, todo_item: TodoItem) -> Result<(), StorageError> {
+        self.with_lock(LockMode::Exclusive, move |db| {
+            let path = db.todo_file(todo_item.id);
+            if path.try_exists()? {
+                return Err(StorageError::Conflict(format!(
+                    "todo {} already exists",
+                    todo_item.id
+                )));
+            }
+            db.write_todo(&todo_item)
+        })
+        .await
     }
 
-    async fn get_todos(&self) -> Result<Vec<TodoItem>, anyhow::Error> {
-        todo!()
+    async fn get_todos(&self) -> Result<Vec<TodoItem>, StorageError> {
+        self.with_lock(LockMode::Shared, |db| db.read_todos()).await
     }
+
+    async fn query_todos(&self, query: &TodoQuery) -> Result<TodoPage, StorageError> {
+        paginate_todos(query, self.get_todos().await?)
+    }
+
+    async fn find_todos_by_hash(
+        &self,
+        project_dir: &str,
+        branch: &str,
+        hash: &str,
+    ) -> Result<Vec<TodoItem>, StorageError> {
+        let todos = self
+            .get_todos()
+            .await?
+            .into_iter()
+            .filter(|todo| {
+                todo.deleted_at.is_none()
+                    && todo.hash == hash
+                    && todo.project_dir == project_dir
+                    && todo.branch == branch
+            })
+            .collect();
+
+        Ok(todos)
+    }
+
+    async fn update_todo(&self, todo_id: Uuid, patch: TodoPatch) -> Result<TodoItem, StorageError> {
+        self.with_lock(LockMode::Exclusive, move |db| {
+            let mut todo_item = db.read_todo(todo_id)?;
+            patch.apply(&mut todo_item);
+            db.write_todo(&todo_item)?;
+            Ok(todo_item)
+        })
+        .await
+    }
+
+    async fn delete_todo(&self, todo_id: Uuid) -> Result<(), StorageError> {
+        self.with_lock(LockMode::Exclusive, move |db| {
+            let mut todo_item = db.read_todo(todo_id)?;
+            todo_item.deleted_at = Some(Utc::now());
+            db.write_todo(&todo_item)
+        })
+        .await
+    }
+
+    /// Reads every changed todo and writes each to a synced temporary file
+    /// before renaming any of them over the records, all under one lock.
+    async fn apply_todo_changes(&self, changes: TodoChanges) -> Result<(), StorageError> {
+        self.with_lock(LockMode::Exclusive, move |db| {
+            for todo_item in &changes.inserted {
+                if db.todo_file(todo_item.id).try_exists()? {
+                    return Err(StorageError::Conflict(format!(
+                        "todo {} already exists",
+                        todo_item.id
+                    )));
+                }
+            }
+            let mut moved = Vec::new();
+            for (todo_id, line_number) in changes.moved {
+                let mut todo_item = db.read_todo(todo_id)?;
+                TodoChanges::move_patch(line_number).apply(&mut todo_item);
+                moved.push(todo_item);
+            }
+            let now = Utc::now();
+            let mut deleted = Vec::new();
+            for todo_id in changes.deleted {
+                let mut todo_item = db.read_todo(todo_id)?;
+                todo_item.deleted_at = Some(now);
+                deleted.push(todo_item);
+            }
+
+            let changed: Vec<_> = changes
+                .inserted
+                .iter()
+                .chain(&moved)
+                .chain(&deleted)
+                .collect();
+            let staged = changed
+                .iter()
+                .map(|todo_item| Staged::write(&db.todo_file(todo_item.id), *todo_item))
+                .collect::<Result<Vec<_>, _>>()?;
+            for staged in staged {
+                staged.commit()?;
+            }
+            sync_dir(&db.dir)?;
+            for todo_item in changed {
+                db.update_index(|index| index.index_todo(todo_item));
+            }
+
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[async_trait::async_trait]
+impl SearchStorage for FileDatabase {
+    async fn search(&self, query: &SearchQuery) -> Result<Vec<SearchHit>, StorageError> {
+        let query = query.clone();
+
+        self.with_lock(LockMode::Shared, move |db| {
+            if !db.index_is_current() {
+                let index = SearchIndex::build(&db.read_notes()?, &db.read_todos()?);
+                let stamp = db.stamp();
+                *db.cached_index() = Some(CachedIndex { index, stamp });
+            }
+
+            let cached = db.cached_index();
+            Ok(cached
+                .as_ref()
+                .map(|cached| cached.index.search(&query))
+                .unwrap_or_default())
+        })
+        .await
+    }
+}
+
+/// The records in `dir` and their ids. Records are named after their id,
+/// which keeps the lock file, leftover temporary files and other directories
+/// out of the listing.
+fn record_paths(dir: &Path) -> Result<Vec<(Uuid, PathBuf)>, StorageError> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut paths = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let id = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| Uuid::parse_str(name).ok());
+        if let Some(id) = id
+            && entry.file_type()?.is_file()
+        {
+            paths.push((id, entry.path()));
+        }
+    }
+
+    Ok(paths)
+}
+
+/// Decode the record at `path`, `None` when there is none.
+fn decode_file<R: Record>(
+    path: &Path,
+) -> Result<Option<Result<Decoded<R>, DecodeError>>, StorageError> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let decoded = serde_json::from_reader(std::io::BufReader::new(file))
+        .map_err(|e| DecodeError::Malformed(e.to_string()))
+        .and_then(record::decode);
+
+    Ok(Some(decoded))
 }
 
-async fn write_file<S: Serialize + Send + 'static>(
+/// Atomically replace `path` with the serialised `record`.
+///
+/// The content goes to a temporary file in the same directory which is synced
+/// before being renamed over `path`, the directory is synced afterwards so the
+/// rename itself survives a crash.
+fn write_file<R: Record>(path: &Path, record: &R) -> Result<(), StorageError> {
+    let staged = Staged::write(path, record)?;
+    let dir = staged.dir().to_path_buf();
+    staged.commit()?;
+    sync_dir(&dir)
+}
+
+fn sync_dir(dir: &Path) -> Result<(), StorageError> {
+    std::fs::File::open(dir).and_then(|d| d.sync_all())?;
+    Ok(())
+}
+
+/// A record written and synced to a temporary file next to its path, which
+/// only replaces the record once committed. Dropping it uncommitted removes
+/// the temporary file.
+struct Staged {
     path: PathBuf,
-    content: S,
-) -> Result<(), anyhow::Error> {
-    tokio::task::spawn_blocking(move || {
-        std::fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(path)
-            .context("failed to open file for writing")
-            .and_then(|f| serde_json::to_writer(f, &content).context("failed to write to file"))
-    })
-    .await?
+    tmp_path: PathBuf,
+    committed: bool,
+}
+
+impl Staged {
+    fn write<R: Record>(path: &Path, record: &R) -> Result<Self, StorageError> {
+        let (Some(dir), Some(file_name)) = (path.parent(), path.file_name()) else {
+            return Err(StorageError::Invalid(format!(
+                "{} is not a record path",
+                path.display()
+            )));
+        };
+        let file_name = file_name.to_string_lossy();
+        let staged = Self {
+            path: path.to_path_buf(),
+            tmp_path: dir.join(format!(".{file_name}.{}.tmp", Uuid::new_v4())),
+            committed: false,
+        };
+
+        let file = std::fs::File::create_new(&staged.tmp_path)?;
+        (|| {
+            let mut writer = std::io::BufWriter::new(file);
+            serde_json::to_writer(&mut writer, &record::encode(record)?)?;
+            let file = writer.into_inner().map_err(|e| e.into_error())?;
+            file.sync_all()
+        })()?;
+
+        Ok(staged)
+    }
+
+    fn dir(&self) -> &Path {
+        self.tmp_path.parent().unwrap_or(Path::new("."))
+    }
+
+    /// Rename the temporary file over the record. The directory still needs
+    /// to be synced for the rename to survive a crash.
+    fn commit(mut self) -> Result<(), StorageError> {
+        std::fs::rename(&self.tmp_path, &self.path)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = std::fs::remove_file(&self.tmp_path);
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use uuid::Uuid;
+
     use crate::{
-        database::{NoteStorage, file::FileDatabase},
-        types::{NewNote, NoteContext},
+        database::{
+            NoteStorage, SearchStorage, StorageError, TodoStorage, file::FileDatabase,
+            record::Record,
+        },
+        fixtures,
+        types::{NotePatch, search::SearchQuery, todo::TodoItem},
     };
 
+    fn updated_content() -> NotePatch {
+        NotePatch {
+            content: Some("An updated note".to_string()),
+            ..Default::default()
+        }
+    }
+
     #[tokio::test]
     async fn new_note_is_saved_to_disk() {
-        let new_note = NewNote {
-            context: NoteContext {
-                filename: "test_file.rs".to_string(),
-                project_dir: "/test_user/projects/test_project".to_string(),
-                selection: "fn test_function() {".to_string(),
-            },
-            content: "A new test note".to_string(),
-        };
-        let file_db = FileDatabase::init().await;
-        println!("saving files to: {}", file_db.dir.display());
+        let new_note =
+            fixtures::new_note("test_file.rs", "fn test_function() {", "A new test note");
+        let dir = tempfile::tempdir().unwrap();
+        let file_db = FileDatabase::init(dir.path()).await.unwrap();
         let id = file_db.save_note(new_note).await.unwrap();
 
-        assert!(std::fs::exists(id.to_string()).unwrap());
+        let note_file = file_db.dir.join("notes").join(id.to_string());
+        assert!(std::fs::exists(&note_file).unwrap());
+    }
+
+    #[tokio::test]
+    async fn note_can_be_updated_and_deleted() {
+        let new_note =
+            fixtures::new_note("test_file.rs", "fn test_function() {", "A new test note");
+        let dir = tempfile::tempdir().unwrap();
+        let file_db = FileDatabase::init(dir.path()).await.unwrap();
+        let id = file_db.save_note(new_note).await.unwrap();
+
+        file_db.update_note(id, updated_content()).await.unwrap();
+        assert_eq!(
+            file_db.get_note(id).await.unwrap().content,
+            "An updated note"
+        );
+        assert!(
+            file_db
+                .get_notes()
+                .await
+                .unwrap()
+                .iter()
+                .any(|n| n.id == id)
+        );
+
+        file_db.delete_note(id).await.unwrap();
+        let err = file_db.delete_note(id).await.unwrap_err();
+        assert!(matches!(err, StorageError::NotFound { .. }));
+        let err = file_db.get_note(id).await.unwrap_err();
+        assert!(matches!(err, StorageError::NotFound { .. }));
+    }
+
+    #[tokio::test]
+    async fn writes_leave_no_temporary_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_db = FileDatabase::init(dir.path()).await.unwrap();
+        let id = file_db
+            .save_note(fixtures::new_note(
+                "test_file.rs",
+                "fn test_function() {",
+                "A new test note",
+            ))
+            .await
+            .unwrap();
+        file_db.update_note(id, updated_content()).await.unwrap();
+
+        let names: Vec<_> = std::fs::read_dir(dir.path().join("notes"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(names, vec![id.to_string()]);
+    }
+
+    #[tokio::test]
+    async fn unreadable_records_are_quarantined() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_db = FileDatabase::init(dir.path()).await.unwrap();
+        let id = file_db
+            .save_note(fixtures::new_note(
+                "test_file.rs",
+                "fn test_function() {",
+                "A new test note",
+            ))
+            .await
+            .unwrap();
+        let corrupt_id = Uuid::new_v4();
+        std::fs::write(
+            dir.path().join("notes").join(corrupt_id.to_string()),
+            "{\"id\": ",
+        )
+        .unwrap();
+
+        let notes = file_db.get_notes().await.unwrap();
+
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].id, id);
+        let quarantined: Vec<_> = std::fs::read_dir(dir.path().join("quarantine"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(quarantined.len(), 1);
+        assert!(quarantined[0].starts_with(&format!("notes-{corrupt_id}")));
+        // the read left the move to a write, counted like the save
+        let generation = std::fs::read_to_string(dir.path().join("generation")).unwrap();
+        assert_eq!(generation, "2");
+    }
+
+    #[tokio::test]
+    async fn old_records_are_upgraded_and_optionally_rewritten() {
+        let dir = tempfile::tempdir().unwrap();
+        let id = Uuid::new_v4();
+        let old_todo = serde_json::json!({
+            "id": id,
+            "branch": "main",
+            "file_path": "src/lib.rs",
+            "line_number": 3,
+            "content": "TODO: old",
+            "created_at": "2025-01-01T00:00:00Z",
+            "deleted_at": null,
+        });
+        let path = dir.path().join(id.to_string());
+        std::fs::write(&path, old_todo.to_string()).unwrap();
+        let stored = || -> serde_json::Value {
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap()
+        };
+
+        let file_db = FileDatabase::init(dir.path()).await.unwrap();
+        let todos = file_db.get_todos().await.unwrap();
+        assert_eq!(todos.len(), 1);
+        assert!(!todos[0].hash.is_empty());
+        assert!(stored().get("schema_version").is_none());
+
+        let file_db = file_db.rewrite_upgraded(true);
+        assert_eq!(file_db.get_todos().await.unwrap(), todos);
+        assert_eq!(stored()["schema_version"], TodoItem::version());
+        assert_eq!(stored()["hash"], todos[0].hash.as_str());
+    }
+
+    #[tokio::test]
+    async fn records_from_newer_versions_are_skipped_not_quarantined() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_db = FileDatabase::init(dir.path()).await.unwrap();
+        let id = Uuid::new_v4();
+        std::fs::create_dir_all(dir.path().join("notes")).unwrap();
+        std::fs::write(
+            dir.path().join("notes").join(id.to_string()),
+            serde_json::json!({ "schema_version": 1000, "id": id }).to_string(),
+        )
+        .unwrap();
+
+        assert!(file_db.get_notes().await.unwrap().is_empty());
+        let err = file_db.get_note(id).await.unwrap_err();
+        assert!(matches!(err, StorageError::Corrupt(_)));
+        assert!(!dir.path().join("quarantine").exists());
+    }
+
+    #[tokio::test]
+    async fn search_sees_own_and_other_processes_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_db = FileDatabase::init(dir.path()).await.unwrap();
+        // a second instance stands in for another backend process
+        let other = FileDatabase::init(dir.path()).await.unwrap();
+        let search = |text: &str| SearchQuery {
+            terms: SearchQuery::parse_terms(text),
+            kind: None,
+            project_dir: None,
+            limit: SearchQuery::DEFAULT_LIMIT,
+            tag: None,
+        };
+
+        let id = file_db
+            .save_note(fixtures::new_note(
+                "test_file.rs",
+                "fn test_function() {",
+                "A new test note",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(file_db.search(&search("test")).await.unwrap().len(), 1);
+
+        file_db.update_note(id, updated_content()).await.unwrap();
+        let hits = file_db.search(&search("updated")).await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].id, id);
+
+        other.delete_note(id).await.unwrap();
+        assert!(file_db.search(&search("updated")).await.unwrap().is_empty());
+
+        // a write within the same tick of the directory modification times
+        // is noticed all the same
+        let id = file_db
+            .save_note(fixtures::new_note(
+                "test_file.rs",
+                "fn test_function() {",
+                "A new test note",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(file_db.search(&search("test")).await.unwrap().len(), 1);
+        let modified = |dir: &std::path::Path| std::fs::metadata(dir).unwrap().modified().unwrap();
+        let dirs = [dir.path().to_path_buf(), dir.path().join("notes")];
+        let before = dirs.clone().map(|dir| modified(&dir));
+        other.update_note(id, updated_content()).await.unwrap();
+        for (dir, modified) in dirs.iter().zip(before) {
+            std::fs::File::open(dir)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+        assert_eq!(file_db.search(&search("updated")).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn concurrent_saves_do_not_duplicate_todos() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_db = FileDatabase::init(dir.path()).await.unwrap();
+
+        let saves = (0..16).map(|_| {
+            let file_db = file_db.clone();
+            tokio::spawn(async move {
+                file_db
+                    .save_todo(fixtures::new_todo("src/lib.rs", 4, "TODO: only once"))
+                    .await
+                    .unwrap()
+            })
+        });
+        let ids = futures::future::join_all(saves).await;
 
-        std::fs::remove_file(id.to_string()).unwrap();
+        assert!(
+            ids.iter()
+                .all(|id| id.as_ref().unwrap() == ids[0].as_ref().unwrap())
+        );
+        assert_eq!(file_db.get_todos().await.unwrap().len(), 1);
     }
 }
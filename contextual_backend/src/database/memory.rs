@@ -0,0 +1,379 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::{
+    database::{
+        NoteStorage, SearchStorage, StorageError, TodoChanges, TodoStorage, find_duplicate,
+        paginate_todos,
+    },
+    search::SearchIndex,
+    types::{
+        NewNote, Note, NotePatch,
+        search::{DocKind, SearchHit, SearchQuery},
+        todo::{NewTodoItem, TodoItem, TodoPage, TodoPatch, TodoQuery},
+    },
+};
+
+/// Keeps everything in memory, nothing survives the process.
+///
+/// Meant for tests and throwaway editor sessions. Clones share the same data.
+#[derive(Clone, Default)]
+pub struct MemoryDatabase {
+    state: Arc<RwLock<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    notes: HashMap<Uuid, Note>,
+    todos: HashMap<Uuid, TodoItem>,
+    index: SearchIndex,
+}
+
+impl MemoryDatabase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, State> {
+        // a panic while holding the lock can't leave the maps half updated,
+        // so a poisoned lock is safe to keep using
+        self.state.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, State> {
+        self.state.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait::async_trait]
+impl NoteStorage for MemoryDatabase {
+    async fn save_note(&self, new_note: NewNote) -> Result<Uuid, StorageError> {
+        let note = Note::new(new_note);
+        let note_id = note.id;
+        let mut state = self.write();
+        state.index.index_note(&note);
+        state.notes.insert(note_id, note);
+
+        Ok(note_id)
+    }
+
+    async fn insert_note(&self, note: Note) -> Result<(), StorageError> {
+        let mut state = self.write();
+        if state.notes.contains_key(&note.id) {
+            return Err(StorageError::Conflict(format!(
+                "note {} already exists",
+                note.id
+            )));
+        }
+        state.index.index_note(&note);
+        state.notes.insert(note.id, note);
+
+        Ok(())
+    }
+
+    async fn get_note(&self, note_id: Uuid) -> Result<Note, StorageError> {
+        self.read()
+            .notes
+            .get(&note_id)
+            .cloned()
+            .ok_or(StorageError::note_not_found(note_id))
+    }
+
+    /// Sorted by project and file, like the sqlite storage.
+    async fn get_notes(&self) -> Result<Vec<Note>, StorageError> {
+        let mut notes: Vec<_> = self.read().notes.values().cloned().collect();
+        notes.sort_by(|a, b| {
+            (&a.context.project_dir, &a.context.filename)
+                .cmp(&(&b.context.project_dir, &b.context.filename))
+        });
+
+        Ok(notes)
+    }
+
+    async fn update_note(&self, note_id: Uuid, patch: NotePatch) -> Result<Note, StorageError> {
+        let mut state = self.write();
+        let note = state
+            .notes
+            .get_mut(&note_id)
+            .ok_or(StorageError::note_not_found(note_id))?;
+        patch.apply(note);
+        let note = note.clone();
+        state.index.index_note(&note);
+
+        Ok(note)
+    }
+
+    async fn delete_note(&self, note_id: Uuid) -> Result<(), StorageError> {
+        let mut state = self.write();
+        state
+            .notes
+            .remove(&note_id)
+            .ok_or(StorageError::note_not_found(note_id))?;
+        state.index.remove(DocKind::Note, note_id);
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl TodoStorage for MemoryDatabase {
+    /// Same as the provided implementation, but the lookup and the insert
+    /// happen under one lock so concurrent saves can't both insert.
+    async fn save_todo(&self, new_todo: NewTodoItem) -> Result<Uuid, StorageError> {
+        let todo_item = TodoItem::new(new_todo);
+        let mut state = self.write();
+
+        let existing: Vec<_> = state
+            .todos
+            .values()
+            .filter(|todo| {
+                todo.deleted_at.is_none()
+                    && todo.hash == todo_item.hash
+                    && todo.project_dir == todo_item.project_dir
+                    && todo.branch == todo_item.branch
+            })
+            .cloned()
+            .collect();
+
+        match find_duplicate(&existing, &todo_item) {
+            Some(todo) => Ok(todo.id),
+            None => {
+                let todo_id = todo_item.id;
+                state.index.index_todo(&todo_item);
+                state.todos.insert(todo_id, todo_item);
+                Ok(todo_id)
+            }
+        }
+    }
+
+    async fn insert_todo(&self, todo_item: TodoItem) -> Result<(), StorageError> {
+        let mut state = self.write();
+        if state.todos.contains_key(&todo_item.id) {
+            return Err(StorageError::Conflict(format!(
+                "todo {} already exists",
+                todo_item.id
+            )));
+        }
+        state.index.index_todo(&todo_item);
+        state.todos.insert(todo_item.id, todo_item);
+
+        Ok(())
+    }
+
+    /// Sorted by file and line, like the sqlite storage.
+    async fn get_todos(&self) -> Result<Vec<TodoItem>, StorageError> {
+        let mut todos: Vec<_> = self.read().todos.values().cloned().collect();
+        todos.sort_by(|a, b| (&a.file_path, a.line_number).cmp(&(&b.file_path, b.line_number)));
+
+        Ok(todos)
+    }
+
+    async fn query_todos(&self, query: &TodoQuery) -> Result<TodoPage, StorageError> {
+        paginate_todos(query, self.get_todos().await?)
+    }
+
+    async fn find_todos_by_hash(
+        &self,
+        project_dir: &str,
+        branch: &str,
+        hash: &str,
+    ) -> Result<Vec<TodoItem>, StorageError> {
+        let todos = self
+            .read()
+            .todos
+            .values()
+            .filter(|todo| {
+                todo.deleted_at.is_none()
+                    && todo.hash == hash
+                    && todo.project_dir == project_dir
+                    && todo.branch == branch
+            })
+            .cloned()
+            .collect();
+
+        Ok(todos)
+    }
+
+    async fn update_todo(&self, todo_id: Uuid, patch: TodoPatch) -> Result<TodoItem, StorageError> {
+        let mut state = self.write();
+        let todo_item = state
+            .todos
+            .get_mut(&todo_id)
+            .ok_or(StorageError::todo_not_found(todo_id))?;
+        patch.apply(todo_item);
+        let todo_item = todo_item.clone();
+        state.index.index_todo(&todo_item);
+
+        Ok(todo_item)
+    }
+
+    async fn delete_todo(&self, todo_id: Uuid) -> Result<(), StorageError> {
+        let mut state = self.write();
+        let todo_item = state
+            .todos
+            .get_mut(&todo_id)
+            .ok_or(StorageError::todo_not_found(todo_id))?;
+        todo_item.deleted_at = Some(Utc::now());
+        state.index.remove(DocKind::Todo, todo_id);
+
+        Ok(())
+    }
+
+    /// Checks every change before applying any, all under one lock.
+    async fn apply_todo_changes(&self, changes: TodoChanges) -> Result<(), StorageError> {
+        let mut state = self.write();
+        if let Some(todo_item) = changes
+            .inserted
+            .iter()
+            .find(|todo_item| state.todos.contains_key(&todo_item.id))
+        {
+            return Err(StorageError::Conflict(format!(
+                "todo {} already exists",
+                todo_item.id
+            )));
+        }
+        let changed = changes.moved.iter().map(|(todo_id, _)| todo_id);
+        if let Some(todo_id) = changed
+            .chain(&changes.deleted)
+            .find(|todo_id| !state.todos.contains_key(todo_id))
+        {
+            return Err(StorageError::todo_not_found(*todo_id));
+        }
+
+        for todo_item in changes.inserted {
+            state.index.index_todo(&todo_item);
+            state.todos.insert(todo_item.id, todo_item);
+        }
+        for (todo_id, line_number) in changes.moved {
+            if let Some(todo_item) = state.todos.get_mut(&todo_id) {
+                TodoChanges::move_patch(line_number).apply(todo_item);
+                let todo_item = todo_item.clone();
+                state.index.index_todo(&todo_item);
+            }
+        }
+        let now = Utc::now();
+        for todo_id in changes.deleted {
+            if let Some(todo_item) = state.todos.get_mut(&todo_id) {
+                todo_item.deleted_at = Some(now);
+            }
+            state.index.remove(DocKind::Todo, todo_id);
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl SearchStorage for MemoryDatabase {
+    async fn search(&self, query: &SearchQuery) -> Result<Vec<SearchHit>, StorageError> {
+        Ok(self.read().index.search(query))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        database::{
+            NoteStorage, Storage, StorageError, TodoStorage, memory::MemoryDatabase,
+            sqlite::SqliteDatabase,
+        },
+        fixtures,
+    };
+
+    #[tokio::test]
+    async fn clones_share_data() {
+        let db = MemoryDatabase::new();
+        let id = db
+            .clone()
+            .save_note(fixtures::new_note(
+                "test_file.rs",
+                "fn test_function() {",
+                "A new test note",
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(db.get_note(id).await.unwrap().content, "A new test note");
+        db.delete_note(id).await.unwrap();
+        let err = db.get_note(id).await.unwrap_err();
+        assert!(matches!(err, StorageError::NotFound { .. }));
+    }
+
+    #[tokio::test]
+    async fn todos_are_deduplicated_and_soft_deleted() {
+        let db = MemoryDatabase::new();
+        let new_todo =
+            |line_number| fixtures::new_todo("src/lib.rs", line_number, "TODO: write docs");
+
+        let id = db.save_todo(new_todo(3)).await.unwrap();
+        assert_eq!(db.save_todo(new_todo(3)).await.unwrap(), id);
+        // the same text on another line is another todo
+        let other = db.save_todo(new_todo(5)).await.unwrap();
+        assert_ne!(other, id);
+        db.delete_todo(id).await.unwrap();
+
+        let todos = db.get_todos().await.unwrap();
+        assert_eq!(todos.len(), 2);
+        let deleted = todos.iter().find(|todo| todo.id == id).unwrap();
+        assert_eq!(deleted.line_number, 3);
+        assert!(deleted.deleted_at.is_some());
+        // a deleted todo is not a duplicate anymore
+        assert_ne!(db.save_todo(new_todo(3)).await.unwrap(), id);
+    }
+
+    #[tokio::test]
+    async fn listings_are_sorted_like_sqlite() {
+        let memory = MemoryDatabase::new();
+        let sqlite = SqliteDatabase::in_memory().await.unwrap();
+        let new_note = |project_dir: &str, filename: &str| {
+            let mut new_note = fixtures::new_note(filename, "", "note");
+            new_note.context.project_dir = project_dir.to_string();
+            new_note
+        };
+        let new_todo =
+            |file_path: &str, line_number| fixtures::new_todo(file_path, line_number, "TODO: sort");
+
+        for db in [&memory as &dyn Storage, &sqlite] {
+            for (project_dir, filename) in [("/b", "a.rs"), ("/a", "b.rs"), ("/a", "a.rs")] {
+                db.save_note(new_note(project_dir, filename)).await.unwrap();
+            }
+            for (file_path, line_number) in [("b.rs", 1), ("a.rs", 10), ("a.rs", 2)] {
+                db.save_todo(new_todo(file_path, line_number))
+                    .await
+                    .unwrap();
+            }
+        }
+
+        let notes = async |db: &dyn Storage| {
+            db.get_notes()
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|note| (note.context.project_dir, note.context.filename))
+                .collect::<Vec<_>>()
+        };
+        let todos = async |db: &dyn Storage| {
+            db.get_todos()
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|todo| (todo.file_path, todo.line_number))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(notes(&memory).await, notes(&sqlite).await);
+        assert_eq!(
+            todos(&memory).await,
+            vec![
+                ("a.rs".to_string(), 2),
+                ("a.rs".to_string(), 10),
+                ("b.rs".to_string(), 1)
+            ]
+        );
+        assert_eq!(todos(&memory).await, todos(&sqlite).await);
+    }
+}
@@ -1,26 +1,230 @@
+use std::{cmp::Ordering, path::PathBuf};
+
+use anyhow::Context;
 use uuid::Uuid;
 
 use crate::types::{
-    NewNote, Note,
-    todo::{NewTodoItem, TodoItem},
+    NewNote, Note, NotePatch,
+    search::{SearchHit, SearchQuery},
+    todo::{NewTodoItem, TodoItem, TodoPage, TodoPatch, TodoQuery},
 };
 
 pub mod file;
+pub mod memory;
+pub mod record;
+pub mod sqlite;
+
+/// Errors reported by every storage implementation.
+#[derive(Debug)]
+pub enum StorageError {
+    /// The record requested by its id does not exist.
+    NotFound {
+        kind: &'static str,
+        id: Uuid,
+    },
+    /// The write clashes with a record which already exists.
+    Conflict(String),
+    /// A stored record could not be read back.
+    Corrupt(String),
+    /// The request can't be answered as asked, e.g. an unknown cursor.
+    Invalid(String),
+    Io(std::io::Error),
+    /// Any other failure of the underlying database.
+    Database(sqlx::Error),
+}
 
-pub trait Storage: NoteStorage + TodoStorage {}
-impl<T: NoteStorage + TodoStorage> Storage for T {}
+impl StorageError {
+    pub fn note_not_found(id: Uuid) -> Self {
+        Self::NotFound { kind: "note", id }
+    }
+
+    pub fn todo_not_found(id: Uuid) -> Self {
+        Self::NotFound { kind: "todo", id }
+    }
+
+    /// Todos outside of a git repository are scoped by the branch the client
+    /// sends.
+    pub fn branch_required() -> Self {
+        Self::Invalid("branch is required outside of a git repository".to_string())
+    }
+}
+
+impl std::fmt::Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageError::NotFound { kind, id } => write!(f, "{kind} {id} not found"),
+            StorageError::Conflict(msg) => write!(f, "conflict: {msg}"),
+            StorageError::Corrupt(msg) => write!(f, "corrupt record: {msg}"),
+            StorageError::Invalid(msg) => write!(f, "invalid request: {msg}"),
+            StorageError::Io(e) => write!(f, "I/O error: {e}"),
+            StorageError::Database(e) => write!(f, "database error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StorageError::Io(e) => Some(e),
+            StorageError::Database(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for StorageError {
+    fn from(error: std::io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl From<sqlx::Error> for StorageError {
+    fn from(error: sqlx::Error) -> Self {
+        match error {
+            sqlx::Error::Database(e) if e.is_unique_violation() => Self::Conflict(e.to_string()),
+            sqlx::Error::Io(e) => Self::Io(e),
+            sqlx::Error::ColumnDecode { .. } | sqlx::Error::Decode(_) => {
+                Self::Corrupt(error.to_string())
+            }
+            error => Self::Database(error),
+        }
+    }
+}
+
+impl From<tokio::task::JoinError> for StorageError {
+    fn from(error: tokio::task::JoinError) -> Self {
+        Self::Io(std::io::Error::other(error))
+    }
+}
+
+/// The directory used for persisted data when none is configured.
+pub fn default_data_dir() -> Result<PathBuf, anyhow::Error> {
+    dirs::data_dir()
+        .map(|dir| dir.join("contextual"))
+        .context("unable to determine the user's data directory, use --data-dir to set one")
+}
+
+/// Writes to todos which belong together, see
+/// [TodoStorage::apply_todo_changes].
+#[derive(Debug, Default)]
+pub struct TodoChanges {
+    /// Todos stored as is, like [TodoStorage::insert_todo].
+    pub inserted: Vec<TodoItem>,
+    /// Todos moved to start on the given line.
+    pub moved: Vec<(Uuid, u64)>,
+    /// Todos soft-deleted, like [TodoStorage::delete_todo].
+    pub deleted: Vec<Uuid>,
+}
+
+impl TodoChanges {
+    fn move_patch(line_number: u64) -> TodoPatch {
+        TodoPatch {
+            line_number: Some(line_number),
+            ..Default::default()
+        }
+    }
+}
+
+/// Pick the stored todo a new todo duplicates, the one on the same line.
+/// `existing` holds the active todos with the same hash.
+///
+/// The same text on another line may well be another todo, telling moved
+/// todos apart is left to [crate::sync::reconcile] which sees the whole file.
+fn find_duplicate<'a>(existing: &'a [TodoItem], todo_item: &TodoItem) -> Option<&'a TodoItem> {
+    existing
+        .iter()
+        .find(|todo| todo.line_number == todo_item.line_number)
+}
+
+/// Apply a query to the complete set of stored todos, for storages without a
+/// query engine of their own.
+fn paginate_todos(query: &TodoQuery, todos: Vec<TodoItem>) -> Result<TodoPage, StorageError> {
+    let cursor = match query.cursor {
+        Some(cursor) => Some(
+            todos
+                .iter()
+                .find(|todo| todo.id == cursor)
+                .cloned()
+                .ok_or_else(|| StorageError::Invalid(format!("unknown cursor: {cursor}")))?,
+        ),
+        None => None,
+    };
+
+    let mut todos: Vec<_> = todos
+        .into_iter()
+        .filter(|todo| query.matches(todo))
+        .filter(|todo| {
+            cursor
+                .as_ref()
+                .is_none_or(|cursor| query.compare(todo, cursor) == Ordering::Greater)
+        })
+        .collect();
+    todos.sort_by(|a, b| query.compare(a, b));
+
+    Ok(TodoPage::from_overfetched(todos, query.limit))
+}
+
+pub trait Storage: NoteStorage + TodoStorage + SearchStorage {}
+impl<T: NoteStorage + TodoStorage + SearchStorage> Storage for T {}
 
 #[async_trait::async_trait]
 pub trait NoteStorage: Send + Sync {
-    async fn save_note(&self, new_note: NewNote) -> Result<Uuid, anyhow::Error>;
-    async fn get_note(&self, note_id: Uuid) -> Result<Note, anyhow::Error>;
-    async fn get_notes(&self) -> Result<Vec<String>, anyhow::Error>;
-    async fn update_note(&self, note_id: u64, updated_note: String) -> Result<(), anyhow::Error>;
-    async fn delete_note(&self, note_id: u64) -> Result<(), anyhow::Error>;
+    async fn save_note(&self, new_note: NewNote) -> Result<Uuid, StorageError>;
+    /// Store a note as is, keeping its id. Fails with
+    /// [StorageError::Conflict] when a note with the same id exists.
+    async fn insert_note(&self, note: Note) -> Result<(), StorageError>;
+    async fn get_note(&self, note_id: Uuid) -> Result<Note, StorageError>;
+    async fn get_notes(&self) -> Result<Vec<Note>, StorageError>;
+    /// Apply `patch` to a note and return the updated note.
+    async fn update_note(&self, note_id: Uuid, patch: NotePatch) -> Result<Note, StorageError>;
+    async fn delete_note(&self, note_id: Uuid) -> Result<(), StorageError>;
 }
 
 #[async_trait::async_trait]
 pub trait TodoStorage: Send + Sync {
-    async fn save_todo(&self, new_todo: NewTodoItem) -> Result<Uuid, anyhow::Error>;
-    async fn get_todos(&self) -> Result<Vec<TodoItem>, anyhow::Error>;
+    /// Save a new todo, deduplicating on its content hash.
+    ///
+    /// When the same project and branch already hold an active todo with the
+    /// same hash on the same line, no new todo is created and the id of the
+    /// existing one is returned.
+    async fn save_todo(&self, new_todo: NewTodoItem) -> Result<Uuid, StorageError> {
+        let todo_item = TodoItem::new(new_todo);
+        let existing = self
+            .find_todos_by_hash(&todo_item.project_dir, &todo_item.branch, &todo_item.hash)
+            .await?;
+
+        match find_duplicate(&existing, &todo_item) {
+            Some(todo) => Ok(todo.id),
+            None => {
+                let todo_id = todo_item.id;
+                self.insert_todo(todo_item).await?;
+                Ok(todo_id)
+            }
+        }
+    }
+    /// Store a todo as is, without any deduplication. Fails with
+    /// [StorageError::Conflict] when a todo with the same id exists.
+    async fn insert_todo(&self, todo_item: TodoItem) -> Result<(), StorageError>;
+    async fn get_todos(&self) -> Result<Vec<TodoItem>, StorageError>;
+    async fn query_todos(&self, query: &TodoQuery) -> Result<TodoPage, StorageError>;
+    /// Active (not deleted) todos of a project and branch with the given hash.
+    async fn find_todos_by_hash(
+        &self,
+        project_dir: &str,
+        branch: &str,
+        hash: &str,
+    ) -> Result<Vec<TodoItem>, StorageError>;
+    /// Apply `patch` to a todo and return the updated todo.
+    async fn update_todo(&self, todo_id: Uuid, patch: TodoPatch) -> Result<TodoItem, StorageError>;
+    /// Soft-delete a todo by setting its `deleted_at` timestamp.
+    async fn delete_todo(&self, todo_id: Uuid) -> Result<(), StorageError>;
+    /// Apply every change or, when one of them fails, none.
+    async fn apply_todo_changes(&self, changes: TodoChanges) -> Result<(), StorageError>;
+}
+
+#[async_trait::async_trait]
+pub trait SearchStorage: Send + Sync {
+    /// Notes and active todos matching every term of `query`, best match
+    /// first. The index behind it is kept up to date by every write.
+    async fn search(&self, query: &SearchQuery) -> Result<Vec<SearchHit>, StorageError>;
 }
@@ -0,0 +1,368 @@
+//! Versioned serialisation of records persisted as JSON.
+//!
+//! Every record is written with a `schema_version` field next to its own
+//! fields. Records are read by upgrading the raw JSON one version at a time
+//! until it matches the current shape of the type, so adding a field to a
+//! record only needs a new upgrade step instead of breaking older files.
+//! Records without a version predate versioning and are treated as version 0.
+
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::{Map, Value};
+
+use crate::types::{
+    Note,
+    marker::TodoMarker,
+    tag::{Tags, extract_tags},
+    todo::TodoItem,
+};
+
+const VERSION_KEY: &str = "schema_version";
+
+/// Rewrites a record from one schema version to the next.
+type Upgrade = fn(&mut Map<String, Value>) -> Result<(), String>;
+
+/// A type persisted through [encode] and [decode].
+pub trait Record: Serialize + DeserializeOwned {
+    /// Name used in error messages.
+    const KIND: &'static str;
+
+    /// Upgrade steps, where step `n` turns version `n` into version `n + 1`.
+    /// The current version is the number of steps.
+    fn upgrades() -> &'static [Upgrade];
+
+    fn version() -> u64 {
+        Self::upgrades().len() as u64
+    }
+}
+
+impl Record for Note {
+    const KIND: &'static str = "note";
+
+    fn upgrades() -> &'static [Upgrade] {
+        &[
+            // 0 -> 1: nothing changed, the version marker is introduced
+            |_| Ok(()),
+            // 1 -> 2: notes get tags
+            |record| {
+                record
+                    .entry("tags")
+                    .or_insert_with(|| Value::Array(Vec::new()));
+                Ok(())
+            },
+            note_v2_to_v3,
+            // 3 -> 4: notes get columns, older clients didn't send any
+            |record| {
+                let context = record
+                    .get_mut("context")
+                    .and_then(Value::as_object_mut)
+                    .ok_or("context is missing")?;
+                context.entry("start_column").or_insert(Value::Null);
+                context.entry("end_column").or_insert(Value::Null);
+                context
+                    .entry("position_encoding")
+                    .or_insert_with(|| Value::String("utf-16".to_string()));
+                Ok(())
+            },
+            // 4 -> 5: the git state wasn't recorded before
+            add_git_state,
+        ]
+    }
+}
+
+/// Notes remember where their selection was last found, older notes have no
+/// position yet and are anchored on their next re-anchoring.
+fn note_v2_to_v3(record: &mut Map<String, Value>) -> Result<(), String> {
+    let context = record
+        .get_mut("context")
+        .and_then(Value::as_object_mut)
+        .ok_or("context is missing")?;
+    context.entry("start_line").or_insert(Value::Null);
+    context.entry("end_line").or_insert(Value::Null);
+    context
+        .entry("context_before")
+        .or_insert_with(|| Value::Array(Vec::new()));
+    context
+        .entry("context_after")
+        .or_insert_with(|| Value::Array(Vec::new()));
+    record.entry("orphaned").or_insert(Value::Bool(false));
+
+    Ok(())
+}
+
+impl Record for TodoItem {
+    const KIND: &'static str = "todo";
+
+    fn upgrades() -> &'static [Upgrade] {
+        &[
+            todo_v0_to_v1,
+            todo_v1_to_v2,
+            add_git_state,
+            todo_v3_to_v4,
+            todo_v4_to_v5,
+            todo_v5_to_v6,
+            todo_v6_to_v7,
+        ]
+    }
+}
+
+/// Todos written before todos were scoped by project and identified by their
+/// hash lack both fields.
+fn todo_v0_to_v1(record: &mut Map<String, Value>) -> Result<(), String> {
+    record
+        .entry("project_dir")
+        .or_insert_with(|| Value::String(String::new()));
+
+    let has_hash = record
+        .get("hash")
+        .and_then(Value::as_str)
+        .is_some_and(|hash| !hash.is_empty());
+    if !has_hash {
+        let field = |key| {
+            record
+                .get(key)
+                .and_then(Value::as_str)
+                .ok_or_else(|| format!("{key} is missing"))
+        };
+        let hash = TodoItem::compute_hash(field("file_path")?, field("content")?);
+        record.insert("hash".to_string(), Value::String(hash));
+    }
+
+    Ok(())
+}
+
+/// Todos get tags, starting with the ones written inline in their content.
+fn todo_v1_to_v2(record: &mut Map<String, Value>) -> Result<(), String> {
+    if !record.contains_key("tags") {
+        let content = record
+            .get("content")
+            .and_then(Value::as_str)
+            .ok_or("content is missing")?;
+        let tags = extract_tags(content)
+            .into_iter()
+            .map(Value::String)
+            .collect();
+        record.insert("tags".to_string(), Value::Array(tags));
+    }
+
+    Ok(())
+}
+
+/// Todos get a kind, which older todos only have in their content.
+fn todo_v3_to_v4(record: &mut Map<String, Value>) -> Result<(), String> {
+    if !record.contains_key("kind") {
+        let content = record
+            .get("content")
+            .and_then(Value::as_str)
+            .ok_or("content is missing")?;
+        let kind = TodoItem::kind_from_content(content);
+        record.insert("kind".to_string(), Value::String(kind));
+    }
+
+    Ok(())
+}
+
+/// Todos get the owner, issue, priority and due date written with their
+/// marker.
+fn todo_v4_to_v5(record: &mut Map<String, Value>) -> Result<(), String> {
+    let field = |key| {
+        record
+            .get(key)
+            .and_then(Value::as_str)
+            .ok_or_else(|| format!("{key} is missing"))
+    };
+    let marker = TodoMarker::parse(field("content")?, field("kind")?);
+    let Value::Object(marker) = serde_json::to_value(marker).map_err(|e| e.to_string())? else {
+        return Err("marker is not an object".to_string());
+    };
+    for (key, value) in marker {
+        record.entry(key).or_insert(value);
+    }
+
+    Ok(())
+}
+
+/// Todos span lines, older todos only ever covered their first line.
+fn todo_v5_to_v6(record: &mut Map<String, Value>) -> Result<(), String> {
+    if !record.contains_key("end_line_number") {
+        let line_number = record
+            .get("line_number")
+            .cloned()
+            .ok_or("line_number is missing")?;
+        record.insert("end_line_number".to_string(), line_number);
+    }
+
+    Ok(())
+}
+
+/// Todos keep the tags added later apart from their inline tags.
+fn todo_v6_to_v7(record: &mut Map<String, Value>) -> Result<(), String> {
+    if !record.contains_key("added_tags") {
+        let content = record
+            .get("content")
+            .and_then(Value::as_str)
+            .ok_or("content is missing")?;
+        let tags: Tags = record
+            .get("tags")
+            .cloned()
+            .map(serde_json::from_value)
+            .transpose()
+            .map_err(|e| format!("invalid tags: {e}"))?
+            .unwrap_or_default();
+        let added_tags = TodoItem::added_tags_of(&tags, content)
+            .into_iter()
+            .map(Value::String)
+            .collect();
+        record.insert("added_tags".to_string(), Value::Array(added_tags));
+    }
+
+    Ok(())
+}
+
+/// Notes and todos record the git state they were created in, which is
+/// unknown for older records.
+fn add_git_state(record: &mut Map<String, Value>) -> Result<(), String> {
+    record.entry("git").or_insert(Value::Null);
+    Ok(())
+}
+
+/// Why a stored record could not be decoded.
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The record is damaged or doesn't match its schema.
+    Malformed(String),
+    /// The record was written by a newer version of the backend; it is
+    /// intact but can't be read by this one.
+    Unsupported { version: u64 },
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Malformed(msg) => write!(f, "{msg}"),
+            DecodeError::Unsupported { version } => {
+                write!(f, "schema version {version} is newer than supported")
+            }
+        }
+    }
+}
+
+/// A decoded record and whether it had to be upgraded to get there.
+pub struct Decoded<R> {
+    pub record: R,
+    pub upgraded: bool,
+}
+
+/// Serialise a record at the current version.
+pub fn encode<R: Record>(record: &R) -> Result<Value, serde_json::Error> {
+    let mut value = serde_json::to_value(record)?;
+    if let Value::Object(fields) = &mut value {
+        fields.insert(VERSION_KEY.to_string(), R::version().into());
+    }
+
+    Ok(value)
+}
+
+/// Deserialise a record of any known version, upgrading it on the way.
+pub fn decode<R: Record>(value: Value) -> Result<Decoded<R>, DecodeError> {
+    let Value::Object(mut fields) = value else {
+        return Err(DecodeError::Malformed(format!(
+            "{} is not a JSON object",
+            R::KIND
+        )));
+    };
+
+    let version = match fields.remove(VERSION_KEY) {
+        None => 0,
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| DecodeError::Malformed(format!("invalid {VERSION_KEY}: {version}")))?,
+    };
+    if version > R::version() {
+        return Err(DecodeError::Unsupported { version });
+    }
+
+    for upgrade in &R::upgrades()[version as usize..] {
+        upgrade(&mut fields)
+            .map_err(|e| DecodeError::Malformed(format!("failed to upgrade {}: {e}", R::KIND)))?;
+    }
+
+    let record = serde_json::from_value(Value::Object(fields))
+        .map_err(|e| DecodeError::Malformed(e.to_string()))?;
+
+    Ok(Decoded {
+        record,
+        upgraded: version < R::version(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{
+        database::record::{DecodeError, Record, decode, encode},
+        types::todo::TodoItem,
+    };
+
+    #[test]
+    fn unversioned_todos_are_upgraded() {
+        let decoded = decode::<TodoItem>(json!({
+            "id": "6f1c0a52-7e55-4b8e-9a70-1a1f0e7f9a11",
+            "branch": "main",
+            "file_path": "src/lib.rs",
+            "line_number": 3,
+            "content": "TODO: old #legacy",
+            "created_at": "2025-01-01T00:00:00Z",
+            "deleted_at": null,
+        }))
+        .unwrap();
+
+        assert!(decoded.upgraded);
+        assert_eq!(decoded.record.project_dir, "");
+        assert!(decoded.record.tags.contains("legacy"));
+        assert!(decoded.record.added_tags.is_empty());
+        assert_eq!(decoded.record.kind, "TODO");
+        assert_eq!(
+            decoded.record.hash,
+            TodoItem::compute_hash("src/lib.rs", "TODO: old #legacy")
+        );
+
+        let encoded = encode(&decoded.record).unwrap();
+        assert_eq!(encoded["schema_version"], TodoItem::version());
+        let again = decode::<TodoItem>(encoded).unwrap();
+        assert!(!again.upgraded);
+        assert_eq!(again.record, decoded.record);
+    }
+
+    #[test]
+    fn todo_kinds_are_taken_from_the_content() {
+        let decoded = decode::<TodoItem>(json!({
+            "schema_version": 3,
+            "id": "6f1c0a52-7e55-4b8e-9a70-1a1f0e7f9a11",
+            "hash": TodoItem::compute_hash("src/lib.rs", "FIXME: old"),
+            "project_dir": "/project",
+            "branch": "main",
+            "file_path": "src/lib.rs",
+            "line_number": 3,
+            "content": "FIXME: old",
+            "created_at": "2025-01-01T00:00:00Z",
+            "deleted_at": null,
+            "tags": [],
+            "git": null,
+        }))
+        .unwrap();
+
+        assert!(decoded.upgraded);
+        assert_eq!(decoded.record.kind, "FIXME");
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let result = decode::<TodoItem>(json!({ "schema_version": 99 }));
+
+        assert!(matches!(
+            result,
+            Err(DecodeError::Unsupported { version: 99 })
+        ));
+    }
+}
@@ -0,0 +1,1099 @@
+use std::{collections::HashMap, path::Path, str::FromStr};
+
+use anyhow::Context;
+use chrono::{DateTime, SecondsFormat, Utc};
+use sqlx::{
+    QueryBuilder, Row, Sqlite, Transaction,
+    migrate::Migrator,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions, SqliteRow},
+};
+use uuid::Uuid;
+
+use crate::{
+    database::{NoteStorage, SearchStorage, StorageError, TodoChanges, TodoStorage},
+    search,
+    types::{
+        NewNote, Note, NoteContext, NotePatch,
+        git::GitState,
+        marker::TodoMarker,
+        search::{DocKind, QueryTerm, SearchField, SearchHit, SearchQuery},
+        tag::Tags,
+        todo::{SortDirection, TodoItem, TodoOrder, TodoPage, TodoPatch, TodoQuery},
+    },
+};
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+#[derive(Clone)]
+pub struct SqliteDatabase {
+    pool: SqlitePool,
+}
+
+impl SqliteDatabase {
+    /// Open (or create) the database at `path` and apply any pending
+    /// migrations.
+    pub async fn init(path: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal);
+        let pool = SqlitePoolOptions::new()
+            .connect_with(options)
+            .await
+            .context("failed to open sqlite database")?;
+
+        Self::with_pool(pool).await
+    }
+
+    /// Open the existing database at `path` without ever changing it. Fails
+    /// unless every migration has been applied to it, as it can't be
+    /// migrated. Writes fail.
+    pub async fn open_read_only(path: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        let path = path.as_ref();
+        let options = SqliteConnectOptions::new().filename(path).read_only(true);
+        let pool = SqlitePoolOptions::new()
+            .connect_with(options)
+            .await
+            .with_context(|| format!("failed to open sqlite database {}", path.display()))?;
+
+        let applied: Vec<(i64, Vec<u8>)> =
+            sqlx::query_as("SELECT version, checksum FROM _sqlx_migrations WHERE success = 1")
+                .fetch_all(&pool)
+                .await
+                .with_context(|| format!("{} is not a contextual database", path.display()))?;
+        let applied: HashMap<_, _> = applied.into_iter().collect();
+        for migration in MIGRATOR.iter() {
+            match applied.get(&migration.version) {
+                Some(checksum) if *checksum == *migration.checksum => {}
+                Some(_) => anyhow::bail!(
+                    "{} was migrated to a different schema than this version's",
+                    path.display()
+                ),
+                None => anyhow::bail!(
+                    "{} has an older schema, open it once with --storage sqlite to upgrade it",
+                    path.display()
+                ),
+            }
+        }
+        if applied.len() > MIGRATOR.iter().count() {
+            anyhow::bail!("{} was written by a newer version", path.display());
+        }
+
+        Ok(Self { pool })
+    }
+
+    /// Create a database which only lives as long as the returned value.
+    pub async fn in_memory() -> Result<Self, anyhow::Error> {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:")?;
+        // every connection to `:memory:` opens a new database, so the pool must
+        // never hand out (or recycle) more than a single connection
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with(options)
+            .await
+            .context("failed to open in-memory sqlite database")?;
+
+        Self::with_pool(pool).await
+    }
+
+    async fn with_pool(pool: SqlitePool) -> Result<Self, anyhow::Error> {
+        MIGRATOR
+            .run(&pool)
+            .await
+            .context("failed to run sqlite migrations")?;
+
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait::async_trait]
+impl NoteStorage for SqliteDatabase {
+    async fn save_note(&self, new_note: NewNote) -> Result<Uuid, StorageError> {
+        let note = Note::new(new_note);
+        insert_note(&self.pool, &note).await?;
+
+        Ok(note.id)
+    }
+
+    async fn insert_note(&self, note: Note) -> Result<(), StorageError> {
+        insert_note(&self.pool, &note).await
+    }
+
+    async fn get_note(&self, note_id: Uuid) -> Result<Note, StorageError> {
+        let row = sqlx::query("SELECT * FROM notes WHERE id = ?")
+            .bind(note_id.to_string())
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or(StorageError::note_not_found(note_id))?;
+
+        note_from_row(&row)
+    }
+
+    async fn get_notes(&self) -> Result<Vec<Note>, StorageError> {
+        sqlx::query("SELECT * FROM notes ORDER BY project_dir, filename")
+            .fetch_all(&self.pool)
+            .await?
+            .iter()
+            .map(note_from_row)
+            .collect()
+    }
+
+    async fn update_note(&self, note_id: Uuid, patch: NotePatch) -> Result<Note, StorageError> {
+        let mut tx = self.pool.begin().await?;
+        let row = sqlx::query("SELECT * FROM notes WHERE id = ?")
+            .bind(note_id.to_string())
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or(StorageError::note_not_found(note_id))?;
+        let mut note = note_from_row(&row)?;
+        patch.apply(&mut note);
+
+        sqlx::query(
+            "UPDATE notes SET filename = ?, project_dir = ?, selection = ?, content = ?, tags = ?,
+             start_line = ?, end_line = ?, context_before = ?, context_after = ?, orphaned = ?,
+             start_column = ?, end_column = ?, position_encoding = ?
+             WHERE id = ?",
+        )
+        .bind(&note.context.filename)
+        .bind(&note.context.project_dir)
+        .bind(&note.context.selection)
+        .bind(&note.content)
+        .bind(encode_tags(&note.tags))
+        .bind(note.context.start_line.map(to_i64).transpose()?)
+        .bind(note.context.end_line.map(to_i64).transpose()?)
+        .bind(encode_lines(&note.context.context_before))
+        .bind(encode_lines(&note.context.context_after))
+        .bind(note.orphaned)
+        .bind(note.context.start_column.map(to_i64).transpose()?)
+        .bind(note.context.end_column.map(to_i64).transpose()?)
+        .bind(note.context.position_encoding.as_str())
+        .bind(note.id.to_string())
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(note)
+    }
+
+    async fn delete_note(&self, note_id: Uuid) -> Result<(), StorageError> {
+        let result = sqlx::query("DELETE FROM notes WHERE id = ?")
+            .bind(note_id.to_string())
+            .execute(&self.pool)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(StorageError::note_not_found(note_id));
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl TodoStorage for SqliteDatabase {
+    async fn insert_todo(&self, todo_item: TodoItem) -> Result<(), StorageError> {
+        insert_todo(&self.pool, &todo_item).await
+    }
+
+    async fn get_todos(&self) -> Result<Vec<TodoItem>, StorageError> {
+        sqlx::query("SELECT * FROM todos ORDER BY file_path, line_number")
+            .fetch_all(&self.pool)
+            .await?
+            .iter()
+            .map(todo_from_row)
+            .collect()
+    }
+
+    async fn query_todos(&self, query: &TodoQuery) -> Result<TodoPage, StorageError> {
+        // the sort key as SQL expression, ids break ties so the order is total
+        let sort_key = match query.order_by {
+            TodoOrder::CreatedAt => "created_at, id",
+            TodoOrder::FilePath => "file_path, line_number, id",
+        };
+
+        let mut builder: QueryBuilder<Sqlite> =
+            QueryBuilder::new("SELECT * FROM todos WHERE 1 = 1");
+        if !query.include_deleted {
+            builder.push(" AND deleted_at IS NULL");
+        }
+        if let Some(project_dir) = &query.project_dir {
+            builder.push(" AND project_dir = ").push_bind(project_dir);
+        }
+        if let Some(branch) = &query.branch {
+            builder.push(" AND branch = ").push_bind(branch);
+        }
+        if let Some(prefix) = &query.file_path_prefix {
+            builder
+                .push(" AND substr(file_path, 1, ")
+                .push_bind(prefix.chars().count() as i64)
+                .push(") = ")
+                .push_bind(prefix);
+        }
+        if let Some(needle) = &query.content_contains {
+            builder
+                .push(" AND instr(content_lower, ")
+                .push_bind(needle.to_lowercase())
+                .push(") > 0");
+        }
+        if let Some(tag) = &query.tag {
+            builder
+                .push(" AND EXISTS (SELECT 1 FROM json_each(todos.tags) WHERE value = ")
+                .push_bind(tag)
+                .push(")");
+        }
+        if let Some(kind) = &query.kind {
+            builder.push(" AND kind = ").push_bind(kind);
+        }
+        if let Some(owner) = &query.owner {
+            builder
+                .push(" AND json_extract(marker, '$.owner') = ")
+                .push_bind(owner);
+        }
+        if let Some(issue) = &query.issue {
+            builder
+                .push(" AND json_extract(marker, '$.issue') = ")
+                .push_bind(issue);
+        }
+        if let Some(priority) = query.priority {
+            builder
+                .push(" AND json_extract(marker, '$.priority') = ")
+                .push_bind(i64::from(priority));
+        }
+        if let Some(day) = query.due_before {
+            // ISO dates compare like strings
+            builder
+                .push(" AND json_extract(marker, '$.due') <= ")
+                .push_bind(day.to_string());
+        }
+        if let Some(after) = query.created_after {
+            builder
+                .push(" AND created_at >= ")
+                .push_bind(encode_time(after));
+        }
+        if let Some(before) = query.created_before {
+            builder
+                .push(" AND created_at < ")
+                .push_bind(encode_time(before));
+        }
+        if let Some(cursor) = query.cursor {
+            let exists: Option<i64> = sqlx::query_scalar("SELECT 1 FROM todos WHERE id = ?")
+                .bind(cursor.to_string())
+                .fetch_optional(&self.pool)
+                .await?;
+            if exists.is_none() {
+                return Err(StorageError::Invalid(format!("unknown cursor: {cursor}")));
+            }
+
+            let comparison = match query.direction {
+                SortDirection::Asc => ">",
+                SortDirection::Desc => "<",
+            };
+            builder
+                .push(format!(
+                    " AND ({sort_key}) {comparison} (SELECT {sort_key} FROM todos WHERE id = "
+                ))
+                .push_bind(cursor.to_string())
+                .push(")");
+        }
+
+        let direction = match query.direction {
+            SortDirection::Asc => "ASC",
+            SortDirection::Desc => "DESC",
+        };
+        let order_by = sort_key
+            .split(", ")
+            .map(|column| format!("{column} {direction}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        builder
+            .push(format!(" ORDER BY {order_by} LIMIT "))
+            .push_bind(to_i64(query.limit as u64)? + 1);
+
+        let todos = builder
+            .build()
+            .fetch_all(&self.pool)
+            .await?
+            .iter()
+            .map(todo_from_row)
+            .collect::<Result<_, _>>()?;
+
+        Ok(TodoPage::from_overfetched(todos, query.limit))
+    }
+
+    async fn find_todos_by_hash(
+        &self,
+        project_dir: &str,
+        branch: &str,
+        hash: &str,
+    ) -> Result<Vec<TodoItem>, StorageError> {
+        sqlx::query(
+            "SELECT * FROM todos
+             WHERE hash = ? AND project_dir = ? AND branch = ? AND deleted_at IS NULL
+             ORDER BY line_number",
+        )
+        .bind(hash)
+        .bind(project_dir)
+        .bind(branch)
+        .fetch_all(&self.pool)
+        .await?
+        .iter()
+        .map(todo_from_row)
+        .collect()
+    }
+
+    async fn update_todo(&self, todo_id: Uuid, patch: TodoPatch) -> Result<TodoItem, StorageError> {
+        let mut tx = self.pool.begin().await?;
+        let todo_item = update_todo(&mut tx, todo_id, patch).await?;
+        tx.commit().await?;
+
+        Ok(todo_item)
+    }
+
+    async fn delete_todo(&self, todo_id: Uuid) -> Result<(), StorageError> {
+        delete_todo(&self.pool, todo_id).await
+    }
+
+    /// Applies the changes in one transaction.
+    async fn apply_todo_changes(&self, changes: TodoChanges) -> Result<(), StorageError> {
+        let mut tx = self.pool.begin().await?;
+        for todo_item in &changes.inserted {
+            insert_todo(&mut *tx, todo_item).await?;
+        }
+        for (todo_id, line_number) in changes.moved {
+            update_todo(&mut tx, todo_id, TodoChanges::move_patch(line_number)).await?;
+        }
+        for todo_id in changes.deleted {
+            delete_todo(&mut *tx, todo_id).await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl SearchStorage for SqliteDatabase {
+    async fn search(&self, query: &SearchQuery) -> Result<Vec<SearchHit>, StorageError> {
+        // bm25() is lower for better matches
+        let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+            "SELECT kind, id, content, selection, -bm25(search_index) AS score
+             FROM search_index WHERE search_index MATCH ",
+        );
+        builder.push_bind(fts5_query(&query.terms));
+        if let Some(kind) = query.kind {
+            builder.push(" AND kind = ").push_bind(kind.as_str());
+        }
+        if let Some(project_dir) = &query.project_dir {
+            builder.push(" AND project_dir = ").push_bind(project_dir);
+        }
+        if let Some(tag) = &query.tag {
+            builder
+                .push(
+                    " AND search_index.id IN (
+                        SELECT notes.id FROM notes, json_each(notes.tags) WHERE json_each.value = ",
+                )
+                .push_bind(tag)
+                .push(
+                    " UNION ALL
+                        SELECT todos.id FROM todos, json_each(todos.tags) WHERE json_each.value = ",
+                )
+                .push_bind(tag)
+                .push(")");
+        }
+        builder
+            .push(" ORDER BY score DESC, kind, id LIMIT ")
+            .push_bind(to_i64(query.limit as u64)?);
+
+        builder
+            .build()
+            .fetch_all(&self.pool)
+            .await?
+            .iter()
+            .map(|row| {
+                let kind = match row.try_get::<&str, _>("kind")? {
+                    "note" => DocKind::Note,
+                    "todo" => DocKind::Todo,
+                    other => {
+                        return Err(StorageError::Corrupt(format!(
+                            "unknown kind in search index: {other}"
+                        )));
+                    }
+                };
+                let mut fields = vec![(SearchField::Content, row.try_get("content")?)];
+                if kind == DocKind::Note {
+                    fields.push((SearchField::Selection, row.try_get("selection")?));
+                }
+
+                Ok(SearchHit {
+                    kind,
+                    id: parse_id(row)?,
+                    score: row.try_get("score")?,
+                    snippets: search::snippets(&fields, &query.terms),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Express query terms in FTS5's query syntax, which joins them with AND.
+/// Tokens only hold alphanumeric characters, so they need no escaping.
+fn fts5_query(terms: &[QueryTerm]) -> String {
+    terms
+        .iter()
+        .map(|term| match term {
+            QueryTerm::Word(word) => format!("\"{word}\""),
+            QueryTerm::Prefix(prefix) => format!("\"{prefix}\"*"),
+            QueryTerm::Phrase(words) => format!("\"{}\"", words.join(" ")),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+async fn insert_note<'e, E>(executor: E, note: &Note) -> Result<(), StorageError>
+where
+    E: sqlx::SqliteExecutor<'e>,
+{
+    sqlx::query(
+        "INSERT INTO notes (id, filename, project_dir, selection, content, tags,
+                            start_line, end_line, context_before, context_after, orphaned,
+                            start_column, end_column, position_encoding, git)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(note.id.to_string())
+    .bind(&note.context.filename)
+    .bind(&note.context.project_dir)
+    .bind(&note.context.selection)
+    .bind(&note.content)
+    .bind(encode_tags(&note.tags))
+    .bind(note.context.start_line.map(to_i64).transpose()?)
+    .bind(note.context.end_line.map(to_i64).transpose()?)
+    .bind(encode_lines(&note.context.context_before))
+    .bind(encode_lines(&note.context.context_after))
+    .bind(note.orphaned)
+    .bind(note.context.start_column.map(to_i64).transpose()?)
+    .bind(note.context.end_column.map(to_i64).transpose()?)
+    .bind(note.context.position_encoding.as_str())
+    .bind(encode_git(note.git.as_ref())?)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+async fn insert_todo<'e, E>(executor: E, todo_item: &TodoItem) -> Result<(), StorageError>
+where
+    E: sqlx::SqliteExecutor<'e>,
+{
+    sqlx::query(
+        "INSERT INTO todos (id, hash, project_dir, branch, file_path, line_number, end_line_number, content, content_lower, kind, marker, created_at, deleted_at, tags, added_tags, git)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(todo_item.id.to_string())
+    .bind(&todo_item.hash)
+    .bind(&todo_item.project_dir)
+    .bind(&todo_item.branch)
+    .bind(&todo_item.file_path)
+    .bind(to_i64(todo_item.line_number)?)
+    .bind(to_i64(todo_item.end_line_number)?)
+    .bind(&todo_item.content)
+    .bind(todo_item.content.to_lowercase())
+    .bind(&todo_item.kind)
+    .bind(encode_marker(&todo_item.marker)?)
+    .bind(encode_time(todo_item.created_at))
+    .bind(todo_item.deleted_at.map(encode_time))
+    .bind(encode_tags(&todo_item.tags))
+    .bind(encode_tags(&todo_item.added_tags))
+    .bind(encode_git(todo_item.git.as_ref())?)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+/// Apply `patch` to a todo within a transaction and return the updated todo.
+async fn update_todo(
+    tx: &mut Transaction<'_, Sqlite>,
+    todo_id: Uuid,
+    patch: TodoPatch,
+) -> Result<TodoItem, StorageError> {
+    let row = sqlx::query("SELECT * FROM todos WHERE id = ?")
+        .bind(todo_id.to_string())
+        .fetch_optional(&mut **tx)
+        .await?
+        .ok_or(StorageError::todo_not_found(todo_id))?;
+    let mut todo_item = todo_from_row(&row)?;
+    patch.apply(&mut todo_item);
+
+    sqlx::query(
+        "UPDATE todos SET hash = ?, file_path = ?, line_number = ?, end_line_number = ?,
+             content = ?, content_lower = ?, tags = ?, added_tags = ?, marker = ? WHERE id = ?",
+    )
+    .bind(&todo_item.hash)
+    .bind(&todo_item.file_path)
+    .bind(to_i64(todo_item.line_number)?)
+    .bind(to_i64(todo_item.end_line_number)?)
+    .bind(&todo_item.content)
+    .bind(todo_item.content.to_lowercase())
+    .bind(encode_tags(&todo_item.tags))
+    .bind(encode_tags(&todo_item.added_tags))
+    .bind(encode_marker(&todo_item.marker)?)
+    .bind(todo_item.id.to_string())
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(todo_item)
+}
+
+async fn delete_todo<'e, E>(executor: E, todo_id: Uuid) -> Result<(), StorageError>
+where
+    E: sqlx::SqliteExecutor<'e>,
+{
+    let result = sqlx::query("UPDATE todos SET deleted_at = ? WHERE id = ?")
+        .bind(encode_time(Utc::now()))
+        .bind(todo_id.to_string())
+        .execute(executor)
+        .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(StorageError::todo_not_found(todo_id));
+    }
+
+    Ok(())
+}
+
+/// Times are stored as RFC 3339 text in UTC with every digit of the
+/// nanoseconds, so comparing the text compares the times exactly, like
+/// [TodoQuery::compare] does.
+fn encode_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Nanos, true)
+}
+
+/// Tags are stored as a JSON array, which `json_each` can filter on.
+fn encode_tags(tags: &Tags) -> String {
+    serde_json::Value::from_iter(tags.iter().map(String::as_str)).to_string()
+}
+
+fn tags_from_row(row: &SqliteRow, column: &str) -> Result<Tags, StorageError> {
+    serde_json::from_str(row.try_get(column)?)
+        .map_err(|e| StorageError::Corrupt(format!("invalid tags stored in database: {e}")))
+}
+
+fn encode_marker(marker: &TodoMarker) -> Result<String, StorageError> {
+    serde_json::to_string(marker)
+        .map_err(|e| StorageError::Invalid(format!("failed to encode todo marker: {e}")))
+}
+
+fn marker_from_row(row: &SqliteRow) -> Result<TodoMarker, StorageError> {
+    serde_json::from_str(row.try_get("marker")?)
+        .map_err(|e| StorageError::Corrupt(format!("invalid todo marker stored in database: {e}")))
+}
+
+fn encode_git(git: Option<&GitState>) -> Result<Option<String>, StorageError> {
+    git.map(serde_json::to_string)
+        .transpose()
+        .map_err(|e| StorageError::Invalid(format!("failed to encode git state: {e}")))
+}
+
+fn git_from_row(row: &SqliteRow) -> Result<Option<GitState>, StorageError> {
+    row.try_get::<Option<&str>, _>("git")?
+        .map(serde_json::from_str)
+        .transpose()
+        .map_err(|e| StorageError::Corrupt(format!("invalid git state stored in database: {e}")))
+}
+
+fn encode_lines(lines: &[String]) -> String {
+    serde_json::Value::from_iter(lines.iter().map(String::as_str)).to_string()
+}
+
+fn lines_from_row(row: &SqliteRow, column: &str) -> Result<Vec<String>, StorageError> {
+    serde_json::from_str(row.try_get(column)?)
+        .map_err(|e| StorageError::Corrupt(format!("invalid {column} stored in database: {e}")))
+}
+
+fn opt_u64_from_row(row: &SqliteRow, column: &str) -> Result<Option<u64>, StorageError> {
+    row.try_get::<Option<i64>, _>(column)?
+        .map(|value| {
+            u64::try_from(value).map_err(|_| {
+                StorageError::Corrupt(format!("invalid {column} stored in database: {value}"))
+            })
+        })
+        .transpose()
+}
+
+/// SQLite integers are signed.
+fn to_i64(value: u64) -> Result<i64, StorageError> {
+    i64::try_from(value).map_err(|_| StorageError::Invalid(format!("{value} is out of range")))
+}
+
+fn parse_id(row: &SqliteRow) -> Result<Uuid, StorageError> {
+    let id: String = row.try_get("id")?;
+    Uuid::parse_str(&id)
+        .map_err(|_| StorageError::Corrupt(format!("invalid id stored in database: {id}")))
+}
+
+fn note_from_row(row: &SqliteRow) -> Result<Note, StorageError> {
+    Ok(Note {
+        id: parse_id(row)?,
+        context: NoteContext {
+            filename: row.try_get("filename")?,
+            project_dir: row.try_get("project_dir")?,
+            selection: row.try_get("selection")?,
+            start_line: opt_u64_from_row(row, "start_line")?,
+            end_line: opt_u64_from_row(row, "end_line")?,
+            start_column: opt_u64_from_row(row, "start_column")?,
+            end_column: opt_u64_from_row(row, "end_column")?,
+            position_encoding: row
+                .try_get::<&str, _>("position_encoding")?
+                .parse()
+                .map_err(|e| StorageError::Corrupt(format!("{e}")))?,
+            context_before: lines_from_row(row, "context_before")?,
+            context_after: lines_from_row(row, "context_after")?,
+        },
+        content: row.try_get("content")?,
+        tags: tags_from_row(row, "tags")?,
+        orphaned: row.try_get("orphaned")?,
+        git: git_from_row(row)?,
+    })
+}
+
+fn line_number_from_row(row: &SqliteRow, column: &str) -> Result<u64, StorageError> {
+    let line_number: i64 = row.try_get(column)?;
+    line_number.try_into().map_err(|_| {
+        StorageError::Corrupt(format!(
+            "invalid line number stored in database: {line_number}"
+        ))
+    })
+}
+
+fn todo_from_row(row: &SqliteRow) -> Result<TodoItem, StorageError> {
+    Ok(TodoItem {
+        id: parse_id(row)?,
+        hash: row.try_get("hash")?,
+        project_dir: row.try_get("project_dir")?,
+        branch: row.try_get("branch")?,
+        file_path: row.try_get("file_path")?,
+        line_number: line_number_from_row(row, "line_number")?,
+        end_line_number: line_number_from_row(row, "end_line_number")?,
+        content: row.try_get("content")?,
+        kind: row.try_get("kind")?,
+        marker: marker_from_row(row)?,
+        created_at: row.try_get::<DateTime<Utc>, _>("created_at")?,
+        deleted_at: row.try_get("deleted_at")?,
+        tags: tags_from_row(row, "tags")?,
+        added_tags: tags_from_row(row, "added_tags")?,
+        git: git_from_row(row)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::{
+        database::{
+            NoteStorage, SearchStorage, Storage, StorageError, TodoChanges, TodoStorage,
+            file::FileDatabase, memory::MemoryDatabase, paginate_todos, sqlite::SqliteDatabase,
+        },
+        fixtures,
+        types::{
+            NotePatch,
+            search::{DocKind, SearchQuery},
+            todo::{NewTodoItem, SortDirection, TodoItem, TodoOrder, TodoPatch, TodoQuery},
+        },
+    };
+
+    #[tokio::test]
+    async fn note_crud_roundtrip() {
+        let db = SqliteDatabase::in_memory().await.unwrap();
+        let new_note =
+            fixtures::new_note("test_file.rs", "fn test_function() {", "A new test note");
+
+        let id = db.save_note(new_note).await.unwrap();
+        let note = db.get_note(id).await.unwrap();
+
+        assert_eq!(note.id, id);
+        assert_eq!(note.content, "A new test note");
+        assert_eq!(note.context.selection, "fn test_function() {");
+
+        let patch = NotePatch {
+            content: Some("An updated note".to_string()),
+            ..Default::default()
+        };
+        let updated = db.update_note(id, patch).await.unwrap();
+        assert_eq!(updated.context.selection, "fn test_function() {");
+        let notes = db.get_notes().await.unwrap();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].content, "An updated note");
+
+        db.delete_note(id).await.unwrap();
+        let err = db.get_note(id).await.unwrap_err();
+        assert!(matches!(err, StorageError::NotFound { .. }));
+        let err = db.delete_note(id).await.unwrap_err();
+        assert!(matches!(err, StorageError::NotFound { .. }));
+    }
+
+    #[tokio::test]
+    async fn saved_todos_are_listed() {
+        let db = SqliteDatabase::in_memory().await.unwrap();
+        for (line_number, content) in [(12, "TODO: write docs"), (3, "TODO: write tests")] {
+            let new_todo = fixtures::new_todo("src/lib.rs", line_number, content);
+            db.save_todo(new_todo).await.unwrap();
+        }
+
+        let todos = db.get_todos().await.unwrap();
+
+        assert_eq!(todos.len(), 2);
+        assert_eq!(todos[0].line_number, 3);
+        assert_eq!(todos[1].line_number, 12);
+        assert!(todos.iter().all(|t| t.deleted_at.is_none()));
+    }
+
+    #[tokio::test]
+    async fn repeated_todos_are_deduplicated() {
+        let db = SqliteDatabase::in_memory().await.unwrap();
+        let new_todo =
+            |line_number| fixtures::new_todo("src/lib.rs", line_number, "TODO: write docs");
+
+        let first = db.save_todo(new_todo(12)).await.unwrap();
+        let second = db.save_todo(new_todo(12)).await.unwrap();
+        let elsewhere = db.save_todo(new_todo(15)).await.unwrap();
+
+        assert_eq!(first, second);
+        assert_ne!(first, elsewhere);
+        let todos = db.get_todos().await.unwrap();
+        assert_eq!(todos.len(), 2);
+        assert_eq!(todos[0].line_number, 12);
+        assert_eq!(todos[1].line_number, 15);
+    }
+
+    #[tokio::test]
+    async fn todos_are_paged_with_cursor() {
+        let db = SqliteDatabase::in_memory().await.unwrap();
+        for (file_path, line_number) in [
+            ("src/a.rs", 1),
+            ("src/b.rs", 7),
+            ("src/a.rs", 9),
+            ("tests/c.rs", 2),
+        ] {
+            let new_todo = fixtures::new_todo(
+                file_path,
+                line_number,
+                &format!("TODO: fix {file_path}:{line_number}"),
+            );
+            db.save_todo(new_todo).await.unwrap();
+        }
+
+        let mut query = TodoQuery {
+            file_path_prefix: Some("src/".to_string()),
+            order_by: TodoOrder::FilePath,
+            direction: SortDirection::Desc,
+            limit: 2,
+            ..Default::default()
+        };
+        let first = db.query_todos(&query).await.unwrap();
+        let lines: Vec<_> = first.todos.iter().map(|t| t.line_number).collect();
+        assert_eq!(lines, vec![7, 9]);
+        assert!(first.next_cursor.is_some());
+
+        query.cursor = first.next_cursor;
+        let second = db.query_todos(&query).await.unwrap();
+        let lines: Vec<_> = second.todos.iter().map(|t| t.line_number).collect();
+        assert_eq!(lines, vec![1]);
+        assert!(second.next_cursor.is_none());
+
+        // the in-memory implementation used by other storages agrees
+        query.cursor = first.next_cursor;
+        let paginated = paginate_todos(&query, db.get_todos().await.unwrap()).unwrap();
+        assert_eq!(paginated.todos[0].id, second.todos[0].id);
+    }
+
+    #[tokio::test]
+    async fn todos_are_ordered_by_exact_creation_time() {
+        let db = SqliteDatabase::in_memory().await.unwrap();
+        let second = "2026-01-01T00:00:00Z"
+            .parse::<chrono::DateTime<chrono::Utc>>()
+            .unwrap();
+        // fractions which sort differently when written with fewer digits
+        for nanos in [100_000_000, 99_999_999, 0, 5, 100_000_000] {
+            let mut todo_item = TodoItem::new(fixtures::new_todo(
+                "src/lib.rs",
+                1,
+                &format!("TODO: {nanos}"),
+            ));
+            todo_item.created_at = second + chrono::Duration::nanoseconds(nanos);
+            db.insert_todo(todo_item).await.unwrap();
+        }
+
+        let mut query = TodoQuery {
+            created_after: Some(second + chrono::Duration::nanoseconds(5)),
+            limit: 2,
+            ..Default::default()
+        };
+        let todos = db.get_todos().await.unwrap();
+        let mut paged = Vec::new();
+        loop {
+            let page = db.query_todos(&query).await.unwrap();
+            let expected = paginate_todos(&query, todos.clone()).unwrap();
+            assert_eq!(page.todos, expected.todos);
+            assert_eq!(page.next_cursor, expected.next_cursor);
+            paged.extend(page.todos);
+            match page.next_cursor {
+                Some(cursor) => query.cursor = Some(cursor),
+                None => break,
+            }
+        }
+        let nanos: Vec<_> = paged
+            .iter()
+            .map(|todo| todo.created_at.timestamp_subsec_nanos())
+            .collect();
+        assert_eq!(nanos, vec![5, 99_999_999, 100_000_000, 100_000_000]);
+    }
+
+    #[tokio::test]
+    async fn failed_todo_changes_apply_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let sqlite = SqliteDatabase::in_memory().await.unwrap();
+        let memory = MemoryDatabase::new();
+        let file = FileDatabase::init(dir.path()).await.unwrap();
+        let new_todo = |line_number| {
+            fixtures::new_todo("src/lib.rs", line_number, &format!("TODO: {line_number}"))
+        };
+
+        for db in [&sqlite as &dyn Storage, &memory, &file] {
+            let kept = db.save_todo(new_todo(1)).await.unwrap();
+            let before = db.get_todos().await.unwrap();
+
+            let changes = TodoChanges {
+                inserted: vec![TodoItem::new(new_todo(2))],
+                moved: vec![(kept, 5)],
+                deleted: vec![kept, Uuid::new_v4()],
+            };
+            let err = db.apply_todo_changes(changes).await.unwrap_err();
+            assert!(matches!(err, StorageError::NotFound { .. }));
+            assert_eq!(db.get_todos().await.unwrap(), before);
+
+            let changes = TodoChanges {
+                inserted: vec![TodoItem::new(new_todo(2))],
+                moved: vec![(kept, 5)],
+                deleted: Vec::new(),
+            };
+            db.apply_todo_changes(changes).await.unwrap();
+            let mut lines: Vec<_> = db
+                .get_todos()
+                .await
+                .unwrap()
+                .iter()
+                .map(|todo| todo.line_number)
+                .collect();
+            lines.sort();
+            assert_eq!(lines, vec![2, 5]);
+        }
+    }
+
+    #[tokio::test]
+    async fn todos_are_filtered_by_content_ignoring_case() {
+        let db = SqliteDatabase::in_memory().await.unwrap();
+        for (line_number, content) in [(1, "TODO: ÄNDERN"), (2, "TODO: Fix Parser"), (3, "TODO: x")]
+        {
+            db.save_todo(fixtures::new_todo("src/lib.rs", line_number, content))
+                .await
+                .unwrap();
+        }
+
+        for (needle, line_number) in [("ändern", 1), ("Än", 1), ("fix parser", 2)] {
+            let query = TodoQuery {
+                content_contains: Some(needle.to_string()),
+                ..Default::default()
+            };
+            let page = db.query_todos(&query).await.unwrap();
+            let lines: Vec<_> = page.todos.iter().map(|t| t.line_number).collect();
+            assert_eq!(lines, vec![line_number], "{needle}");
+
+            // the in-memory implementation used by other storages agrees
+            let paginated = paginate_todos(&query, db.get_todos().await.unwrap()).unwrap();
+            assert_eq!(paginated.todos, page.todos, "{needle}");
+        }
+    }
+
+    #[tokio::test]
+    async fn search_index_follows_writes() {
+        let db = SqliteDatabase::in_memory().await.unwrap();
+        let note_id = db
+            .save_note(fixtures::new_note(
+                "src/parser.rs",
+                "fn parse_header(input: &str)",
+                "Chokes on empty headers",
+            ))
+            .await
+            .unwrap();
+        let todo_id = db
+            .save_todo(fixtures::new_todo(
+                "src/parser.rs",
+                12,
+                "TODO: handle empty input",
+            ))
+            .await
+            .unwrap();
+        let search = |text: &str| SearchQuery {
+            terms: SearchQuery::parse_terms(text),
+            kind: None,
+            project_dir: None,
+            limit: SearchQuery::DEFAULT_LIMIT,
+            tag: None,
+        };
+
+        let hits = db.search(&search("empty")).await.unwrap();
+        assert_eq!(hits.len(), 2);
+        let hits = db.search(&search("\"parse header\" head*")).await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!((hits[0].kind, hits[0].id), (DocKind::Note, note_id));
+        let (start, end) = hits[0].snippets[0].highlights[0];
+        assert_eq!(&hits[0].snippets[0].text[start..end], "headers");
+
+        db.delete_todo(todo_id).await.unwrap();
+        let patch = NotePatch {
+            content: Some("Works now".to_string()),
+            ..Default::default()
+        };
+        db.update_note(note_id, patch).await.unwrap();
+        assert!(db.search(&search("empty")).await.unwrap().is_empty());
+        assert_eq!(db.search(&search("works")).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn search_folds_case_and_diacritics_like_the_in_memory_index() {
+        let sqlite = SqliteDatabase::in_memory().await.unwrap();
+        let memory = MemoryDatabase::new();
+        let search = |text: &str| SearchQuery {
+            terms: SearchQuery::parse_terms(text),
+            kind: None,
+            project_dir: None,
+            limit: SearchQuery::DEFAULT_LIMIT,
+            tag: None,
+        };
+
+        for db in [&sqlite as &dyn Storage, &memory] {
+            for content in ["TODO: Café au lait", "TODO: ÄNDERN"] {
+                db.save_todo(fixtures::new_todo("src/lib.rs", 1, content))
+                    .await
+                    .unwrap();
+            }
+
+            for text in ["cafe", "CAFÉ", "caf*", "andern", "\"ändern\""] {
+                let hits = db.search(&search(text)).await.unwrap();
+                assert_eq!(hits.len(), 1, "{text}");
+                let (start, end) = hits[0].snippets[0].highlights[0];
+                assert!(
+                    ["Café", "ÄNDERN"].contains(&&hits[0].snippets[0].text[start..end]),
+                    "{text}"
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn tags_are_persisted_and_filterable() {
+        let db = SqliteDatabase::in_memory().await.unwrap();
+        let new_todo = |content: &str| fixtures::new_todo("src/lib.rs", 1, content);
+        let perf = db.save_todo(new_todo("TODO: faster #perf")).await.unwrap();
+        db.save_todo(new_todo("TODO: faster docs")).await.unwrap();
+
+        let query = TodoQuery {
+            tag: Some("perf".to_string()),
+            ..Default::default()
+        };
+        let page = db.query_todos(&query).await.unwrap();
+        assert_eq!(page.todos.len(), 1);
+        assert_eq!(page.todos[0].id, perf);
+
+        let patch = TodoPatch {
+            add_tags: ["hot".to_string()].into(),
+            remove_tags: ["perf".to_string()].into(),
+            ..Default::default()
+        };
+        let updated = db.update_todo(perf, patch).await.unwrap();
+        assert_eq!(updated.tags, ["hot".to_string()].into());
+        let search = SearchQuery {
+            terms: SearchQuery::parse_terms("faster"),
+            kind: None,
+            project_dir: None,
+            tag: Some("hot".to_string()),
+            limit: SearchQuery::DEFAULT_LIMIT,
+        };
+        let hits = db.search(&search).await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].id, perf);
+
+        // inline tags follow the content, added ones stay
+        let patch = TodoPatch {
+            content: Some("TODO: faster #cache".to_string()),
+            ..Default::default()
+        };
+        db.update_todo(perf, patch).await.unwrap();
+        let patch = TodoPatch {
+            content: Some("TODO: faster #io".to_string()),
+            ..Default::default()
+        };
+        let updated = db.update_todo(perf, patch).await.unwrap();
+        assert_eq!(updated.tags, ["hot".to_string(), "io".to_string()].into());
+        assert_eq!(updated.added_tags, ["hot".to_string()].into());
+        let stored = db.get_todos().await.unwrap();
+        assert_eq!(stored.iter().find(|t| t.id == perf), Some(&updated));
+    }
+
+    #[tokio::test]
+    async fn todos_are_filtered_by_marker() {
+        let db = SqliteDatabase::in_memory().await.unwrap();
+        let new_todo = |kind: &str, content: &str| NewTodoItem {
+            kind: kind.to_string(),
+            ..fixtures::new_todo("src/lib.rs", 1, content)
+        };
+        let urgent = db
+            .save_todo(new_todo(
+                "FIXME",
+                "// FIXME(alice, P1)[#12] @2026-03-01: crash",
+            ))
+            .await
+            .unwrap();
+        db.save_todo(new_todo("TODO", "// TODO(bob) @2027-01-01: docs"))
+            .await
+            .unwrap();
+        db.save_todo(new_todo("todo!", "todo!(\"parse\")"))
+            .await
+            .unwrap();
+
+        let queries = [
+            TodoQuery {
+                kind: Some("FIXME".to_string()),
+                ..Default::default()
+            },
+            TodoQuery {
+                owner: Some("alice".to_string()),
+                ..Default::default()
+            },
+            TodoQuery {
+                issue: Some("#12".to_string()),
+                ..Default::default()
+            },
+            TodoQuery {
+                priority: Some(1),
+                ..Default::default()
+            },
+            TodoQuery {
+                due_before: Some("2026-12-31".parse().unwrap()),
+                ..Default::default()
+            },
+        ];
+        for query in queries {
+            let page = db.query_todos(&query).await.unwrap();
+            let ids: Vec<_> = page.todos.iter().map(|t| t.id).collect();
+            assert_eq!(ids, vec![urgent], "{query:?}");
+
+            // the in-memory implementation used by other storages agrees
+            let paginated = paginate_todos(&query, db.get_todos().await.unwrap()).unwrap();
+            assert_eq!(paginated.todos.len(), 1, "{query:?}");
+        }
+    }
+}
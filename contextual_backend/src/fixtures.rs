@@ -0,0 +1,32 @@
+//! Notes and todos shared by the tests of several modules.
+
+use crate::types::{NewNote, NoteContext, todo::NewTodoItem};
+
+/// A note on `selection` in `filename` of the project `/project`.
+pub fn new_note(filename: &str, selection: &str, content: &str) -> NewNote {
+    NewNote {
+        context: NoteContext {
+            filename: filename.to_string(),
+            project_dir: "/project".to_string(),
+            selection: selection.to_string(),
+            ..Default::default()
+        },
+        content: content.to_string(),
+        tags: Default::default(),
+        git: None,
+    }
+}
+
+/// A single line `TODO` of the project `/project` on branch `main`.
+pub fn new_todo(file_path: &str, line_number: u64, content: &str) -> NewTodoItem {
+    NewTodoItem {
+        project_dir: "/project".to_string(),
+        branch: "main".to_string(),
+        file_path: file_path.to_string(),
+        line_number,
+        end_line_number: line_number,
+        content: content.to_string(),
+        kind: "TODO".to_string(),
+        git: None,
+    }
+}
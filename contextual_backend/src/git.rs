@@ -0,0 +1,214 @@
+//! Reading the state of the git repository a project lives in.
+//!
+//! The backend runs the `git` executable rather than trusting the branch an
+//! editor reports. Projects outside of a repository, or machines without git,
+//! simply have no git state.
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use tokio::process::Command;
+
+use crate::types::{NewNote, git::GitState, todo::NewTodoItem};
+
+/// A snapshot of a repository: its HEAD and which files are dirty.
+#[derive(Debug, Clone)]
+pub struct Repository {
+    /// The directory paths are resolved against, as given.
+    dir: PathBuf,
+    /// `dir` with symlinks resolved, which is how git reports paths.
+    canonical_dir: PathBuf,
+    branch: Option<String>,
+    commit: Option<String>,
+    /// Absolute paths of modified, staged and untracked files.
+    dirty: HashSet<PathBuf>,
+}
+
+impl Repository {
+    /// Read the repository containing `dir`, `None` when there is none.
+    pub async fn open(dir: impl AsRef<Path>) -> Option<Self> {
+        let dir = dir.as_ref();
+        let root = PathBuf::from(git(dir, &["rev-parse", "--show-toplevel"]).await?);
+        let branch = current_branch(dir).await;
+        let commit = git(dir, &["rev-parse", "--quiet", "--verify", "HEAD^{commit}"]).await;
+        let status = git(
+            dir,
+            &["status", "--porcelain=v1", "-z", "--untracked-files=all"],
+        )
+        .await?;
+
+        Some(Self {
+            dir: dir.to_path_buf(),
+            canonical_dir: tokio::fs::canonicalize(dir)
+                .await
+                .unwrap_or_else(|_| dir.to_path_buf()),
+            branch,
+            commit,
+            dirty: parse_status(&status).map(|path| root.join(path)).collect(),
+        })
+    }
+
+    pub fn branch(&self) -> Option<&str> {
+        self.branch.as_deref()
+    }
+
+    /// The state of `file`, either absolute or relative to the directory the
+    /// repository was opened at.
+    pub fn state_of(&self, file: &str) -> GitState {
+        let path = Path::new(file);
+        let relative = if path.is_absolute() {
+            path.strip_prefix(&self.dir)
+                .or_else(|_| path.strip_prefix(&self.canonical_dir))
+                .ok()
+        } else {
+            Some(path)
+        };
+        let dirty = match relative {
+            Some(relative) => self.dirty.contains(&self.canonical_dir.join(relative)),
+            None => self.dirty.contains(path),
+        };
+
+        GitState {
+            branch: self.branch.clone(),
+            commit: self.commit.clone(),
+            dirty,
+        }
+    }
+
+    /// Record the repository state on a new note.
+    pub fn stamp_note(&self, note: &mut NewNote) {
+        note.git = Some(self.state_of(&note.context.filename));
+    }
+
+    /// Record the repository state on a scanned todo, the checked out branch
+    /// replaces the one sent by the client.
+    pub fn stamp_todo(&self, todo: &mut NewTodoItem) {
+        if let Some(branch) = &self.branch {
+            todo.branch.clone_from(branch);
+        }
+        todo.git = Some(self.state_of(&todo.file_path));
+    }
+
+    /// The full id of the commit `rev` names, `None` when it names none.
+    pub async fn resolve(&self, rev: &str) -> Option<String> {
+        let rev = format!("{rev}^{{commit}}");
+        git(
+            &self.dir,
+            &["rev-parse", "--quiet", "--verify", "--end-of-options", &rev],
+        )
+        .await
+    }
+
+    /// Whether `ancestor` is `commit` or one of its ancestors.
+    pub async fn is_ancestor(&self, ancestor: &str, commit: &str) -> bool {
+        git(
+            &self.dir,
+            &["merge-base", "--is-ancestor", "--", ancestor, commit],
+        )
+        .await
+        .is_some()
+    }
+}
+
+/// Run git in `dir` and return its trimmed output, `None` when it failed.
+async fn git(dir: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        // don't take the index lock just to refresh it, an editor's git
+        // integration may be holding it
+        .env("GIT_OPTIONAL_LOCKS", "0")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .await
+        .ok()?;
+
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The branch checked out in the repository containing `dir`, without the
+/// cost of reading its whole state. `None` outside of a repository and on a
+/// detached HEAD.
+pub async fn current_branch(dir: impl AsRef<Path>) -> Option<String> {
+    git(
+        dir.as_ref(),
+        &["symbolic-ref", "--quiet", "--short", "HEAD"],
+    )
+    .await
+}
+
+/// The paths in `git status --porcelain -z` output, relative to the root of
+/// the repository.
+fn parse_status(status: &str) -> impl Iterator<Item = &str> {
+    let mut entries = status.split('\0').filter(|entry| !entry.is_empty());
+    std::iter::from_fn(move || {
+        let entry = entries.next()?;
+        let (code, path) = (entry.get(..2)?, entry.get(3..)?);
+        // renames and copies are followed by their source path
+        if code.contains(['R', 'C']) {
+            entries.next();
+        }
+        Some(path)
+    })
+}
+
+/// Run `git` with `args` in `dir` for a test, committing as a test user.
+#[cfg(test)]
+pub fn run_git(dir: &Path, args: &[&str]) {
+    let status = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+        .args(args)
+        .output()
+        .unwrap()
+        .status;
+    assert!(status.success(), "git {args:?} failed");
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::git::{Repository, parse_status, run_git as git};
+
+    #[test]
+    fn status_paths_are_parsed() {
+        let status = " M src/lib.rs\0R  new.rs\0old.rs\0?? notes/todo.md\0";
+
+        assert_eq!(
+            parse_status(status).collect::<Vec<_>>(),
+            vec!["src/lib.rs", "new.rs", "notes/todo.md"]
+        );
+    }
+
+    #[tokio::test]
+    async fn repository_state_is_read() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(Repository::open(dir.path()).await.is_none());
+
+        git(dir.path(), &["init", "--quiet", "--initial-branch=trunk"]);
+        std::fs::write(dir.path().join("clean.rs"), "fn clean() {}\n").unwrap();
+        git(dir.path(), &["add", "clean.rs"]);
+        git(dir.path(), &["commit", "--quiet", "-m", "initial"]);
+        std::fs::write(dir.path().join("dirty.rs"), "// TODO: commit me\n").unwrap();
+
+        let repo = Repository::open(dir.path()).await.unwrap();
+        let head = repo.resolve("HEAD").await.unwrap();
+        let clean = repo.state_of("clean.rs");
+        assert_eq!(clean.branch.as_deref(), Some("trunk"));
+        assert_eq!(clean.commit.as_deref(), Some(head.as_str()));
+        assert!(!clean.dirty);
+        let absolute = dir.path().join("dirty.rs");
+        assert!(repo.state_of(absolute.to_str().unwrap()).dirty);
+
+        assert!(repo.is_ancestor(&head, "HEAD").await);
+        assert!(repo.resolve("no-such-branch").await.is_none());
+    }
+}
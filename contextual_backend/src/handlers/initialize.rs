@@ -0,0 +1,38 @@
+use futures::future::BoxFuture;
+
+use crate::{
+    handlers::{parse_params, to_response},
+    jsonrpc::{JsonRpcRequest, ResponseError},
+    service::Service,
+    types::position::{InitializeParams, InitializeResult, ServerCapabilities, ServerInfo},
+};
+
+/// `contextual/initialize`: negotiate how columns are counted, following
+/// LSP's `positionEncoding`.
+///
+/// The backend keeps no per-client state, so clients keep sending the agreed
+/// encoding as `position_encoding` along with positions.
+#[derive(Debug, Clone)]
+pub struct InitializeService;
+
+impl Service<JsonRpcRequest> for InitializeService {
+    type Response = serde_json::Value;
+    type Error = ResponseError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&mut self, req: JsonRpcRequest) -> Self::Future {
+        Box::pin(async move {
+            let params: InitializeParams = parse_params(req.params)?;
+
+            to_response(InitializeResult {
+                capabilities: ServerCapabilities {
+                    position_encoding: params.position_encoding(),
+                },
+                server_info: ServerInfo {
+                    name: env!("CARGO_PKG_NAME"),
+                    version: env!("CARGO_PKG_VERSION"),
+                },
+            })
+        })
+    }
+}
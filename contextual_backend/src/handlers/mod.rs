@@ -1,37 +1,771 @@
+use std::path::PathBuf;
+
+use serde::Serialize;
 use serde_json::{Value, json};
 
-use crate::{database::Storage, types::todo::NewTodoItems};
+use crate::{
+    config::Config,
+    database::{Storage, StorageError, TodoChanges},
+    git::Repository,
+    handlers::{
+        echo::EchoService,
+        initialize::InitializeService,
+        note::{
+            CreateNoteService, DeleteNoteService, GetNoteService, ListNotesService,
+            ReanchorNotesService, UpdateNoteService,
+        },
+        search::SearchService,
+        tag::{ListTagsService, UpdateTagsService},
+        todo::{
+            ListTodosService, NewTodoService, ScanTodosService, SyncFileTodosService,
+            SyncTodosService,
+        },
+        watch::{UnwatchService, WatchService},
+    },
+    jsonrpc::ResponseError,
+    router::RouterFactory,
+    scan::{self, ScanOptions},
+    sync,
+    types::{
+        FieldError,
+        todo::{
+            FileTodos, MovedTodo, NewTodoItem, NewTodoItems, ScanTodosParams, ScannedTodos,
+            SyncFileTodosParams, SyncTodosParams, TodoItem, TodoOrder, TodoQuery, TodoSyncReport,
+        },
+    },
+    watch::Watcher,
+};
 
 pub mod echo;
+pub mod initialize;
+pub mod note;
+pub mod search;
+pub mod tag;
 pub mod todo;
+pub mod watch;
+
+/// Register every `contextual/*` method backed by `storage`. Watching
+/// projects is only offered when the configuration enables it.
+pub fn routes<S>(storage: S, config: &Config) -> RouterFactory
+where
+    S: Storage + Clone + Send + Sync + 'static,
+{
+    let router = RouterFactory::new()
+        .with_route("contextual/echo", EchoService)
+        .with_route("contextual/initialize", InitializeService)
+        .with_route("contextual/new_todo", NewTodoService::new(storage.clone()))
+        .with_route(
+            "contextual/sync_todos",
+            SyncTodosService::new(storage.clone()),
+        )
+        .with_route(
+            "contextual/sync_file_todos",
+            SyncFileTodosService::new(
+                Handler::new(storage.clone()).with_scan_options(config.scan_options()),
+            ),
+        )
+        .with_route(
+            "contextual/scan_todos",
+            ScanTodosService::new(
+                Handler::new(storage.clone()).with_scan_options(config.scan_options()),
+            ),
+        )
+        .with_route(
+            "contextual/todo/list",
+            ListTodosService::new(storage.clone()),
+        )
+        .with_route(
+            "contextual/note/create",
+            CreateNoteService::new(storage.clone()),
+        )
+        .with_route("contextual/note/get", GetNoteService::new(storage.clone()))
+        .with_route(
+            "contextual/note/list",
+            ListNotesService::new(storage.clone()),
+        )
+        .with_route(
+            "contextual/note/update",
+            UpdateNoteService::new(storage.clone()),
+        )
+        .with_route(
+            "contextual/note/delete",
+            DeleteNoteService::new(storage.clone()),
+        )
+        .with_route(
+            "contextual/note/reanchor",
+            ReanchorNotesService::new(storage.clone()),
+        )
+        .with_route("contextual/search", SearchService::new(storage.clone()))
+        .with_route(
+            "contextual/tags/add",
+            UpdateTagsService::add(storage.clone()),
+        )
+        .with_route(
+            "contextual/tags/remove",
+            UpdateTagsService::remove(storage.clone()),
+        )
+        .with_route(
+            "contextual/tags/list",
+            ListTagsService::new(storage.clone()),
+        );
+    if !config.watch.enabled {
+        return router;
+    }
+
+    let watcher = Watcher::new(
+        Handler::new(storage).with_scan_options(config.scan_options()),
+        router.notifier(),
+        config.watch.debounce(),
+    );
+    router
+        .with_route("contextual/watch", WatchService::new(watcher.clone()))
+        .with_route("contextual/unwatch", UnwatchService::new(watcher))
+}
 
+/// Parse the request params, answering with an "invalid params" error when
+/// they do not have the expected shape. The error's data names the offending
+/// field.
+fn parse_params<T>(params: Value) -> Result<T, ResponseError>
+where
+    T: TryFrom<Value, Error = anyhow::Error>,
+{
+    T::try_from(params).map_err(|e| {
+        let error = ResponseError::invalid_params(format!("{e:#}"));
+        if let Some(field) = FieldError::field_of(&e) {
+            error.with_data(json!({ "field": field }))
+        } else {
+            error
+        }
+    })
+}
+
+impl From<StorageError> for ResponseError {
+    fn from(error: StorageError) -> Self {
+        let code = match error {
+            StorageError::NotFound { .. } => ResponseError::NOT_FOUND,
+            StorageError::Conflict(_) => ResponseError::CONFLICT,
+            StorageError::Corrupt(_) => ResponseError::CORRUPT,
+            StorageError::Invalid(_) => ResponseError::INVALID_PARAMS,
+            StorageError::Io(_) | StorageError::Database(_) => ResponseError::SERVER_ERROR,
+        };
+
+        ResponseError::new(code, error.to_string())
+    }
+}
+
+fn to_response<T: Serialize>(result: T) -> Result<Value, ResponseError> {
+    serde_json::to_value(result)
+        .map_err(|e| ResponseError::new(ResponseError::INTERNAL_ERROR, e.to_string()))
+}
+
+#[derive(Clone)]
 pub struct Handler<DB> {
     database: DB,
+    scan_options: ScanOptions,
 }
 
 impl<DB: Storage> Handler<DB> {
     pub fn new(database: DB) -> Self {
-        Self { database }
+        Self {
+            database,
+            scan_options: ScanOptions::default(),
+        }
+    }
+
+    /// Use `scan_options` for the scans requests don't configure otherwise.
+    pub fn with_scan_options(mut self, scan_options: ScanOptions) -> Self {
+        self.scan_options = scan_options;
+        self
+    }
+
+    /// Reconcile a full scan of a project's todos on one branch with the
+    /// stored todos.
+    ///
+    /// New todos are saved, todos which moved keep their id and get their line
+    /// updated, and todos missing from the scan are soft-deleted. Invalid todos
+    /// of the scan are reported and left out, the stored todos of their files
+    /// are kept.
+    pub async fn sync_todos(
+        &self,
+        mut params: SyncTodosParams,
+    ) -> Result<TodoSyncReport, StorageError> {
+        let repo = Repository::open(&params.project_dir).await;
+        stamp_todos(repo.as_ref(), &mut params.branch, &mut params.todos.todos);
+        self.reconcile(params, None, |_| true).await
     }
 
-    pub async fn save_note(&self, params: Value) -> Result<Value, anyhow::Error> {
-        let new_note = params.try_into()?;
-        let note_id = self.database.save_note(new_note).await?;
+    /// Rescan `paths`, files or directories relative to `project_dir`, and
+    /// reconcile only the stored todos within them, leaving the rest of the
+    /// project alone.
+    pub async fn sync_paths(
+        &self,
+        project_dir: String,
+        mut branch: String,
+        paths: Vec<String>,
+    ) -> Result<TodoSyncReport, StorageError> {
+        let dir = PathBuf::from(&project_dir);
+        let options = self.scan_options.clone();
+        let (scan_branch, scan_paths) = (branch.clone(), paths.clone());
+        let mut todos = tokio::task::spawn_blocking(move || {
+            scan::scan_paths(&dir, &scan_branch, &scan_paths, &options)
+        })
+        .await?;
+        let repo = Repository::open(&project_dir).await;
+        stamp_todos(repo.as_ref(), &mut branch, &mut todos);
 
-        Ok(json!({"id": note_id}))
+        let params = SyncTodosParams {
+            project_dir,
+            branch,
+            todos: todos.into(),
+        };
+        self.reconcile(params, None, |file_path| {
+            paths.iter().any(|path| {
+                path.is_empty()
+                    || file_path
+                        .strip_prefix(path.as_str())
+                        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+        })
+        .await
     }
 
-    pub async fn sync_todos(&self, params: Value) -> Result<Value, anyhow::Error> {
-        let _project_todos: NewTodoItems = params.try_into()?;
-        let _saved_todos = self.database.get_todos().await?;
+    /// Reconcile the todos of a single file with its stored todos, e.g. after
+    /// it was saved, instead of rescanning the whole project.
+    pub async fn sync_file_todos(
+        &self,
+        params: SyncFileTodosParams,
+    ) -> Result<TodoSyncReport, StorageError> {
+        let SyncFileTodosParams {
+            project_dir,
+            mut branch,
+            file_path,
+            source,
+        } = params;
+        let mut todos = match source {
+            FileTodos::Todos(todos) => todos,
+            FileTodos::Contents { text, config } => {
+                let options = self.scan_options.clone().apply(config);
+                let dir = PathBuf::from(&project_dir);
+                let (scan_branch, scan_path) = (branch.clone(), file_path.clone());
+                let todos = tokio::task::spawn_blocking(move || {
+                    scan::scan_contents(&dir, &scan_branch, &scan_path, &text, &options)
+                })
+                .await?;
+                NewTodoItems::from(todos)
+            }
+        };
+        let repo = Repository::open(&project_dir).await;
+        stamp_todos(repo.as_ref(), &mut branch, &mut todos.todos);
 
-        todo!()
+        let params = SyncTodosParams {
+            project_dir,
+            branch,
+            todos,
+        };
+        self.reconcile(params, Some(file_path.clone()), |path| path == file_path)
+            .await
     }
 
-    pub async fn save_todo_item(&self, params: Value) -> Result<Value, anyhow::Error> {
-        let new_todo = params.try_into()?;
-        let todo_id = self.database.save_todo(new_todo).await?;
+    /// Reconcile `params.todos`, stamped with [stamp_todos], with the stored
+    /// todos of the project and branch whose file path starts with
+    /// `file_path_prefix` and is `in_scope`.
+    async fn reconcile(
+        &self,
+        params: SyncTodosParams,
+        file_path_prefix: Option<String>,
+        in_scope: impl Fn(&str) -> bool,
+    ) -> Result<TodoSyncReport, StorageError> {
+        if params.branch.is_empty() {
+            return Err(StorageError::branch_required());
+        }
+
+        let mut query = TodoQuery {
+            project_dir: Some(params.project_dir.clone()),
+            branch: Some(params.branch.clone()),
+            file_path_prefix,
+            order_by: TodoOrder::FilePath,
+            limit: TodoQuery::MAX_LIMIT,
+            ..Default::default()
+        };
+        let mut saved_todos = Vec::new();
+        loop {
+            let page = self.database.query_todos(&query).await?;
+            saved_todos.extend(
+                page.todos
+                    .into_iter()
+                    .filter(|todo| in_scope(&todo.file_path)),
+            );
+            match page.next_cursor {
+                Some(cursor) => query.cursor = Some(cursor),
+                None => break,
+            }
+        }
+        let NewTodoItems { todos, errors } = params.todos;
+        let reconciliation = sync::reconcile(saved_todos, todos);
+
+        let mut report = TodoSyncReport::default();
+        let mut changes = TodoChanges::default();
+        for new_todo in reconciliation.added {
+            // the reconciliation already accounted for duplicates, so the
+            // todo is inserted as is instead of going through `save_todo`
+            let todo_item = TodoItem::new(new_todo);
+            report.added.push(todo_item.id);
+            changes.inserted.push(todo_item);
+        }
+        for (todo, line_number) in reconciliation.moved {
+            changes.moved.push((todo.id, line_number));
+            report.moved.push(MovedTodo {
+                id: todo.id,
+                from_line: todo.line_number,
+                to_line: line_number,
+            });
+        }
+        let mut unchanged = reconciliation.unchanged;
+        for todo in reconciliation.removed {
+            // an invalid todo of the scan may be this one
+            let maybe_invalid = errors.iter().any(|error| {
+                error
+                    .file_path
+                    .as_ref()
+                    .is_none_or(|file_path| *file_path == todo.file_path)
+            });
+            if maybe_invalid {
+                unchanged.push(todo);
+                continue;
+            }
+            changes.deleted.push(todo.id);
+            report.removed.push(todo.id);
+        }
+        self.database.apply_todo_changes(changes).await?;
+        report.unchanged = unchanged.iter().map(|todo| todo.id).collect();
+        report.errors = errors;
+
+        Ok(report)
+    }
+
+    /// Scan a project for todos on the backend, and reconcile them with the
+    /// stored todos when `params.sync` is set.
+    pub async fn scan_todos(&self, params: ScanTodosParams) -> Result<ScannedTodos, StorageError> {
+        let ScanTodosParams {
+            project_dir,
+            mut branch,
+            config,
+            sync,
+        } = params;
+        let options = self.scan_options.clone().apply(config);
+
+        let dir = PathBuf::from(&project_dir);
+        let scan_branch = branch.clone();
+        let mut todos =
+            tokio::task::spawn_blocking(move || scan::scan(&dir, &scan_branch, &options))
+                .await?
+                .map_err(|e| StorageError::Invalid(format!("cannot scan {project_dir}: {e}")))?;
+        let repo = Repository::open(&project_dir).await;
+        stamp_todos(repo.as_ref(), &mut branch, &mut todos);
+
+        let sync = if sync {
+            let params = SyncTodosParams {
+                project_dir,
+                branch,
+                todos: todos.clone().into(),
+            };
+            Some(self.reconcile(params, None, |_| true).await?)
+        } else {
+            None
+        };
+
+        Ok(ScannedTodos { todos, sync })
+    }
+}
+
+/// Inside a repository the checked out branch is the scope of `todos`,
+/// whatever the client says, and each todo is stamped with the state of the
+/// repository.
+fn stamp_todos(repo: Option<&Repository>, branch: &mut String, todos: &mut [NewTodoItem]) {
+    let Some(repo) = repo else {
+        return;
+    };
+    if let Some(repo_branch) = repo.branch() {
+        *branch = repo_branch.to_string();
+    }
+    for todo in todos {
+        repo.stamp_todo(todo);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{
+        config::Config,
+        database::{StorageError, TodoStorage, memory::MemoryDatabase},
+        git::run_git as git,
+        handlers::{Handler, routes},
+        jsonrpc::{JsonRpcRequest, ResponseError},
+        service::Service,
+        types::todo::SyncFileTodosParams,
+    };
+
+    #[tokio::test]
+    async fn router_dispatches_to_registered_methods() {
+        let mut router = routes(MemoryDatabase::new(), &Config::default()).service();
+        let request = |id, method: &str| JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id,
+            method: method.to_string(),
+            params: json!({ "hello": "world" }),
+        };
+
+        let response = router.call(request(1, "contextual/echo")).await.unwrap();
+        assert_eq!(response.id, 1);
+        assert_eq!(response.result, Some(json!({ "hello": "world" })));
+
+        let response = router.call(request(2, "contextual/unknown")).await.unwrap();
+        assert_eq!(
+            response.error.unwrap().code,
+            ResponseError::METHOD_NOT_FOUND
+        );
+
+        let response = router
+            .call(request(3, "contextual/note/list"))
+            .await
+            .unwrap();
+        assert_eq!(response.result, Some(json!({ "notes": [] })));
+    }
+
+    #[tokio::test]
+    async fn sync_todos_soft_deletes_vanished_todos() {
+        let storage = MemoryDatabase::new();
+        let handler = Handler::new(storage.clone());
+
+        let first = handler
+            .sync_todos(
+                json!({
+                    "project_dir": "/project",
+                    "branch": "main",
+                    "todos": [
+                        { "file_path": "src/lib.rs", "line_number": 4, "content": "TODO: a" },
+                        { "file_path": "src/lib.rs", "line_number": 9, "content": "TODO: b" },
+                    ],
+                })
+                .try_into()
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(first.added.len(), 2);
+
+        let second = handler
+            .sync_todos(
+                json!({
+                    "project_dir": "/project",
+                    "branch": "main",
+                    "todos": [
+                        { "file_path": "src/lib.rs", "line_number": 6, "content": "TODO: b" },
+                    ],
+                })
+                .try_into()
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert!(second.added.is_empty());
+        assert_eq!(second.moved.len(), 1);
+        assert_eq!(second.moved[0].to_line, 6);
+        assert_eq!(second.removed.len(), 1);
+
+        let todos = storage.get_todos().await.unwrap();
+        assert_eq!(todos.len(), 2);
+        let removed = todos.iter().find(|t| t.id == second.removed[0]).unwrap();
+        assert!(removed.deleted_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn invalid_todos_are_reported_by_index_and_field() {
+        let storage = MemoryDatabase::new();
+        let mut router = routes(storage.clone(), &Config::default()).service();
+        let mut call = async |method: &str, params| {
+            let request = JsonRpcRequest {
+                jsonrpc: "2.0".to_string(),
+                id: 1,
+                method: method.to_string(),
+                params,
+            };
+            router.call(request).await.unwrap()
+        };
+        let sync = |todos| {
+            json!({
+                "project_dir": "/project",
+                "branch": "main",
+                "todos": todos,
+            })
+        };
+
+        let response = call(
+            "contextual/sync_todos",
+            sync(json!([
+                { "file_path": "a.rs", "line_number": 1, "content": "TODO: a" },
+                { "file_path": "b.rs", "line_number": 2, "content": "TODO: b" },
+            ])),
+        )
+        .await;
+        assert_eq!(
+            response.result.unwrap()["added"].as_array().unwrap().len(),
+            2
+        );
+
+        let response = call(
+            "contextual/sync_todos",
+            sync(json!([
+                { "file_path": "a.rs", "line_number": 1, "content": "TODO: a" },
+                { "file_path": "b.rs", "content": "TODO: b" },
+                "TODO: c",
+                { "file_path": "c.rs", "line_number": 4, "end_line_number": 3, "content": "TODO: d" },
+                { "file_path": "c.rs", "line_number": 5, "content": "TODO: e" },
+            ])),
+        )
+        .await;
+        let report = response.result.unwrap();
+        assert_eq!(report["added"].as_array().unwrap().len(), 1);
+        assert_eq!(report["unchanged"].as_array().unwrap().len(), 2);
+        // todo 2 may be the one stored for any file, so none is removed
+        assert_eq!(report["removed"], json!([]));
+        assert_eq!(
+            report["errors"],
+            json!([
+                {
+                    "index": 1,
+                    "file_path": "b.rs",
+                    "field": "line_number",
+                    "message": "line_number is required",
+                },
+                { "index": 2, "file_path": null, "field": null, "message": "todo must be an object" },
+                {
+                    "index": 3,
+                    "file_path": "c.rs",
+                    "field": "end_line_number",
+                    "message": "end_line_number must not be before line_number",
+                },
+            ])
+        );
+        let todos = storage.get_todos().await.unwrap();
+        assert_eq!(todos.len(), 3);
+        assert!(todos.iter().all(|todo| todo.deleted_at.is_none()));
+
+        let error = call("contextual/note/get", json!({})).await.error.unwrap();
+        assert_eq!(error.message, "id is required");
+        assert_eq!(error.data.unwrap(), json!({ "field": "id" }));
+    }
+
+    #[tokio::test]
+    async fn file_todos_are_synced_on_their_own() {
+        let dir = tempfile::tempdir().unwrap();
+        let project_dir = dir.path().to_str().unwrap();
+        let storage = MemoryDatabase::new();
+        let handler = Handler::new(storage.clone());
+        handler
+            .sync_todos(
+                json!({
+                    "project_dir": project_dir,
+                    "branch": "main",
+                    "todos": [
+                        { "file_path": "a.rs", "line_number": 1, "content": "// TODO: a" },
+                        { "file_path": "b.rs", "line_number": 1, "content": "TODO: b" },
+                    ],
+                })
+                .try_into()
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let report = handler
+            .sync_file_todos(
+                json!({
+                    "project_dir": project_dir,
+                    "branch": "main",
+                    "file_path": dir.path().join("a.rs"),
+                    "contents": "fn main() {}\n// TODO: a\n// FIXME: c\n",
+                })
+                .try_into()
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.added.len(), 1);
+        assert_eq!(report.moved.len(), 1);
+        assert!(report.removed.is_empty());
+
+        let report = handler
+            .sync_file_todos(
+                json!({
+                    "project_dir": project_dir,
+                    "branch": "main",
+                    "file_path": "b.rs",
+                    "todos": [],
+                })
+                .try_into()
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.removed.len(), 1);
+        let live = storage.get_todos().await.unwrap();
+        let mut live: Vec<_> = live
+            .iter()
+            .filter(|todo| todo.deleted_at.is_none())
+            .map(|todo| (todo.file_path.as_str(), todo.line_number))
+            .collect();
+        live.sort();
+        assert_eq!(live, vec![("a.rs", 2), ("a.rs", 3)]);
+
+        // the saved contents of files a scan skips have no todos
+        std::fs::write(dir.path().join(".gitignore"), "ignored.rs\n").unwrap();
+        let options = crate::scan::ScanOptions {
+            max_file_size: 16,
+            ..Default::default()
+        };
+        let handler = Handler::new(storage.clone()).with_scan_options(options);
+        for (file_path, contents) in [
+            ("ignored.rs", "// TODO: d\n"),
+            (".hidden.rs", "// TODO: d\n"),
+            ("large.rs", "// TODO: d, a lot of it\n"),
+        ] {
+            std::fs::write(dir.path().join(file_path), contents).unwrap();
+            let report = handler
+                .sync_file_todos(
+                    json!({
+                        "project_dir": project_dir,
+                        "branch": "main",
+                        "file_path": file_path,
+                        "contents": contents,
+                    })
+                    .try_into()
+                    .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert!(report.added.is_empty(), "{file_path} was scanned");
+        }
+
+        let params = |extra: serde_json::Value| {
+            let mut params = json!({ "project_dir": "/project", "file_path": "a.rs" });
+            params
+                .as_object_mut()
+                .unwrap()
+                .extend(extra.as_object().unwrap().clone());
+            SyncFileTodosParams::try_from(params)
+        };
+        assert!(params(json!({})).is_err());
+        assert!(params(json!({ "todos": [], "contents": "" })).is_err());
+        assert!(params(json!({ "todos": [{ "file_path": "b.rs", "line_number": 1, "content": "TODO: b" }] })).is_err());
+    }
+
+    #[tokio::test]
+    async fn scanned_todos_are_synced() {
+        let dir = tempfile::tempdir().unwrap();
+        let project_dir = dir.path().to_str().unwrap();
+        std::fs::write(dir.path().join("main.lua"), "-- TODO: a\n-- NOTE: b\n").unwrap();
+        let handler = Handler::new(MemoryDatabase::new());
+        let params = |keywords| {
+            json!({
+                "project_dir": project_dir,
+                "branch": "main",
+                "keywords": keywords,
+                "sync": true,
+            })
+            .try_into()
+            .unwrap()
+        };
+
+        let scanned = handler.scan_todos(params(json!(null))).await.unwrap();
+        assert_eq!(scanned.todos.len(), 1);
+        assert_eq!(scanned.sync.unwrap().added.len(), 1);
+
+        let scanned = handler.scan_todos(params(json!(["NOTE"]))).await.unwrap();
+        let report = scanned.sync.unwrap();
+        assert_eq!((report.added.len(), report.removed.len()), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn projects_outside_of_git_need_a_branch() {
+        let dir = tempfile::tempdir().unwrap();
+        let project_dir = dir.path().to_str().unwrap();
+        std::fs::write(dir.path().join("main.lua"), "-- TODO: a\n").unwrap();
+        let handler = Handler::new(MemoryDatabase::new());
+
+        let params = json!({ "project_dir": project_dir, "sync": true });
+        let error = handler
+            .scan_todos(params.try_into().unwrap())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            StorageError::branch_required().to_string()
+        );
+
+        let params = json!({ "project_dir": project_dir, "branch": "default", "sync": true });
+        let scanned = handler
+            .scan_todos(params.try_into().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(scanned.todos[0].branch, "default");
+        assert_eq!(scanned.sync.unwrap().added.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn git_state_is_read_from_the_repository() {
+        let dir = tempfile::tempdir().unwrap();
+        let project_dir = dir.path().to_str().unwrap();
+        git(dir.path(), &["init", "--quiet", "--initial-branch=trunk"]);
+        std::fs::write(dir.path().join("lib.rs"), "// TODO: a\n").unwrap();
+        git(dir.path(), &["add", "lib.rs"]);
+        git(dir.path(), &["commit", "--quiet", "-m", "first"]);
+        git(dir.path(), &["tag", "first"]);
+        let mut router = routes(MemoryDatabase::new(), &Config::default()).service();
+        let mut call = async |method: &str, params| {
+            let request = JsonRpcRequest {
+                jsonrpc: "2.0".to_string(),
+                id: 1,
+                method: method.to_string(),
+                params,
+            };
+            router.call(request).await.unwrap().result.unwrap()
+        };
+
+        // the editor's idea of the branch is ignored
+        call(
+            "contextual/sync_todos",
+            json!({
+                "project_dir": project_dir,
+                "branch": "main",
+                "todos": [{ "file_path": "lib.rs", "line_number": 1, "content": "TODO: a" }],
+            }),
+        )
+        .await;
+        let page = call("contextual/todo/list", json!({ "branch": "trunk" })).await;
+        let todo = &page["todos"][0];
+        assert_eq!(todo["git"]["branch"], "trunk");
+        assert_eq!(todo["git"]["dirty"], false);
+
+        git(
+            dir.path(),
+            &["commit", "--quiet", "--allow-empty", "-m", "second"],
+        );
+        let note = call(
+            "contextual/note/create",
+            json!({
+                "context": { "filename": "lib.rs", "project_dir": project_dir, "selection": "a" },
+                "content": "added after the first commit",
+            }),
+        )
+        .await;
+        assert_ne!(note["git"]["commit"], todo["git"]["commit"]);
 
-        Ok(json!({"id": todo_id}))
+        let listed = call("contextual/note/list", json!({ "as_of": "HEAD" })).await;
+        assert_eq!(listed["notes"].as_array().unwrap().len(), 1);
+        let listed = call("contextual/note/list", json!({ "as_of": "first" })).await;
+        assert!(listed["notes"].as_array().unwrap().is_empty());
     }
 }
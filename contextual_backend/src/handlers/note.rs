@@ -0,0 +1,475 @@
+use std::collections::{BTreeMap, HashMap};
+
+use futures::future::BoxFuture;
+
+use crate::{
+    anchor,
+    database::NoteStorage,
+    git::Repository,
+    handlers::{parse_params, to_response},
+    jsonrpc::{JsonRpcRequest, ResponseError},
+    service::Service,
+    types::{
+        AnchorStatus, DeletedNote, ListNotesParams, NewNote, Note, NoteIdParams, NoteList,
+        NotePatch, ReanchorParams, ReanchorReport, ReanchoredNote, UpdateNoteParams,
+    },
+};
+
+/// `contextual/note/create`: store a new note and respond with the saved
+/// [crate::types::Note].
+#[derive(Debug, Clone)]
+pub struct CreateNoteService<S> {
+    storage: S,
+}
+
+impl<S> CreateNoteService<S> {
+    pub fn new(storage: S) -> Self {
+        Self { storage }
+    }
+}
+
+impl<S> Service<JsonRpcRequest> for CreateNoteService<S>
+where
+    S: NoteStorage + Clone + Send + 'static,
+{
+    type Response = serde_json::Value;
+    type Error = ResponseError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&mut self, req: JsonRpcRequest) -> Self::Future {
+        let storage = self.storage.clone();
+
+        Box::pin(async move {
+            let mut new_note: NewNote = parse_params(req.params)?;
+            if let Some(repo) = Repository::open(&new_note.context.project_dir).await {
+                repo.stamp_note(&mut new_note);
+            }
+            let note_id = storage.save_note(new_note).await?;
+            let note = storage.get_note(note_id).await?;
+
+            to_response(note)
+        })
+    }
+}
+
+/// `contextual/note/get`: respond with a single note by its id.
+#[derive(Debug, Clone)]
+pub struct GetNoteService<S> {
+    storage: S,
+}
+
+impl<S> GetNoteService<S> {
+    pub fn new(storage: S) -> Self {
+        Self { storage }
+    }
+}
+
+impl<S> Service<JsonRpcRequest> for GetNoteService<S>
+where
+    S: NoteStorage + Clone + Send + 'static,
+{
+    type Response = serde_json::Value;
+    type Error = ResponseError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&mut self, req: JsonRpcRequest) -> Self::Future {
+        let storage = self.storage.clone();
+
+        Box::pin(async move {
+            let params: NoteIdParams = parse_params(req.params)?;
+            let note = storage.get_note(params.id).await?;
+
+            to_response(note)
+        })
+    }
+}
+
+/// `contextual/note/list`: respond with all notes, optionally narrowed down to
+/// a project and/or file.
+#[derive(Debug, Clone)]
+pub struct ListNotesService<S> {
+    storage: S,
+}
+
+impl<S> ListNotesService<S> {
+    pub fn new(storage: S) -> Self {
+        Self { storage }
+    }
+}
+
+impl<S> Service<JsonRpcRequest> for ListNotesService<S>
+where
+    S: NoteStorage + Clone + Send + 'static,
+{
+    type Response = serde_json::Value;
+    type Error = ResponseError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&mut self, req: JsonRpcRequest) -> Self::Future {
+        let storage = self.storage.clone();
+
+        Box::pin(async move {
+            let params: ListNotesParams = parse_params(req.params)?;
+            let mut notes = storage
+                .get_notes()
+                .await?
+                .into_iter()
+                .filter(|note| params.matches(note))
+                .collect();
+            if let Some(rev) = &params.as_of {
+                notes = notes_as_of(notes, rev, params.project_dir.is_some()).await?;
+            }
+
+            to_response(NoteList { notes })
+        })
+    }
+}
+
+/// The notes which already existed at commit `rev` of their project, notes
+/// without a recorded commit are left out. `rev` has to exist in the project
+/// when listing a single one.
+async fn notes_as_of(
+    notes: Vec<Note>,
+    rev: &str,
+    single_project: bool,
+) -> Result<Vec<Note>, ResponseError> {
+    let mut by_project: BTreeMap<String, Vec<Note>> = BTreeMap::new();
+    for note in notes {
+        by_project
+            .entry(note.context.project_dir.clone())
+            .or_default()
+            .push(note);
+    }
+
+    let mut kept = Vec::new();
+    for (project_dir, notes) in by_project {
+        let Some(repo) = Repository::open(&project_dir).await else {
+            continue;
+        };
+        let Some(commit) = repo.resolve(rev).await else {
+            if single_project {
+                return Err(ResponseError::new(
+                    ResponseError::INVALID_PARAMS,
+                    format!("unknown commit: {rev}"),
+                ));
+            }
+            continue;
+        };
+
+        let mut existed: HashMap<String, bool> = HashMap::new();
+        for note in notes {
+            let Some(created_at) = note.git.as_ref().and_then(|git| git.commit.clone()) else {
+                continue;
+            };
+            let is_ancestor = match existed.get(&created_at) {
+                Some(is_ancestor) => *is_ancestor,
+                None => {
+                    let is_ancestor = repo.is_ancestor(&created_at, &commit).await;
+                    existed.insert(created_at, is_ancestor);
+                    is_ancestor
+                }
+            };
+            if is_ancestor {
+                kept.push(note);
+            }
+        }
+    }
+
+    Ok(kept)
+}
+
+/// `contextual/note/update`: patch the content and/or context of a note and
+/// respond with the updated note.
+#[derive(Debug, Clone)]
+pub struct UpdateNoteService<S> {
+    storage: S,
+}
+
+impl<S> UpdateNoteService<S> {
+    pub fn new(storage: S) -> Self {
+        Self { storage }
+    }
+}
+
+impl<S> Service<JsonRpcRequest> for UpdateNoteService<S>
+where
+    S: NoteStorage + Clone + Send + 'static,
+{
+    type Response = serde_json::Value;
+    type Error = ResponseError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&mut self, req: JsonRpcRequest) -> Self::Future {
+        let storage = self.storage.clone();
+
+        Box::pin(async move {
+            let params: UpdateNoteParams = parse_params(req.params)?;
+            let note = storage.update_note(params.id, params.patch).await?;
+
+            to_response(note)
+        })
+    }
+}
+
+/// `contextual/note/delete`: remove a note.
+#[derive(Debug, Clone)]
+pub struct DeleteNoteService<S> {
+    storage: S,
+}
+
+impl<S> DeleteNoteService<S> {
+    pub fn new(storage: S) -> Self {
+        Self { storage }
+    }
+}
+
+impl<S> Service<JsonRpcRequest> for DeleteNoteService<S>
+where
+    S: NoteStorage + Clone + Send + 'static,
+{
+    type Response = serde_json::Value;
+    type Error = ResponseError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&mut self, req: JsonRpcRequest) -> Self::Future {
+        let storage = self.storage.clone();
+
+        Box::pin(async move {
+            let params: NoteIdParams = parse_params(req.params)?;
+            storage.delete_note(params.id).await?;
+
+            to_response(DeletedNote { id: params.id })
+        })
+    }
+}
+
+/// `contextual/note/reanchor`: locate the notes of a file in its current
+/// contents, store their new positions and mark the ones that can't be found
+/// as orphaned.
+#[derive(Debug, Clone)]
+pub struct ReanchorNotesService<S> {
+    storage: S,
+}
+
+impl<S> ReanchorNotesService<S> {
+    pub fn new(storage: S) -> Self {
+        Self { storage }
+    }
+}
+
+impl<S> Service<JsonRpcRequest> for ReanchorNotesService<S>
+where
+    S: NoteStorage + Clone + Send + 'static,
+{
+    type Response = serde_json::Value;
+    type Error = ResponseError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&mut self, req: JsonRpcRequest) -> Self::Future {
+        let storage = self.storage.clone();
+
+        Box::pin(async move {
+            let params: ReanchorParams = parse_params(req.params)?;
+            let filter = ListNotesParams {
+                project_dir: Some(params.project_dir),
+                filename: Some(params.filename),
+                tag: None,
+                as_of: None,
+            };
+
+            let mut notes = Vec::new();
+            for note in storage.get_notes().await? {
+                if !filter.matches(&note) {
+                    continue;
+                }
+
+                let (patch, status, confidence) =
+                    match anchor::locate(&note.context, &params.contents) {
+                        Some(located) => (
+                            NotePatch {
+                                context: Some(anchor::anchor(
+                                    &note.context,
+                                    &params.contents,
+                                    &located,
+                                )),
+                                orphaned: Some(false),
+                                ..Default::default()
+                            },
+                            located.status,
+                            located.confidence,
+                        ),
+                        None => (
+                            NotePatch {
+                                orphaned: Some(true),
+                                ..Default::default()
+                            },
+                            AnchorStatus::Orphaned,
+                            0.0,
+                        ),
+                    };
+                let note = storage.update_note(note.id, patch).await?;
+                notes.push(ReanchoredNote {
+                    id: note.id,
+                    status,
+                    confidence,
+                    start_line: note.context.start_line,
+                    end_line: note.context.end_line,
+                    start_column: note.context.start_column,
+                    end_column: note.context.end_column,
+                    position_encoding: note.context.position_encoding,
+                });
+            }
+
+            to_response(ReanchorReport { notes })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use crate::{
+        database::memory::MemoryDatabase,
+        handlers::note::{
+            CreateNoteService, DeleteNoteService, GetNoteService, ListNotesService,
+            ReanchorNotesService, UpdateNoteService,
+        },
+        jsonrpc::{JsonRpcRequest, ResponseError},
+        service::Service,
+    };
+
+    fn request(method: &str, params: Value) -> JsonRpcRequest {
+        JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id: 1,
+            method: method.to_string(),
+            params,
+        }
+    }
+
+    #[tokio::test]
+    async fn note_lifecycle_over_services() {
+        let storage = MemoryDatabase::new();
+
+        let created = CreateNoteService::new(storage.clone())
+            .call(request(
+                "contextual/note/create",
+                json!({
+                    "context": {
+                        "filename": "src/main.rs",
+                        "project_dir": "/projects/contextual",
+                        "selection": "fn main() {",
+                    },
+                    "content": "entry point",
+                }),
+            ))
+            .await
+            .unwrap();
+        let id = created["id"].clone();
+        assert_eq!(created["context"]["selection"], "fn main() {");
+
+        let updated = UpdateNoteService::new(storage.clone())
+            .call(request(
+                "contextual/note/update",
+                json!({ "id": id, "content": "the entry point" }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(updated["content"], "the entry point");
+
+        let listed = ListNotesService::new(storage.clone())
+            .call(request(
+                "contextual/note/list",
+                json!({ "project_dir": "/projects/contextual" }),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(listed["notes"].as_array().unwrap().len(), 1);
+
+        DeleteNoteService::new(storage.clone())
+            .call(request("contextual/note/delete", json!({ "id": id })))
+            .await
+            .unwrap();
+
+        let err = GetNoteService::new(storage)
+            .call(request("contextual/note/get", json!({ "id": id })))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, ResponseError::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn notes_are_reanchored_or_orphaned() {
+        let storage = MemoryDatabase::new();
+        for selection in ["fn main() {", "fn removed() {"] {
+            CreateNoteService::new(storage.clone())
+                .call(request(
+                    "contextual/note/create",
+                    json!({
+                        "context": {
+                            "filename": "src/main.rs",
+                            "project_dir": "/projects/contextual",
+                            "selection": selection,
+                        },
+                        "content": "a note",
+                    }),
+                ))
+                .await
+                .unwrap();
+        }
+
+        let report = ReanchorNotesService::new(storage.clone())
+            .call(request(
+                "contextual/note/reanchor",
+                json!({
+                    "project_dir": "/projects/contextual",
+                    "filename": "src/main.rs",
+                    "contents": "mod cli;\n\nfn main() {\n    cli::run();\n}\n",
+                }),
+            ))
+            .await
+            .unwrap();
+        let mut statuses: Vec<_> = report["notes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|note| (note["status"].clone(), note["start_line"].clone()))
+            .collect();
+        statuses.sort_by_key(|(status, _)| status.to_string());
+        assert_eq!(
+            statuses,
+            vec![(json!("exact"), json!(2)), (json!("orphaned"), Value::Null)]
+        );
+
+        let listed = ListNotesService::new(storage)
+            .call(request("contextual/note/list", json!({})))
+            .await
+            .unwrap();
+        for note in listed["notes"].as_array().unwrap() {
+            let found = note["context"]["selection"] == "fn main() {";
+            assert_eq!(note["orphaned"], !found);
+            if found {
+                assert_eq!(
+                    note["context"]["context_after"],
+                    json!(["    cli::run();", "}"])
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn malformed_params_are_rejected() {
+        let storage = MemoryDatabase::new();
+
+        let err = GetNoteService::new(storage)
+            .call(request(
+                "contextual/note/get",
+                json!({ "id": "not-a-uuid" }),
+            ))
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.code, ResponseError::INVALID_PARAMS);
+    }
+}
@@ -0,0 +1,42 @@
+use futures::future::BoxFuture;
+
+use crate::{
+    database::SearchStorage,
+    handlers::{parse_params, to_response},
+    jsonrpc::{JsonRpcRequest, ResponseError},
+    service::Service,
+    types::search::{SearchQuery, SearchResults},
+};
+
+/// `contextual/search`: ranked full-text search over notes and active todos,
+/// see [SearchQuery] for the query syntax.
+#[derive(Debug, Clone)]
+pub struct SearchService<S> {
+    storage: S,
+}
+
+impl<S> SearchService<S> {
+    pub fn new(storage: S) -> Self {
+        Self { storage }
+    }
+}
+
+impl<S> Service<JsonRpcRequest> for SearchService<S>
+where
+    S: SearchStorage + Clone + Send + 'static,
+{
+    type Response = serde_json::Value;
+    type Error = ResponseError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&mut self, req: JsonRpcRequest) -> Self::Future {
+        let storage = self.storage.clone();
+
+        Box::pin(async move {
+            let query: SearchQuery = parse_params(req.params)?;
+            let hits = storage.search(&query).await?;
+
+            to_response(SearchResults { hits })
+        })
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::BTreeMap;
+
+use futures::future::BoxFuture;
+
+use crate::{
+    database::Storage,
+    handlers::{parse_params, to_response},
+    jsonrpc::{JsonRpcRequest, ResponseError},
+    service::Service,
+    types::{
+        NotePatch,
+        search::DocKind,
+        tag::{ListTagsParams, TagCount, TagList, TagParams},
+        todo::TodoPatch,
+    },
+};
+
+/// `contextual/tags/add` and `contextual/tags/remove`: change the tags of a
+/// note or todo and respond with the updated record.
+#[derive(Debug, Clone)]
+pub struct UpdateTagsService<S> {
+    storage: S,
+    remove: bool,
+}
+
+impl<S> UpdateTagsService<S> {
+    pub fn add(storage: S) -> Self {
+        Self {
+            storage,
+            remove: false,
+        }
+    }
+
+    pub fn remove(storage: S) -> Self {
+        Self {
+            storage,
+            remove: true,
+        }
+    }
+}
+
+impl<S> Service<JsonRpcRequest> for UpdateTagsService<S>
+where
+    S: Storage + Clone + Send + 'static,
+{
+    type Response = serde_json::Value;
+    type Error = ResponseError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&mut self, req: JsonRpcRequest) -> Self::Future {
+        let storage = self.storage.clone();
+        let remove = self.remove;
+
+        Box::pin(async move {
+            let params: TagParams = parse_params(req.params)?;
+            let (add_tags, remove_tags) = match remove {
+                false => (params.tags, Default::default()),
+                true => (Default::default(), params.tags),
+            };
+
+            match params.kind {
+                DocKind::Note => {
+                    let patch = NotePatch {
+                        add_tags,
+                        remove_tags,
+                        ..Default::default()
+                    };
+                    to_response(storage.update_note(params.id, patch).await?)
+                }
+                DocKind::Todo => {
+                    let patch = TodoPatch {
+                        add_tags,
+                        remove_tags,
+                        ..Default::default()
+                    };
+                    to_response(storage.update_todo(params.id, patch).await?)
+                }
+            }
+        })
+    }
+}
+
+/// `contextual/tags/list`: respond with every tag in use and how many notes
+/// and active todos carry it.
+#[derive(Debug, Clone)]
+pub struct ListTagsService<S> {
+    storage: S,
+}
+
+impl<S> ListTagsService<S> {
+    pub fn new(storage: S) -> Self {
+        Self { storage }
+    }
+}
+
+impl<S> Service<JsonRpcRequest> for ListTagsService<S>
+where
+    S: Storage + Clone + Send + 'static,
+{
+    type Response = serde_json::Value;
+    type Error = ResponseError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&mut self, req: JsonRpcRequest) -> Self::Future {
+        let storage = self.storage.clone();
+
+        Box::pin(async move {
+            let params: ListTagsParams = parse_params(req.params)?;
+            let in_project = |dir: &String| {
+                params
+                    .project_dir
+                    .as_ref()
+                    .is_none_or(|project| project == dir)
+            };
+
+            let mut counts: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+            for note in storage.get_notes().await? {
+                if in_project(&note.context.project_dir) {
+                    for tag in note.tags {
+                        counts.entry(tag).or_default().0 += 1;
+                    }
+                }
+            }
+            for todo in storage.get_todos().await? {
+                if todo.deleted_at.is_none() && in_project(&todo.project_dir) {
+                    for tag in todo.tags {
+                        counts.entry(tag).or_default().1 += 1;
+                    }
+                }
+            }
+
+            let tags = counts
+                .into_iter()
+                .map(|(tag, (notes, todos))| TagCount { tag, notes, todos })
+                .collect();
+
+            to_response(TagList { tags })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use crate::{
+        config::Config,
+        database::{TodoStorage, memory::MemoryDatabase},
+        fixtures,
+        handlers::routes,
+        jsonrpc::JsonRpcRequest,
+        service::Service,
+    };
+
+    fn request(method: &str, params: Value) -> JsonRpcRequest {
+        JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id: 1,
+            method: method.to_string(),
+            params,
+        }
+    }
+
+    #[tokio::test]
+    async fn tags_are_added_counted_and_filtered() {
+        let storage = MemoryDatabase::new();
+        let mut router = routes(storage.clone(), &Config::default()).service();
+        let todo_id = storage
+            .save_todo(fixtures::new_todo(
+                "src/lib.rs",
+                3,
+                "TODO: cache lookups #perf",
+            ))
+            .await
+            .unwrap();
+        let mut call = async |method: &str, params: Value| {
+            router
+                .call(request(method, params))
+                .await
+                .unwrap()
+                .result
+                .unwrap()
+        };
+
+        let note = call(
+            "contextual/note/create",
+            json!({
+                "context": {
+                    "filename": "src/lib.rs",
+                    "project_dir": "/project",
+                    "selection": "fn lookup()",
+                },
+                "content": "slow lookup",
+                "tags": ["#Perf"],
+            }),
+        )
+        .await;
+        assert_eq!(note["tags"], json!(["perf"]));
+
+        let todo = call(
+            "contextual/tags/add",
+            json!({ "kind": "todo", "id": todo_id, "tags": ["cache"] }),
+        )
+        .await;
+        assert_eq!(todo["tags"], json!(["cache", "perf"]));
+
+        let tags = call("contextual/tags/list", json!({})).await;
+        assert_eq!(
+            tags["tags"],
+            json!([
+                { "tag": "cache", "notes": 0, "todos": 1 },
+                { "tag": "perf", "notes": 1, "todos": 1 },
+            ])
+        );
+
+        call(
+            "contextual/tags/remove",
+            json!({ "kind": "note", "id": note["id"], "tags": ["perf"] }),
+        )
+        .await;
+        let notes = call("contextual/note/list", json!({ "tag": "perf" })).await;
+        assert_eq!(notes["notes"], json!([]));
+        let todos = call("contextual/todo/list", json!({ "tag": "perf" })).await;
+        assert_eq!(todos["todos"].as_array().unwrap().len(), 1);
+        let hits = call(
+            "contextual/search",
+            json!({ "query": "lookup*", "tag": "cache" }),
+        )
+        .await;
+        assert_eq!(hits["hits"].as_array().unwrap().len(), 1);
+        assert_eq!(hits["hits"][0]["kind"], "todo");
+    }
+}
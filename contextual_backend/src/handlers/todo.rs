@@ -1,10 +1,12 @@
 use futures::future::BoxFuture;
 
 use crate::{
-    database::TodoStorage,
+    database::{Storage, StorageError, TodoStorage},
+    git::Repository,
+    handlers::{Handler, parse_params, to_response},
     jsonrpc::{JsonRpcRequest, ResponseError},
     service::Service,
-    types::todo::NewTodoItem,
+    types::todo::{NewTodoItem, ScanTodosParams, SyncFileTodosParams, SyncTodosParams, TodoQuery},
 };
 
 #[derive(Debug, Clone)]
@@ -30,24 +32,151 @@ where
         let storage = self.storage.clone();
 
         Box::pin(async move {
-            let params = req.params.clone();
-            let new_todo = match NewTodoItem::try_from(params) {
-                Ok(t) => t,
-                Err(e) => {
-                    return Err(ResponseError {
-                        code: -32000, // TODO: use right error code
-                        message: e.to_string(),
-                    });
-                }
-            };
-
-            match storage.save_todo(new_todo).await {
-                Ok(id) => Ok(serde_json::Value::String(id.to_string())),
-                Err(e) => Err(ResponseError {
-                    code: -32000,
-                    message: e.to_string(),
-                }),
+            let mut new_todo: NewTodoItem = parse_params(req.params)?;
+            if let Some(repo) = Repository::open(&new_todo.project_dir).await {
+                repo.stamp_todo(&mut new_todo);
             }
+            if new_todo.branch.is_empty() {
+                return Err(StorageError::branch_required().into());
+            }
+
+            let id = storage.save_todo(new_todo).await?;
+
+            Ok(serde_json::Value::String(id.to_string()))
+        })
+    }
+}
+
+/// `contextual/sync_todos`: reconcile a full project scan with the stored
+/// todos, see [Handler::sync_todos].
+#[derive(Clone)]
+pub struct SyncTodosService<S> {
+    handler: Handler<S>,
+}
+
+impl<S: Storage> SyncTodosService<S> {
+    pub fn new(storage: S) -> Self {
+        Self {
+            handler: Handler::new(storage),
+        }
+    }
+}
+
+impl<S> Service<JsonRpcRequest> for SyncTodosService<S>
+where
+    S: Storage + Clone + Send + 'static,
+{
+    type Response = serde_json::Value;
+    type Error = ResponseError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&mut self, req: JsonRpcRequest) -> Self::Future {
+        let handler = self.handler.clone();
+
+        Box::pin(async move {
+            let params: SyncTodosParams = parse_params(req.params)?;
+            let report = handler.sync_todos(params).await?;
+
+            to_response(report)
+        })
+    }
+}
+
+/// `contextual/sync_file_todos`: reconcile the todos of a single file, see
+/// [Handler::sync_file_todos].
+#[derive(Clone)]
+pub struct SyncFileTodosService<S> {
+    handler: Handler<S>,
+}
+
+impl<S: Storage> SyncFileTodosService<S> {
+    pub fn new(handler: Handler<S>) -> Self {
+        Self { handler }
+    }
+}
+
+impl<S> Service<JsonRpcRequest> for SyncFileTodosService<S>
+where
+    S: Storage + Clone + Send + 'static,
+{
+    type Response = serde_json::Value;
+    type Error = ResponseError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&mut self, req: JsonRpcRequest) -> Self::Future {
+        let handler = self.handler.clone();
+
+        Box::pin(async move {
+            let params: SyncFileTodosParams = parse_params(req.params)?;
+            let report = handler.sync_file_todos(params).await?;
+
+            to_response(report)
+        })
+    }
+}
+
+/// `contextual/scan_todos`: scan a project for todos on the backend, see
+/// [Handler::scan_todos].
+#[derive(Clone)]
+pub struct ScanTodosService<S> {
+    handler: Handler<S>,
+}
+
+impl<S: Storage> ScanTodosService<S> {
+    pub fn new(handler: Handler<S>) -> Self {
+        Self { handler }
+    }
+}
+
+impl<S> Service<JsonRpcRequest> for ScanTodosService<S>
+where
+    S: Storage + Clone + Send + 'static,
+{
+    type Response = serde_json::Value;
+    type Error = ResponseError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&mut self, req: JsonRpcRequest) -> Self::Future {
+        let handler = self.handler.clone();
+
+        Box::pin(async move {
+            let params: ScanTodosParams = parse_params(req.params)?;
+            let scanned = handler.scan_todos(params).await?;
+
+            to_response(scanned)
+        })
+    }
+}
+
+/// `contextual/todo/list`: respond with a filtered page of todos, see
+/// [TodoQuery].
+#[derive(Debug, Clone)]
+pub struct ListTodosService<S> {
+    storage: S,
+}
+
+impl<S> ListTodosService<S> {
+    pub fn new(storage: S) -> Self {
+        Self { storage }
+    }
+}
+
+impl<S> Service<JsonRpcRequest> for ListTodosService<S>
+where
+    S: TodoStorage + Clone + Send + 'static,
+{
+    type Response = serde_json::Value;
+    type Error = ResponseError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&mut self, req: JsonRpcRequest) -> Self::Future {
+        let storage = self.storage.clone();
+
+        Box::pin(async move {
+            let query: TodoQuery = parse_params(req.params)?;
+            let page = storage.query_todos(&query).await?;
+
+            to_response(page)
         })
     }
 }
@@ -0,0 +1,76 @@
+use futures::future::BoxFuture;
+use serde_json::json;
+
+use crate::{
+    database::Storage,
+    handlers::{parse_params, to_response},
+    jsonrpc::{JsonRpcRequest, ResponseError},
+    service::Service,
+    types::todo::WatchParams,
+    watch::Watcher,
+};
+
+/// `contextual/watch`: sync a project's todos and keep them synced while its
+/// files change, see [Watcher::watch].
+#[derive(Clone)]
+pub struct WatchService<S> {
+    watcher: Watcher<S>,
+}
+
+impl<S> WatchService<S> {
+    pub fn new(watcher: Watcher<S>) -> Self {
+        Self { watcher }
+    }
+}
+
+impl<S> Service<JsonRpcRequest> for WatchService<S>
+where
+    S: Storage + Clone + Send + Sync + 'static,
+{
+    type Response = serde_json::Value;
+    type Error = ResponseError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&mut self, req: JsonRpcRequest) -> Self::Future {
+        let watcher = self.watcher.clone();
+
+        Box::pin(async move {
+            let params: WatchParams = parse_params(req.params)?;
+            let report = watcher.watch(params.project_dir, params.branch).await?;
+
+            to_response(report)
+        })
+    }
+}
+
+/// `contextual/unwatch`: stop watching a project, see [Watcher::unwatch].
+#[derive(Clone)]
+pub struct UnwatchService<S> {
+    watcher: Watcher<S>,
+}
+
+impl<S> UnwatchService<S> {
+    pub fn new(watcher: Watcher<S>) -> Self {
+        Self { watcher }
+    }
+}
+
+impl<S> Service<JsonRpcRequest> for UnwatchService<S>
+where
+    S: Storage + Clone + Send + Sync + 'static,
+{
+    type Response = serde_json::Value;
+    type Error = ResponseError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&mut self, req: JsonRpcRequest) -> Self::Future {
+        let watcher = self.watcher.clone();
+
+        Box::pin(async move {
+            let params: WatchParams = parse_params(req.params)?;
+            let watched = watcher.unwatch(&params.project_dir);
+
+            Ok(json!({ "watched": watched }))
+        })
+    }
+}
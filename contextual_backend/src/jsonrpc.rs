@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use tokio::sync::broadcast;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct JsonRpcRequest {
@@ -38,8 +39,116 @@ impl JsonRpcResponse {
     }
 }
 
+/// A message the server sends on its own: it has no id and expects no
+/// answer.
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonRpcNotification {
+    pub jsonrpc: String,
+    pub method: String,
+    pub params: Value,
+}
+
+impl JsonRpcNotification {
+    pub fn new(method: impl Into<String>, params: Value) -> Self {
+        Self {
+            jsonrpc: "2.0".into(),
+            method: method.into(),
+            params,
+        }
+    }
+}
+
+/// Everything the server writes to a client.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum JsonRpcMessage {
+    Response(JsonRpcResponse),
+    Notification(JsonRpcNotification),
+}
+
+impl From<JsonRpcResponse> for JsonRpcMessage {
+    fn from(response: JsonRpcResponse) -> Self {
+        Self::Response(response)
+    }
+}
+
+impl From<JsonRpcNotification> for JsonRpcMessage {
+    fn from(notification: JsonRpcNotification) -> Self {
+        Self::Notification(notification)
+    }
+}
+
+/// Broadcasts notifications to every connected client. Clients which
+/// connect later don't see earlier notifications.
+#[derive(Debug, Clone)]
+pub struct Notifier {
+    sender: broadcast::Sender<JsonRpcNotification>,
+}
+
+impl Notifier {
+    /// How many notifications a slow client may fall behind before it misses
+    /// some.
+    const CAPACITY: usize = 64;
+
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(Self::CAPACITY);
+        Self { sender }
+    }
+
+    pub fn notify(&self, method: &str, params: Value) {
+        // nobody listening is fine
+        let _ = self.sender.send(JsonRpcNotification::new(method, params));
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<JsonRpcNotification> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for Notifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct ResponseError {
     pub code: i32,
     pub message: String,
+    /// Details of the error a client can act on, like which params are
+    /// invalid.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl ResponseError {
+    pub const PARSE_ERROR: i32 = -32700;
+    pub const INVALID_PARAMS: i32 = -32602;
+    pub const METHOD_NOT_FOUND: i32 = -32601;
+    pub const INTERNAL_ERROR: i32 = -32603;
+    /// Generic server error for failures without a more specific code.
+    pub const SERVER_ERROR: i32 = -32000;
+    /// The record referenced by the request does not exist.
+    pub const NOT_FOUND: i32 = -32001;
+    /// The write clashes with an existing record.
+    pub const CONFLICT: i32 = -32002;
+    /// A stored record could not be read.
+    pub const CORRUPT: i32 = -32003;
+
+    pub fn new(code: i32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    pub fn invalid_params(message: impl Into<String>) -> Self {
+        Self::new(Self::INVALID_PARAMS, message)
+    }
+
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.data = Some(data);
+        self
+    }
 }
@@ -1,8 +1,19 @@
+pub mod anchor;
 pub mod args;
+pub mod config;
 pub mod database;
+#[cfg(test)]
+pub mod fixtures;
+pub mod git;
 pub mod handlers;
 pub mod jsonrpc;
+pub mod logging;
+pub mod migrate;
 pub mod router;
+pub mod scan;
+pub mod search;
 pub mod service;
+pub mod sync;
 pub mod transport;
 pub mod types;
+pub mod watch;
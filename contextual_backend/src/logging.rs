@@ -0,0 +1,28 @@
+//! Reporting of problems which don't fail a request, such as records a read
+//! had to skip. They are logged through [log] and written to stderr, which
+//! keeps them out of the stdio transport's messages.
+
+/// Writes every enabled record to stderr.
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{}: {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Log records from info on to stderr. Only the first call has an effect.
+pub fn init() {
+    static LOGGER: StderrLogger = StderrLogger;
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(log::LevelFilter::Info);
+    }
+}
@@ -1,26 +1,125 @@
+use anyhow::Context;
 use contextual_backend::{
-    args::{Args, TransportType},
-    database::file::FileDatabase,
-    handlers::{echo::EchoService, todo::NewTodoService},
-    router::RouterFactory,
+    args::{Args, Command, StorageBackend, TransportType},
+    config::Config,
+    database::{
+        Storage, default_data_dir, file::FileDatabase, memory::MemoryDatabase,
+        sqlite::SqliteDatabase,
+    },
+    handlers::{self, Handler},
+    logging, migrate,
+    scan::ScanConfig,
     transport::{
         Server, codec::JsonRpcCodec, stdio::StdIoTransport, tcp::TcpTransport,
         unix_socket::UnixTransport,
     },
+    types::todo::ScanTodosParams,
 };
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
+    logging::init();
     let args = Args::parse_and_validate();
-    let storage = FileDatabase::init().await;
 
-    let router = RouterFactory::new()
-        .with_route("contextual/echo", EchoService)
-        .with_route("contextual/new_todo", NewTodoService::new(storage.clone()));
+    if let Some(Command::Migrate { from, to, dry_run }) = args.command {
+        if from == to {
+            anyhow::bail!("--from and --to are the same store");
+        }
+        // a dry run against a store which doesn't exist yet must not create it
+        let target: Box<dyn Storage> = if dry_run && !to.exists() {
+            Box::new(MemoryDatabase::new())
+        } else {
+            to.open().await?
+        };
+        let source = from.open_source().await?;
+        let report = migrate::migrate_source(&source, target.as_ref(), dry_run).await?;
+        println!("{report}");
+        if !report.is_success() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let config = Config::load(args.config.as_deref())?;
+
+    match args.storage {
+        StorageBackend::File => {
+            let data_dir = args.data_dir.map_or_else(default_data_dir, Ok)?;
+            let storage = FileDatabase::init(data_dir)
+                .await?
+                .rewrite_upgraded(args.rewrite_upgraded);
+            run(args.command, args.transport, &config, storage).await
+        }
+        StorageBackend::Sqlite => {
+            let data_dir = args.data_dir.map_or_else(default_data_dir, Ok)?;
+            tokio::fs::create_dir_all(&data_dir).await?;
+            let storage = SqliteDatabase::init(data_dir.join("contextual.db")).await?;
+            run(args.command, args.transport, &config, storage).await
+        }
+        StorageBackend::Memory => {
+            run(args.command, args.transport, &config, MemoryDatabase::new()).await
+        }
+    }
+}
+
+/// Run a subcommand against the configured storage, or serve it.
+async fn run<S>(
+    command: Option<Command>,
+    transport: TransportType,
+    config: &Config,
+    storage: S,
+) -> Result<(), anyhow::Error>
+where
+    S: Storage + Clone + 'static,
+{
+    match command {
+        Some(Command::ScanTodos {
+            project_dir,
+            branch,
+            keywords,
+            placeholders,
+            sync,
+        }) => {
+            let overrides = ScanConfig {
+                keywords: (!keywords.is_empty()).then_some(keywords),
+                placeholders: placeholders.then_some(true),
+                ..Default::default()
+            };
+            overrides.validate()?;
+            let params = ScanTodosParams {
+                project_dir: std::fs::canonicalize(&project_dir)
+                    .with_context(|| format!("cannot scan {}", project_dir.display()))?
+                    .to_string_lossy()
+                    .into_owned(),
+                branch: branch.unwrap_or_default(),
+                config: overrides,
+                sync,
+            };
+            let scanned = Handler::new(storage)
+                .with_scan_options(config.scan_options())
+                .scan_todos(params)
+                .await?;
+            println!("{}", serde_json::to_string_pretty(&scanned)?);
+            Ok(())
+        }
+        // handled before any storage is opened
+        Some(Command::Migrate { .. }) => unreachable!(),
+        None => serve(transport, config, storage).await,
+    }
+}
 
+async fn serve<S>(
+    transport: TransportType,
+    config: &Config,
+    storage: S,
+) -> Result<(), anyhow::Error>
+where
+    S: Storage + Clone + 'static,
+{
+    let router = handlers::routes(storage, config);
     let codec = JsonRpcCodec;
 
-    match args.transport {
+    match transport {
         TransportType::Unix { socket_path } => {
             Server::new(UnixTransport::new(socket_path), codec)
                 .start(router)
@@ -0,0 +1,507 @@
+use std::{collections::HashMap, path::PathBuf, str::FromStr};
+
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    database::{
+        Storage, StorageError,
+        file::{FileDatabase, UnreadableRecord},
+        sqlite::SqliteDatabase,
+    },
+    types::search::DocKind,
+};
+
+/// A store to migrate from or to, written as `file:<dir>` or
+/// `sqlite:<path>` on the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StorageLocation {
+    File(PathBuf),
+    Sqlite(PathBuf),
+}
+
+impl StorageLocation {
+    pub fn exists(&self) -> bool {
+        match self {
+            StorageLocation::File(path) | StorageLocation::Sqlite(path) => path.exists(),
+        }
+    }
+
+    /// Open the store, creating it if it doesn't exist yet.
+    pub async fn open(&self) -> Result<Box<dyn Storage>, anyhow::Error> {
+        match self {
+            StorageLocation::File(dir) => Ok(Box::new(FileDatabase::init(dir).await?)),
+            StorageLocation::Sqlite(path) => {
+                if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                    tokio::fs::create_dir_all(dir).await?;
+                }
+                Ok(Box::new(SqliteDatabase::init(path).await?))
+            }
+        }
+    }
+
+    /// Open the existing store to migrate from, along with the records it
+    /// can't read. The store is only read: unreadable records stay where they
+    /// are and a sqlite store isn't migrated.
+    pub async fn open_source(&self) -> Result<Source, anyhow::Error> {
+        if !self.exists() {
+            anyhow::bail!("{self} does not exist");
+        }
+
+        match self {
+            StorageLocation::File(dir) => {
+                let storage = FileDatabase::open_read_only(dir).await?;
+                let unreadable = storage.unreadable_records().await?;
+                Ok(Source {
+                    storage: Box::new(storage),
+                    unreadable,
+                })
+            }
+            StorageLocation::Sqlite(path) => Ok(Source {
+                storage: Box::new(SqliteDatabase::open_read_only(path).await?),
+                unreadable: Vec::new(),
+            }),
+        }
+    }
+}
+
+/// A store to migrate from, see [StorageLocation::open_source].
+pub struct Source {
+    pub storage: Box<dyn Storage>,
+    /// Records the store skips when listing, which can't be migrated.
+    pub unreadable: Vec<UnreadableRecord>,
+}
+
+impl FromStr for StorageLocation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (backend, path) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected <backend>:<path>, got '{s}'"))?;
+        if path.is_empty() {
+            return Err(format!("missing path for '{backend}'"));
+        }
+
+        match backend {
+            "file" => Ok(StorageLocation::File(path.into())),
+            "sqlite" => Ok(StorageLocation::Sqlite(path.into())),
+            _ => Err(format!(
+                "unknown backend '{backend}', expected file or sqlite"
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for StorageLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageLocation::File(dir) => write!(f, "file:{}", dir.display()),
+            StorageLocation::Sqlite(path) => write!(f, "sqlite:{}", path.display()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordKind {
+    Note,
+    Todo,
+}
+
+/// What happened to a single record.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "outcome", content = "reason")]
+pub enum Outcome {
+    /// Copied and read back unchanged from the target.
+    Copied,
+    /// Dry run: the record would be copied.
+    WouldCopy,
+    /// The target already holds an identical record.
+    Unchanged,
+    /// The target holds a different record with the same id, it is left
+    /// alone.
+    Conflict(String),
+    /// Writing or verifying the record failed.
+    Failed(String),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordReport {
+    pub kind: RecordKind,
+    pub id: Uuid,
+    #[serde(flatten)]
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct MigrationReport {
+    pub dry_run: bool,
+    pub records: Vec<RecordReport>,
+}
+
+impl MigrationReport {
+    /// Whether every record made it (or would make it) to the target.
+    pub fn is_success(&self) -> bool {
+        self.records.iter().all(|record| {
+            matches!(
+                record.outcome,
+                Outcome::Copied | Outcome::WouldCopy | Outcome::Unchanged
+            )
+        })
+    }
+
+    fn push(&mut self, kind: RecordKind, id: Uuid, outcome: Outcome) {
+        self.records.push(RecordReport { kind, id, outcome });
+    }
+}
+
+impl std::fmt::Display for MigrationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut counts: Vec<(&str, usize)> = Vec::new();
+        for record in &self.records {
+            let kind = match record.kind {
+                RecordKind::Note => "note",
+                RecordKind::Todo => "todo",
+            };
+            let label = match &record.outcome {
+                Outcome::Copied => "copied",
+                Outcome::WouldCopy => "would copy",
+                Outcome::Unchanged => "unchanged",
+                Outcome::Conflict(_) => "conflict",
+                Outcome::Failed(_) => "failed",
+            };
+            match &record.outcome {
+                Outcome::Conflict(reason) | Outcome::Failed(reason) => {
+                    writeln!(f, "{kind} {}: {label}: {reason}", record.id)?
+                }
+                _ => writeln!(f, "{kind} {}: {label}", record.id)?,
+            }
+
+            match counts.iter_mut().find(|(l, _)| *l == label) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((label, 1)),
+            }
+        }
+
+        let summary: Vec<_> = counts
+            .iter()
+            .map(|(label, count)| format!("{count} {label}"))
+            .collect();
+        write!(
+            f,
+            "{} records{}{}",
+            self.records.len(),
+            if summary.is_empty() { "" } else { ": " },
+            summary.join(", ")
+        )?;
+        if self.dry_run {
+            write!(f, " (dry run, nothing was written)")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Migrate `from` to `to` like [migrate], reporting the records of `from`
+/// which can't be read as failed.
+pub async fn migrate_source(
+    from: &Source,
+    to: &dyn Storage,
+    dry_run: bool,
+) -> Result<MigrationReport, StorageError> {
+    let mut report = migrate(from.storage.as_ref(), to, dry_run).await?;
+    for record in &from.unreadable {
+        let kind = match record.kind {
+            DocKind::Note => RecordKind::Note,
+            DocKind::Todo => RecordKind::Todo,
+        };
+        let reason = format!("unreadable in the source: {}", record.reason);
+        report.push(kind, record.id, Outcome::Failed(reason));
+    }
+
+    Ok(report)
+}
+
+/// Copy every note and todo from `from` to `to`, keeping ids and timestamps.
+///
+/// Records are inserted as is, bypassing deduplication, and each one is read
+/// back from `to` and compared with the original. Records the target already
+/// holds are never overwritten, so running a migration again only copies
+/// what is missing. With `dry_run` nothing is written and the report lists
+/// what would happen.
+pub async fn migrate(
+    from: &dyn Storage,
+    to: &dyn Storage,
+    dry_run: bool,
+) -> Result<MigrationReport, StorageError> {
+    let mut report = MigrationReport {
+        dry_run,
+        ..Default::default()
+    };
+
+    let mut notes = from.get_notes().await?;
+    notes.sort_by_key(|note| note.id);
+    for note in notes {
+        let existing = match to.get_note(note.id).await {
+            Ok(existing) => Some(existing),
+            Err(StorageError::NotFound { .. }) => None,
+            Err(e) => {
+                report.push(RecordKind::Note, note.id, Outcome::Failed(e.to_string()));
+                continue;
+            }
+        };
+
+        let outcome = match existing {
+            Some(existing) if existing == note => Outcome::Unchanged,
+            Some(_) => Outcome::Conflict("the target holds a different note".to_string()),
+            None if dry_run => Outcome::WouldCopy,
+            None => match to.insert_note(note.clone()).await {
+                Ok(()) => match to.get_note(note.id).await {
+                    Ok(copied) if copied == note => Outcome::Copied,
+                    Ok(_) => Outcome::Failed("the copy differs from the original".to_string()),
+                    Err(e) => Outcome::Failed(format!("failed to read back: {e}")),
+                },
+                Err(e) => Outcome::Failed(e.to_string()),
+            },
+        };
+        report.push(RecordKind::Note, note.id, outcome);
+    }
+
+    let mut todos = from.get_todos().await?;
+    todos.sort_by_key(|todo| todo.id);
+    let existing: HashMap<_, _> = to
+        .get_todos()
+        .await?
+        .into_iter()
+        .map(|todo| (todo.id, todo))
+        .collect();
+    let mut inserted = Vec::new();
+    for todo in todos {
+        let outcome = match existing.get(&todo.id) {
+            Some(existing) if *existing == todo => Outcome::Unchanged,
+            Some(_) => Outcome::Conflict("the target holds a different todo".to_string()),
+            None if dry_run => Outcome::WouldCopy,
+            None => match to.insert_todo(todo.clone()).await {
+                Ok(()) => {
+                    inserted.push((report.records.len(), todo.clone()));
+                    Outcome::Copied
+                }
+                Err(e) => Outcome::Failed(e.to_string()),
+            },
+        };
+        report.push(RecordKind::Todo, todo.id, outcome);
+    }
+
+    // storages can only list todos, so they are verified in one go
+    if !inserted.is_empty() {
+        let copies: HashMap<_, _> = to
+            .get_todos()
+            .await?
+            .into_iter()
+            .map(|todo| (todo.id, todo))
+            .collect();
+        for (index, todo) in inserted {
+            match copies.get(&todo.id) {
+                Some(copy) if *copy == todo => {}
+                Some(_) => {
+                    report.records[index].outcome =
+                        Outcome::Failed("the copy differs from the original".to_string())
+                }
+                None => {
+                    report.records[index].outcome =
+                        Outcome::Failed("the copy is missing from the target".to_string())
+                }
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        database::{NoteStorage, TodoStorage, memory::MemoryDatabase, sqlite::SqliteDatabase},
+        fixtures,
+        migrate::{Outcome, StorageLocation, migrate, migrate_source},
+    };
+
+    async fn populated() -> MemoryDatabase {
+        let db = MemoryDatabase::new();
+        db.save_note(fixtures::new_note(
+            "src/main.rs",
+            "fn main() {",
+            "entry point",
+        ))
+        .await
+        .unwrap();
+        for (line_number, content) in [(3, "TODO: a"), (8, "TODO: b")] {
+            db.save_todo(fixtures::new_todo("src/main.rs", line_number, content))
+                .await
+                .unwrap();
+        }
+        let deleted = db.get_todos().await.unwrap()[0].id;
+        db.delete_todo(deleted).await.unwrap();
+
+        db
+    }
+
+    #[test]
+    fn locations_are_parsed() {
+        assert_eq!(
+            "file:/data".parse(),
+            Ok(StorageLocation::File("/data".into()))
+        );
+        assert_eq!(
+            "sqlite:db/contextual.db".parse(),
+            Ok(StorageLocation::Sqlite("db/contextual.db".into()))
+        );
+        assert!("postgres:/db".parse::<StorageLocation>().is_err());
+        assert!("file:".parse::<StorageLocation>().is_err());
+        assert!("/data".parse::<StorageLocation>().is_err());
+    }
+
+    #[tokio::test]
+    async fn records_are_copied_with_ids_and_timestamps() {
+        let from = populated().await;
+        let to = SqliteDatabase::in_memory().await.unwrap();
+
+        let report = migrate(&from, &to, false).await.unwrap();
+
+        assert!(report.is_success());
+        assert_eq!(report.records.len(), 3);
+        assert!(report.records.iter().all(|r| r.outcome == Outcome::Copied));
+        let mut expected = from.get_todos().await.unwrap();
+        let mut copied = to.get_todos().await.unwrap();
+        expected.sort_by_key(|todo| todo.id);
+        copied.sort_by_key(|todo| todo.id);
+        assert_eq!(copied, expected);
+        assert_eq!(
+            to.get_notes().await.unwrap(),
+            from.get_notes().await.unwrap()
+        );
+
+        let again = migrate(&from, &to, false).await.unwrap();
+        assert!(
+            again
+                .records
+                .iter()
+                .all(|r| r.outcome == Outcome::Unchanged)
+        );
+    }
+
+    #[tokio::test]
+    async fn dry_run_writes_nothing() {
+        let from = populated().await;
+        let to = MemoryDatabase::new();
+
+        let report = migrate(&from, &to, true).await.unwrap();
+
+        assert!(report.is_success());
+        assert!(
+            report
+                .records
+                .iter()
+                .all(|r| r.outcome == Outcome::WouldCopy)
+        );
+        assert!(to.get_notes().await.unwrap().is_empty());
+        assert!(to.get_todos().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn differing_records_are_reported_as_conflicts() {
+        let from = populated().await;
+        let to = MemoryDatabase::new();
+        let mut note = from.get_notes().await.unwrap().remove(0);
+        note.content = "changed in the target".to_string();
+        to.insert_note(note).await.unwrap();
+
+        let report = migrate(&from, &to, false).await.unwrap();
+
+        assert!(!report.is_success());
+        assert!(matches!(report.records[0].outcome, Outcome::Conflict(_)));
+        assert_eq!(
+            to.get_notes().await.unwrap()[0].content,
+            "changed in the target"
+        );
+    }
+
+    #[tokio::test]
+    async fn unreadable_source_records_fail_without_being_moved() {
+        let dir = tempfile::tempdir().unwrap();
+        let notes = dir.path().join("notes");
+        std::fs::create_dir_all(&notes).unwrap();
+        let corrupt_id = uuid::Uuid::new_v4();
+        let newer_id = uuid::Uuid::new_v4();
+        std::fs::write(notes.join(corrupt_id.to_string()), "{\"id\": ").unwrap();
+        std::fs::write(
+            dir.path().join(newer_id.to_string()),
+            serde_json::json!({ "schema_version": 1000, "id": newer_id }).to_string(),
+        )
+        .unwrap();
+        let from = StorageLocation::File(dir.path().to_path_buf());
+        let to = MemoryDatabase::new();
+
+        for dry_run in [true, false] {
+            let source = from.open_source().await.unwrap();
+            let report = migrate_source(&source, &to, dry_run).await.unwrap();
+
+            assert!(!report.is_success());
+            let mut failed: Vec<_> = report
+                .records
+                .iter()
+                .filter(|r| matches!(r.outcome, Outcome::Failed(_)))
+                .map(|r| r.id)
+                .collect();
+            failed.sort();
+            let mut expected = vec![corrupt_id, newer_id];
+            expected.sort();
+            assert_eq!(failed, expected);
+        }
+        assert!(notes.join(corrupt_id.to_string()).exists());
+        assert!(!dir.path().join("quarantine").exists());
+    }
+
+    #[tokio::test]
+    async fn missing_sources_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+
+        for from in [
+            StorageLocation::File(dir.path().join("missing")),
+            StorageLocation::Sqlite(dir.path().join("missing.db")),
+        ] {
+            assert!(from.open_source().await.is_err());
+            assert!(!from.exists());
+        }
+    }
+    #[tokio::test]
+    async fn sqlite_sources_are_read_without_being_migrated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("contextual.db");
+        let db = SqliteDatabase::init(&path).await.unwrap();
+        migrate(&populated().await, &db, false).await.unwrap();
+
+        let from = StorageLocation::Sqlite(path.clone());
+        let source = from.open_source().await.unwrap();
+        let todos = source.storage.get_todos().await.unwrap();
+        assert_eq!(todos.len(), 2);
+        assert!(source.storage.delete_todo(todos[0].id).await.is_err());
+
+        // a store from before the latest migration is refused, not migrated
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite:{}", path.display()))
+            .await
+            .unwrap();
+        sqlx::query("DELETE FROM _sqlx_migrations")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let err = from.open_source().await.err().unwrap();
+        assert!(err.to_string().contains("older schema"), "{err}");
+        let applied: i64 = sqlx::query_scalar("SELECT count(*) FROM _sqlx_migrations")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(applied, 0);
+    }
+}
@@ -3,20 +3,24 @@ use std::{collections::HashMap, sync::Arc};
 use futures::future::BoxFuture;
 use serde_json::Value;
 
+use tokio::sync::broadcast;
+
 use crate::{
-    jsonrpc::{JsonRpcRequest, JsonRpcResponse, ResponseError},
+    jsonrpc::{JsonRpcNotification, JsonRpcRequest, JsonRpcResponse, Notifier, ResponseError},
     service::{CloneableService, Service},
 };
 
+type Routes = Arc<HashMap<String, Arc<dyn CloneableService<JsonRpcRequest, Value, ResponseError>>>>;
+
+#[derive(Default)]
 pub struct RouterFactory {
-    routes: Arc<HashMap<String, Arc<dyn CloneableService<JsonRpcRequest, Value, ResponseError>>>>,
+    routes: Routes,
+    notifier: Notifier,
 }
 
 impl RouterFactory {
     pub fn new() -> Self {
-        Self {
-            routes: Arc::new(HashMap::new()),
-        }
+        Self::default()
     }
 
     pub fn with_route<S>(self, method: &str, svc: S) -> Self
@@ -27,18 +31,40 @@ impl RouterFactory {
         routes.insert(method.into(), Arc::new(svc));
         Self {
             routes: Arc::new(routes),
+            notifier: self.notifier,
         }
     }
 
+    /// Sends notifications to the clients of every service of this factory.
+    pub fn notifier(&self) -> Notifier {
+        self.notifier.clone()
+    }
+
     pub fn service(&self) -> RouterService {
         RouterService {
             routes: self.routes.clone(),
+            notifications: self.notifier.subscribe(),
         }
     }
 }
 
 pub struct RouterService {
-    routes: Arc<HashMap<String, Arc<dyn CloneableService<JsonRpcRequest, Value, ResponseError>>>>,
+    routes: Routes,
+    notifications: broadcast::Receiver<JsonRpcNotification>,
+}
+
+impl RouterService {
+    /// The next notification for the client, `None` once no more can come.
+    /// Notifications the client fell too far behind on are skipped.
+    pub async fn next_notification(&mut self) -> Option<JsonRpcNotification> {
+        loop {
+            match self.notifications.recv().await {
+                Ok(notification) => return Some(notification),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
 }
 
 impl Service<JsonRpcRequest> for RouterService {
@@ -63,10 +89,10 @@ impl Service<JsonRpcRequest> for RouterService {
             Box::pin(async move {
                 Ok(JsonRpcResponse::from_error(
                     id,
-                    ResponseError {
-                        code: -32601,
-                        message: format!("Method note found: {}", req.method),
-                    },
+                    ResponseError::new(
+                        ResponseError::METHOD_NOT_FOUND,
+                        format!("Method not found: {}", req.method),
+                    ),
                 ))
             })
         }
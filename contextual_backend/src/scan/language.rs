@@ -0,0 +1,498 @@
+//! Comment syntax of the languages the scanner understands.
+//!
+//! Only text inside comments is searched for todo markers, so a language has
+//! to describe its comments and, to not mistake `"// TODO"` for a comment, its
+//! string literals. The built-in table covers common languages and can be
+//! extended or overridden from the configuration file.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// How comments and strings are written in one language.
+///
+/// A language without any comment syntax is prose: every line of its files is
+/// searched.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Language {
+    pub name: String,
+    /// File extensions without the dot, matched case-insensitively.
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    /// Exact file names, for files like `Makefile`.
+    #[serde(default)]
+    pub filenames: Vec<String>,
+    /// Openers of comments running to the end of the line, e.g. `//`.
+    #[serde(default)]
+    pub line_comments: Vec<String>,
+    /// Opener and closer of block comments, e.g. `["/*", "*/"]`.
+    #[serde(default)]
+    pub block_comments: Vec<(String, String)>,
+    /// Whether block comments nest, as in Rust.
+    #[serde(default)]
+    pub nested_comments: bool,
+    /// Delimiters of string literals, which open and close the string.
+    /// Backslash escapes the next character. These strings end with their
+    /// line at the latest, so a stray `'` in code or prose doesn't hide the
+    /// comments of the rest of the file.
+    #[serde(default)]
+    pub strings: Vec<String>,
+    /// Delimiters of string literals which may span lines, like `"""`.
+    #[serde(default)]
+    pub multiline_strings: Vec<String>,
+    /// Whether `'x'` is a character literal while a lone `'` is not a string,
+    /// as with Rust's lifetimes.
+    #[serde(default)]
+    pub char_literals: bool,
+    /// Code standing in for unfinished code, like Rust's `todo!`, matched as
+    /// whole words outside of comments and strings.
+    #[serde(default)]
+    pub placeholders: Vec<String>,
+}
+
+/// One line of a file split into its code and its comment text. The contents
+/// of string literals are in neither.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceLine {
+    pub code: String,
+    pub comment: String,
+}
+
+impl Language {
+    fn new(name: &str, extensions: &[&str], line_comments: &[&str]) -> Self {
+        Self {
+            name: name.to_string(),
+            extensions: to_strings(extensions),
+            filenames: Vec::new(),
+            line_comments: to_strings(line_comments),
+            block_comments: Vec::new(),
+            nested_comments: false,
+            strings: to_strings(&["\""]),
+            multiline_strings: Vec::new(),
+            char_literals: false,
+            placeholders: Vec::new(),
+        }
+    }
+
+    fn filenames(mut self, filenames: &[&str]) -> Self {
+        self.filenames = to_strings(filenames);
+        self
+    }
+
+    fn block(mut self, open: &str, close: &str) -> Self {
+        self.block_comments
+            .push((open.to_string(), close.to_string()));
+        self
+    }
+
+    fn nested(mut self) -> Self {
+        self.nested_comments = true;
+        self
+    }
+
+    fn char_literals(mut self) -> Self {
+        self.char_literals = true;
+        self
+    }
+
+    fn strings(mut self, strings: &[&str]) -> Self {
+        self.strings = to_strings(strings);
+        self
+    }
+
+    fn multiline_strings(mut self, strings: &[&str]) -> Self {
+        self.multiline_strings = to_strings(strings);
+        self
+    }
+
+    fn placeholders(mut self, placeholders: &[&str]) -> Self {
+        self.placeholders = to_strings(placeholders);
+        self
+    }
+
+    /// Whether the file at `path` is written in this language.
+    pub fn matches(&self, path: &Path) -> bool {
+        let file_name = path.file_name().and_then(|name| name.to_str());
+        let extension = path.extension().and_then(|ext| ext.to_str());
+
+        file_name.is_some_and(|name| self.filenames.iter().any(|f| f == name))
+            || extension
+                .is_some_and(|ext| self.extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)))
+    }
+
+    /// Whether the language has no comments, making all of its text prose.
+    pub fn is_prose(&self) -> bool {
+        self.line_comments.is_empty() && self.block_comments.is_empty()
+    }
+
+    /// The text inside comments on each line of `text`, as zero-based line
+    /// numbers and the comment text, in order. Comments spanning several
+    /// lines are reported for each of their lines.
+    pub fn comments(&self, text: &str) -> Vec<(usize, String)> {
+        self.split(text)
+            .into_iter()
+            .enumerate()
+            .filter(|(_, line)| !line.comment.is_empty())
+            .map(|(i, line)| (i, line.comment))
+            .collect()
+    }
+
+    /// Split every line of `text` into code and comment.
+    pub fn split(&self, text: &str) -> Vec<SourceLine> {
+        let mut lines = vec![SourceLine::default(); text.lines().count()];
+        if self.is_prose() {
+            for (line, prose) in lines.iter_mut().zip(text.lines()) {
+                line.comment = prose.to_string();
+            }
+            return lines;
+        }
+
+        let mut push = |line: usize, c: char, comment: bool| {
+            if let Some(line) = lines.get_mut(line) {
+                match comment {
+                    true => line.comment.push(c),
+                    false => line.code.push(c),
+                }
+            }
+        };
+
+        let mut state = State::Code;
+        let mut line = 0;
+        let mut chars = text.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            let rest = &text[i..];
+            // the first character of a token was already consumed
+            macro_rules! skip {
+                ($len:expr) => {
+                    while chars.peek().is_some_and(|(j, _)| *j < i + $len) {
+                        chars.next();
+                    }
+                };
+            }
+
+            state = match state {
+                State::Code => {
+                    if let Some(block) = self
+                        .block_comments
+                        .iter()
+                        .position(|(open, _)| rest.starts_with(open.as_str()))
+                    {
+                        skip!(self.block_comments[block].0.len());
+                        State::Block { block, depth: 1 }
+                    } else if let Some(open) = self
+                        .line_comments
+                        .iter()
+                        .find(|open| rest.starts_with(open.as_str()))
+                    {
+                        skip!(open.len());
+                        State::Line
+                    } else if let Some(len) = self.char_literal_len(rest) {
+                        skip!(len);
+                        State::Code
+                    } else if let Some((delimiter, multiline)) = self.string_opener(rest) {
+                        skip!(delimiter.len());
+                        State::String {
+                            delimiter,
+                            multiline,
+                        }
+                    } else {
+                        if c != '\n' {
+                            push(line, c, false);
+                        }
+                        State::Code
+                    }
+                }
+                State::Line if c == '\n' => State::Code,
+                State::Line => {
+                    push(line, c, true);
+                    State::Line
+                }
+                State::Block { block, depth } => {
+                    let (open, close) = &self.block_comments[block];
+                    if rest.starts_with(close.as_str()) {
+                        skip!(close.len());
+                        match depth {
+                            1 => State::Code,
+                            _ => State::Block {
+                                block,
+                                depth: depth - 1,
+                            },
+                        }
+                    } else if self.nested_comments && rest.starts_with(open.as_str()) {
+                        skip!(open.len());
+                        State::Block {
+                            block,
+                            depth: depth + 1,
+                        }
+                    } else {
+                        if c != '\n' {
+                            push(line, c, true);
+                        }
+                        State::Block { block, depth }
+                    }
+                }
+                State::String {
+                    delimiter,
+                    multiline,
+                } => {
+                    if c == '\\' {
+                        // an escaped newline still ends the line
+                        if chars.next().is_some_and(|(_, escaped)| escaped == '\n') {
+                            line += 1;
+                        }
+                        state
+                    } else if rest.starts_with(delimiter) {
+                        skip!(delimiter.len());
+                        State::Code
+                    } else if c == '\n' && !multiline {
+                        State::Code
+                    } else {
+                        state
+                    }
+                }
+            };
+
+            if c == '\n' {
+                line += 1;
+            }
+        }
+
+        lines
+    }
+
+    /// The delimiter of the string literal `text` starts with, if any, and
+    /// whether the string may span lines. The longest delimiter wins, so
+    /// `"""` wins over `"`.
+    fn string_opener(&self, text: &str) -> Option<(&str, bool)> {
+        let single = longest_prefix(&self.strings, text).map(|d| (d, false));
+        let multiline = longest_prefix(&self.multiline_strings, text).map(|d| (d, true));
+
+        [single, multiline]
+            .into_iter()
+            .flatten()
+            .max_by_key(|(delimiter, _)| delimiter.len())
+    }
+
+    /// The length of the character literal `text` starts with, if any.
+    fn char_literal_len(&self, text: &str) -> Option<usize> {
+        if !self.char_literals || !text.starts_with('\'') {
+            return None;
+        }
+        let mut chars = text.char_indices().skip(1);
+        let end = match chars.next()? {
+            // '\n', '\'', '\u{1F980}'
+            (_, '\\') => text.get(3..16)?.find('\'')? + 3,
+            (_, '\'') => return None,
+            (_, _) => {
+                let (end, quote) = chars.next()?;
+                (quote == '\'').then_some(end)?
+            }
+        };
+
+        Some(end + 1)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State<'a> {
+    Code,
+    Line,
+    /// Inside the block comment at index `block` of the language.
+    Block {
+        block: usize,
+        depth: usize,
+    },
+    String {
+        delimiter: &'a str,
+        multiline: bool,
+    },
+}
+
+/// The longest of `candidates` which `text` starts with.
+fn longest_prefix<'a>(candidates: &'a [String], text: &str) -> Option<&'a str> {
+    candidates
+        .iter()
+        .filter(|candidate| !candidate.is_empty() && text.starts_with(candidate.as_str()))
+        .max_by_key(|candidate| candidate.len())
+        .map(String::as_str)
+}
+
+fn to_strings(strs: &[&str]) -> Vec<String> {
+    strs.iter().map(|s| s.to_string()).collect()
+}
+
+/// The languages known without any configuration.
+pub fn builtin() -> Vec<Language> {
+    vec![
+        Language::new("rust", &["rs"], &["//"])
+            .block("/*", "*/")
+            .strings(&[])
+            .multiline_strings(&["\""])
+            .nested()
+            .char_literals()
+            .placeholders(&["todo!", "unimplemented!", "unreachable!"]),
+        Language::new(
+            "c",
+            &[
+                "c", "h", "cc", "cpp", "cxx", "hpp", "hh", "cs", "java", "swift", "dart", "proto",
+            ],
+            &["//"],
+        )
+        .block("/*", "*/")
+        .strings(&["\"", "'"]),
+        Language::new("kotlin", &["kt", "kts"], &["//"])
+            .block("/*", "*/")
+            .nested()
+            .multiline_strings(&["\"\"\""])
+            .char_literals()
+            .placeholders(&["TODO"]),
+        Language::new("scala", &["scala", "sc"], &["//"])
+            .block("/*", "*/")
+            .nested()
+            .multiline_strings(&["\"\"\""])
+            .char_literals()
+            .placeholders(&["???"]),
+        Language::new("zig", &["zig"], &["//"])
+            .strings(&["\""])
+            .char_literals()
+            .placeholders(&["unreachable"]),
+        Language::new(
+            "javascript",
+            &["js", "jsx", "mjs", "cjs", "ts", "tsx", "mts", "cts"],
+            &["//"],
+        )
+        .block("/*", "*/")
+        .strings(&["\"", "'"])
+        .multiline_strings(&["`"]),
+        Language::new("go", &["go"], &["//"])
+            .block("/*", "*/")
+            .strings(&["\"", "'"])
+            .multiline_strings(&["`"]),
+        Language::new("css", &["css"], &[])
+            .block("/*", "*/")
+            .strings(&["\"", "'"]),
+        Language::new("scss", &["scss", "sass", "less"], &["//"])
+            .block("/*", "*/")
+            .strings(&["\"", "'"]),
+        Language::new("lua", &["lua"], &["--"])
+            .block("--[[", "]]")
+            .strings(&["\"", "'"]),
+        Language::new("python", &["py", "pyi"], &["#"])
+            .strings(&["\"", "'"])
+            .multiline_strings(&["\"\"\"", "'''"])
+            .placeholders(&["NotImplementedError"]),
+        Language::new("shell", &["sh", "bash", "zsh", "fish"], &["#"])
+            .filenames(&[".bashrc", ".zshrc", ".profile"]),
+        Language::new("ruby", &["rb", "rake"], &["#"])
+            .filenames(&["Gemfile", "Rakefile"])
+            .block("=begin", "=end")
+            .strings(&["\"", "'"]),
+        Language::new("perl", &["pl", "pm"], &["#"]).strings(&["\"", "'"]),
+        Language::new("elixir", &["ex", "exs"], &["#"]).multiline_strings(&["\"\"\""]),
+        Language::new(
+            "config",
+            &["toml", "yaml", "yml", "ini", "cfg", "conf"],
+            &["#", ";"],
+        )
+        .strings(&["\"", "'"]),
+        Language::new("make", &["mk", "cmake"], &["#"])
+            .filenames(&[
+                "Makefile",
+                "makefile",
+                "GNUmakefile",
+                "CMakeLists.txt",
+                "Dockerfile",
+            ])
+            .strings(&[]),
+        Language::new("nix", &["nix"], &["#"]).block("/*", "*/"),
+        Language::new("sql", &["sql"], &["--"])
+            .block("/*", "*/")
+            .strings(&["'"]),
+        Language::new("haskell", &["hs"], &["--"])
+            .block("{-", "-}")
+            .nested()
+            .placeholders(&["undefined"]),
+        Language::new("elm", &["elm"], &["--"])
+            .block("{-", "-}")
+            .nested()
+            .placeholders(&["Debug.todo"]),
+        Language::new("markup", &["html", "htm", "xml", "svg"], &[])
+            .block("<!--", "-->")
+            .strings(&[]),
+        // single file components mix markup with script and style blocks
+        Language::new("component", &["vue", "svelte", "astro"], &["//"])
+            .block("<!--", "-->")
+            .block("/*", "*/")
+            .strings(&["\"", "'"])
+            .multiline_strings(&["`"]),
+        Language::new("vim", &["vim"], &["\""]).strings(&[]),
+        Language::new("lisp", &["el", "lisp", "clj", "cljs", "scm"], &[";"]),
+        Language::new("text", &["txt", "md", "markdown", "org", "rst"], &[]).strings(&[]),
+    ]
+}
+
+/// The language of text files in no known language, with the most common
+/// comment syntax, so that their todos aren't missed.
+pub fn fallback() -> Language {
+    Language::new("fallback", &[], &["//", "#"])
+        .block("/*", "*/")
+        .strings(&["\"", "'"])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scan::language::builtin;
+
+    fn comments(language: &str, text: &str) -> Vec<(usize, String)> {
+        builtin()
+            .into_iter()
+            .find(|l| l.name == language)
+            .unwrap()
+            .comments(text)
+    }
+
+    #[test]
+    fn only_comments_are_extracted() {
+        let rust = "let s = \"// TODO: not a comment \\\" /* either\";\nlet x = '\"'; // TODO: real\n/* a /* nested */ still\n TODO */ let todo = 2;\n";
+        assert_eq!(
+            comments("rust", rust),
+            vec![
+                (1, " TODO: real".to_string()),
+                (2, " a  nested  still".to_string()),
+                (3, " TODO ".to_string()),
+            ]
+        );
+
+        let lua = "local s = '-- TODO' -- FIXME: here\n--[[ HACK:\nblock ]] print(s)\n";
+        assert_eq!(
+            comments("lua", lua),
+            vec![
+                (0, " FIXME: here".to_string()),
+                (1, " HACK:".to_string()),
+                (2, "block ".to_string()),
+            ]
+        );
+
+        let python = "doc = \"\"\"\n# TODO: in a docstring\n\"\"\"\nx = 1  # TODO: real\n";
+        assert_eq!(
+            comments("python", python),
+            vec![(3, " TODO: real".to_string())]
+        );
+    }
+
+    #[test]
+    fn single_line_strings_end_with_their_line() {
+        assert_eq!(
+            comments("config", "title: Don't panic\n# TODO: a\n"),
+            vec![(1, " TODO: a".to_string())]
+        );
+        assert_eq!(
+            comments("c", "#error don't build\n// TODO: b\n"),
+            vec![(1, " TODO: b".to_string())]
+        );
+
+        let go = "s := `\n// TODO: in a raw string\n` // TODO: real\n";
+        assert_eq!(comments("go", go), vec![(2, " TODO: real".to_string())]);
+    }
+}
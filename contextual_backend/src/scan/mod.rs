@@ -0,0 +1,666 @@
+//! Scanning a project for todos without relying on external tools.
+//!
+//! The scan walks the project like git sees it: files matched by
+//! `.gitignore`, `.ignore` and the global git excludes are skipped, as are
+//! hidden files, binary files and files too large to be source code. Text
+//! files in no known language are searched for the most common comments.
+
+use std::{
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use ignore::WalkBuilder;
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+
+use crate::{
+    scan::language::{Language, SourceLine},
+    types::todo::NewTodoItem,
+};
+
+pub mod language;
+
+/// Markers starting a todo unless configured otherwise.
+pub const DEFAULT_KEYWORDS: [&str; 3] = ["TODO", "FIXME", "HACK"];
+/// The files larger than this are skipped, in bytes.
+const DEFAULT_MAX_FILE_SIZE: u64 = 1024 * 1024;
+/// How much of a file is checked for NUL bytes to tell binary files apart.
+const BINARY_CHECK_LEN: usize = 8 * 1024;
+
+static FALLBACK: LazyLock<Language> = LazyLock::new(language::fallback);
+
+/// What a scan looks for.
+#[derive(Debug, Clone)]
+pub struct ScanOptions {
+    /// Markers starting a todo, matched case-sensitively as whole words.
+    pub keywords: Vec<String>,
+    pub max_file_size: u64,
+    /// When several match a file, the last one wins. Files in none of these
+    /// languages are scanned as [language::fallback].
+    pub languages: Vec<Language>,
+    /// Whether placeholders in code, like `todo!()`, are todos too, see
+    /// [Language::placeholders].
+    pub placeholders: bool,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            keywords: DEFAULT_KEYWORDS.map(String::from).to_vec(),
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            languages: language::builtin(),
+            placeholders: false,
+        }
+    }
+}
+
+impl ScanOptions {
+    /// Apply the settings of `config` over these options. Configured
+    /// languages replace the language of the same name, or are added.
+    pub fn apply(mut self, config: ScanConfig) -> Self {
+        if let Some(keywords) = config.keywords {
+            self.keywords = keywords;
+        }
+        if let Some(max_file_size) = config.max_file_size {
+            self.max_file_size = max_file_size;
+        }
+        if let Some(placeholders) = config.placeholders {
+            self.placeholders = placeholders;
+        }
+        for language in config.languages {
+            match self.languages.iter().position(|l| l.name == language.name) {
+                Some(i) => self.languages[i] = language,
+                None => self.languages.push(language),
+            }
+        }
+
+        self
+    }
+
+    /// The language of the file at `path`, if the scan knows it.
+    pub fn language_of(&self, path: &Path) -> Option<&Language> {
+        self.languages.iter().rev().find(|l| l.matches(path))
+    }
+
+    /// The language the file at `path` is scanned as, the fallback for files
+    /// in no known language.
+    pub fn scanned_language_of(&self, path: &Path) -> &Language {
+        self.language_of(path).unwrap_or(&FALLBACK)
+    }
+}
+
+/// Scan settings from the configuration file or a request, unset ones keep
+/// the value they are applied over, see [ScanOptions::apply].
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScanConfig {
+    pub keywords: Option<Vec<String>>,
+    pub max_file_size: Option<u64>,
+    pub languages: Vec<Language>,
+    pub placeholders: Option<bool>,
+}
+
+impl ScanConfig {
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if let Some(keywords) = &self.keywords
+            && (keywords.is_empty() || keywords.iter().any(|k| k.trim().is_empty()))
+        {
+            anyhow::bail!("keywords must not be empty");
+        }
+        if self.languages.iter().any(|l| l.name.trim().is_empty()) {
+            anyhow::bail!("languages must have a name");
+        }
+        if self
+            .languages
+            .iter()
+            .any(|l| l.placeholders.iter().any(|p| p.trim().is_empty()))
+        {
+            anyhow::bail!("placeholders must not be empty");
+        }
+
+        Ok(())
+    }
+}
+
+impl TryFrom<&JsonValue> for ScanConfig {
+    type Error = anyhow::Error;
+
+    /// Read the optional `keywords`, `max_file_size`, `languages` and
+    /// `placeholders` of a request.
+    fn try_from(value: &JsonValue) -> Result<Self, Self::Error> {
+        let settings = ["keywords", "max_file_size", "languages", "placeholders"]
+            .into_iter()
+            .filter_map(|key| {
+                let setting = value.get(key).filter(|v| !v.is_null())?;
+                Some((key.to_string(), setting.clone()))
+            })
+            .collect();
+        let config: ScanConfig = serde_json::from_value(JsonValue::Object(settings))?;
+        config.validate()?;
+
+        Ok(config)
+    }
+}
+
+/// Scan every file of `project_dir` for todos. File paths are relative to
+/// `project_dir` and line numbers start at 1.
+///
+/// Files which can't be read are skipped, only a `project_dir` which isn't a
+/// readable directory fails the scan.
+pub fn scan(
+    project_dir: &Path,
+    branch: &str,
+    options: &ScanOptions,
+) -> Result<Vec<NewTodoItem>, io::Error> {
+    if !std::fs::metadata(project_dir)?.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotADirectory,
+            format!("{} is not a directory", project_dir.display()),
+        ));
+    }
+
+    Ok(scan_walk(project_dir, branch, options, walker(project_dir)))
+}
+
+/// Scan only `paths`, files or directories relative to `project_dir`, for
+/// todos. Paths which don't exist anymore, or which a full scan would skip,
+/// have none.
+pub fn scan_paths(
+    project_dir: &Path,
+    branch: &str,
+    paths: &[String],
+    options: &ScanOptions,
+) -> Vec<NewTodoItem> {
+    let paths: Vec<PathBuf> = paths.iter().map(|path| project_dir.join(path)).collect();
+    // descend only towards and into the paths, so that ignore files apply
+    // exactly as in a full scan
+    let mut walker = walker(project_dir);
+    walker.filter_entry(move |entry| {
+        paths
+            .iter()
+            .any(|path| path.starts_with(entry.path()) || entry.path().starts_with(path))
+    });
+
+    scan_walk(project_dir, branch, options, walker)
+}
+
+/// The `paths` relative to `project_dir` a scan could find todos in, i.e.
+/// without those which are ignored, hidden or inside such a directory.
+///
+/// Paths which don't exist anymore are judged by their closest existing
+/// ancestor, so that removed files are kept.
+pub fn visible_paths(project_dir: &Path, paths: &[String]) -> Vec<String> {
+    let existing: Vec<PathBuf> = paths
+        .iter()
+        .map(|path| {
+            let path = project_dir.join(path);
+            path.ancestors()
+                .take_while(|ancestor| ancestor.starts_with(project_dir))
+                .find(|ancestor| ancestor.symlink_metadata().is_ok())
+                .unwrap_or(project_dir)
+                .to_path_buf()
+        })
+        .collect();
+
+    // descend only towards the paths, so that ignore files apply exactly as
+    // in a full scan
+    let targets = existing.clone();
+    let mut walker = walker(project_dir);
+    walker.filter_entry(move |entry| {
+        targets
+            .iter()
+            .any(|target| target.starts_with(entry.path()))
+    });
+    let visited: HashSet<PathBuf> = walker
+        .build()
+        .filter_map(Result::ok)
+        .map(|entry| entry.into_path())
+        .collect();
+
+    paths
+        .iter()
+        .zip(&existing)
+        .filter(|(_, existing)| visited.contains(*existing))
+        .map(|(path, _)| path.clone())
+        .collect()
+}
+
+/// The directories a scan of `project_dir` descends into within `paths`,
+/// relative to `project_dir` like in [visible_paths], `""` being the whole
+/// project. The paths themselves are included when they are such
+/// directories.
+pub fn visible_dirs(project_dir: &Path, paths: &[String]) -> Vec<PathBuf> {
+    let targets: Vec<PathBuf> = paths.iter().map(|path| project_dir.join(path)).collect();
+    // descend towards the paths and then into them
+    let walked = targets.clone();
+    let mut walker = walker(project_dir);
+    walker.filter_entry(move |entry| {
+        walked
+            .iter()
+            .any(|target| target.starts_with(entry.path()) || entry.path().starts_with(target))
+    });
+
+    walker
+        .build()
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            entry
+                .file_type()
+                .is_some_and(|file_type| file_type.is_dir())
+        })
+        .map(|entry| entry.into_path())
+        .filter(|dir| targets.iter().any(|target| dir.starts_with(target)))
+        .collect()
+}
+
+fn walker(project_dir: &Path) -> WalkBuilder {
+    let mut walker = WalkBuilder::new(project_dir);
+    walker
+        // honour .gitignore files even before `git init`
+        .require_git(false)
+        .sort_by_file_name(|a, b| a.cmp(b));
+
+    walker
+}
+
+fn scan_walk(
+    project_dir: &Path,
+    branch: &str,
+    options: &ScanOptions,
+    walker: WalkBuilder,
+) -> Vec<NewTodoItem> {
+    let mut todos = Vec::new();
+    for entry in walker.build().filter_map(Result::ok) {
+        if !entry.file_type().is_some_and(|t| t.is_file()) {
+            continue;
+        }
+        let Some(text) = read_text(entry.path(), options.max_file_size) else {
+            continue;
+        };
+        let file_path = relative_path(project_dir, entry.path());
+
+        todos.extend(scan_file(project_dir, branch, &file_path, &text, options));
+    }
+
+    todos
+}
+
+/// The todos in `text`, the contents of the file at `file_path` relative to
+/// `project_dir` as held by an editor. Like in a full scan, files which are
+/// ignored, hidden, too large or binary have none.
+pub fn scan_contents(
+    project_dir: &Path,
+    branch: &str,
+    file_path: &str,
+    text: &str,
+    options: &ScanOptions,
+) -> Vec<NewTodoItem> {
+    if text.len() as u64 > options.max_file_size
+        || is_binary(text.as_bytes())
+        || visible_paths(project_dir, &[file_path.to_string()]).is_empty()
+    {
+        return Vec::new();
+    }
+
+    scan_file(project_dir, branch, file_path, text, options)
+}
+
+/// The todos in `text`, the contents of the file at `file_path` relative to
+/// `project_dir`.
+pub fn scan_file(
+    project_dir: &Path,
+    branch: &str,
+    file_path: &str,
+    text: &str,
+    options: &ScanOptions,
+) -> Vec<NewTodoItem> {
+    let language = options.scanned_language_of(Path::new(file_path));
+
+    scan_text(text, language, options)
+        .into_iter()
+        .map(|found| NewTodoItem {
+            project_dir: project_dir.to_string_lossy().into_owned(),
+            branch: branch.to_string(),
+            file_path: file_path.to_string(),
+            line_number: found.line_number,
+            end_line_number: found.end_line_number,
+            content: found.content,
+            kind: found.kind,
+            git: None,
+        })
+        .collect()
+}
+
+/// A todo found in a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FoundTodo {
+    /// Starts at 1.
+    pub line_number: u64,
+    /// The last line of the todo, inclusive.
+    pub end_line_number: u64,
+    /// The keyword or placeholder marking the todo.
+    pub kind: String,
+    /// The trimmed lines of the todo, joined by newlines.
+    pub content: String,
+}
+
+/// The lines of `text` with one of the keywords in a comment or, when
+/// enabled, a placeholder in code. A line holding both is a comment todo.
+///
+/// A todo in a comment of its own continues over the following comment lines
+/// at the same indentation, up to a blank comment or the next todo.
+pub fn scan_text(text: &str, language: &Language, options: &ScanOptions) -> Vec<FoundTodo> {
+    let placeholders: &[String] = match options.placeholders {
+        true => &language.placeholders,
+        false => &[],
+    };
+    let lines: Vec<&str> = text.lines().collect();
+    let sources = language.split(text);
+
+    let mut found = Vec::new();
+    let mut start = 0;
+    while start < lines.len() {
+        let source = &sources[start];
+        let comment_kind = kind_of(&source.comment, &options.keywords);
+        let Some(kind) = comment_kind.or_else(|| kind_of(&source.code, placeholders)) else {
+            start += 1;
+            continue;
+        };
+
+        let mut end = start;
+        if comment_kind.is_some() && source.code.trim().is_empty() {
+            while end + 1 < lines.len()
+                && continues(lines[start], lines[end + 1], &sources[end + 1], options)
+            {
+                end += 1;
+            }
+        }
+        found.push(FoundTodo {
+            line_number: start as u64 + 1,
+            end_line_number: end as u64 + 1,
+            kind: kind.to_string(),
+            content: lines[start..=end]
+                .iter()
+                .map(|line| line.trim())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        });
+        start = end + 1;
+    }
+
+    found
+}
+
+/// Whether `line` continues the todo starting on `first`: it holds nothing
+/// but a comment at the same indentation, which doesn't start a todo itself.
+fn continues(first: &str, line: &str, source: &SourceLine, options: &ScanOptions) -> bool {
+    let indentation = |line: &str| line.len() - line.trim_start().len();
+
+    source.code.trim().is_empty()
+        && !source.comment.trim().is_empty()
+        && kind_of(&source.comment, &options.keywords).is_none()
+        && line[..indentation(line)] == first[..indentation(first)]
+}
+
+/// The first of `markers` occurring in `text`, see [find_word].
+pub fn kind_of<'a, S: AsRef<str>>(text: &str, markers: &'a [S]) -> Option<&'a str> {
+    markers
+        .iter()
+        .map(AsRef::as_ref)
+        .filter_map(|marker| Some((find_word(text, marker)?, marker)))
+        .min_by_key(|(start, _)| *start)
+        .map(|(_, marker)| marker)
+}
+
+/// Where `word` first occurs in `line` without being part of a longer
+/// identifier, so `TODO:` matches but `TODOS` and `MY_TODO` don't.
+pub fn find_word(line: &str, word: &str) -> Option<usize> {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    let starts_ident = word.chars().next().is_some_and(is_ident);
+    let ends_ident = word.chars().next_back().is_some_and(is_ident);
+
+    line.match_indices(word)
+        .find(|(start, _)| {
+            let before = line[..*start].chars().next_back();
+            let after = line[start + word.len()..].chars().next();
+            let joined_before = starts_ident && before.is_some_and(is_ident);
+            let joined_after = ends_ident && after.is_some_and(is_ident);
+            !joined_before && !joined_after
+        })
+        .map(|(start, _)| start)
+}
+
+/// The contents of a text file, `None` for unreadable, oversized and binary
+/// files.
+fn read_text(path: &Path, max_file_size: u64) -> Option<String> {
+    if std::fs::metadata(path).ok()?.len() > max_file_size {
+        return None;
+    }
+    let bytes = std::fs::read(path).ok()?;
+    if is_binary(&bytes) {
+        return None;
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+fn is_binary(bytes: &[u8]) -> bool {
+    bytes[..bytes.len().min(BINARY_CHECK_LEN)].contains(&0)
+}
+
+/// `path` relative to `dir` with `/` separators, as todos are stored.
+pub fn relative_path(dir: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(dir).unwrap_or(path);
+
+    relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    use crate::scan::{
+        FoundTodo, ScanOptions, scan, scan_paths, scan_text, visible_dirs, visible_paths,
+    };
+
+    #[test]
+    fn keywords_are_matched_as_whole_words() {
+        let options = ScanOptions::default();
+        let rust = options.language_of(Path::new("main.rs")).unwrap();
+        let text = "fn main() {\n    // TODO: parse args\n    let todo_list = TODOS;\n}\n// FIXME(bob) later\n";
+
+        assert_eq!(
+            scan_text(text, rust, &options)
+                .into_iter()
+                .map(|found| (found.line_number, found.content))
+                .collect::<Vec<_>>(),
+            vec![
+                (2, "// TODO: parse args".to_string()),
+                (5, "// FIXME(bob) later".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn todos_continue_over_comment_lines_at_the_same_indentation() {
+        let options = ScanOptions::default();
+        let lua = options.language_of(Path::new("init.lua")).unwrap();
+        let text = "  -- TODO: split this\n  -- into two functions\n  --\n  -- FIXME: a\n  -- b\n    -- nested\n  -- TODO: c\n  call() -- d\n";
+
+        let found: Vec<_> = scan_text(text, lua, &options)
+            .into_iter()
+            .map(|found| (found.line_number, found.end_line_number, found.content))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    1,
+                    2,
+                    "-- TODO: split this\n-- into two functions".to_string()
+                ),
+                (4, 5, "-- FIXME: a\n-- b".to_string()),
+                (7, 7, "-- TODO: c".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn placeholders_are_found_when_enabled() {
+        let mut options = ScanOptions::default();
+        let rust = options.language_of(Path::new("lib.rs")).unwrap().clone();
+        let text = "fn a() { todo!(\"parse\") }\nfn b() { unimplemented!() } // FIXME: b\n// unreachable!()\nlet s = \"todo!()\";\n";
+        assert!(
+            scan_text(text, &rust, &options)
+                .iter()
+                .all(|f| f.kind == "FIXME")
+        );
+
+        options.placeholders = true;
+        assert_eq!(
+            scan_text(text, &rust, &options),
+            vec![
+                FoundTodo {
+                    line_number: 1,
+                    end_line_number: 1,
+                    kind: "todo!".to_string(),
+                    content: "fn a() { todo!(\"parse\") }".to_string(),
+                },
+                FoundTodo {
+                    line_number: 2,
+                    end_line_number: 2,
+                    kind: "FIXME".to_string(),
+                    content: "fn b() { unimplemented!() } // FIXME: b".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn ignored_and_binary_files_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        fs::create_dir_all(dir.path().join("target")).unwrap();
+        fs::write(dir.path().join(".gitignore"), "target/\n").unwrap();
+        fs::write(
+            dir.path().join("src/lib.rs"),
+            "// TODO: a\n\n// HACK: b\nlet s = \"// TODO: not a comment\";\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("target/out.rs"), "// TODO: ignored\n").unwrap();
+        fs::write(dir.path().join("logo.png"), b"\x89PNG\0\0 TODO").unwrap();
+        fs::write(dir.path().join("dump.txt"), b"\0\0 TODO").unwrap();
+
+        let todos = scan(dir.path(), "main", &ScanOptions::default()).unwrap();
+
+        let found: Vec<_> = todos
+            .iter()
+            .map(|todo| (todo.file_path.as_str(), todo.line_number))
+            .collect();
+        assert_eq!(found, vec![("src/lib.rs", 1), ("src/lib.rs", 3)]);
+        assert_eq!(todos[0].branch, "main");
+        assert!(scan(&dir.path().join("missing"), "main", &ScanOptions::default()).is_err());
+    }
+
+    #[test]
+    fn unknown_languages_fall_back_to_common_comments() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("index.php"), "<?php\n// TODO: a\n").unwrap();
+        fs::write(
+            dir.path().join("main.tf"),
+            "# FIXME: b\nname = \"# TODO\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("App.vue"),
+            "<template>\n  <!-- TODO: c -->\n</template>\n<script>\n// TODO: d\n</script>\n",
+        )
+        .unwrap();
+
+        let todos = scan(dir.path(), "main", &ScanOptions::default()).unwrap();
+
+        let found: Vec<_> = todos
+            .iter()
+            .map(|todo| (todo.file_path.as_str(), todo.line_number))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("App.vue", 2),
+                ("App.vue", 5),
+                ("index.php", 2),
+                ("main.tf", 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn only_the_given_paths_are_scanned() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src/nested")).unwrap();
+        fs::create_dir_all(dir.path().join("target")).unwrap();
+        fs::write(dir.path().join(".gitignore"), "target/\n").unwrap();
+        fs::write(dir.path().join("main.rs"), "// TODO: a\n").unwrap();
+        fs::write(dir.path().join("src/lib.rs"), "// TODO: b\n").unwrap();
+        fs::write(dir.path().join("src/nested/mod.rs"), "// TODO: c\n").unwrap();
+        fs::write(dir.path().join("target/out.rs"), "// TODO: ignored\n").unwrap();
+        let scan = |paths: &[&str]| {
+            let paths: Vec<String> = paths.iter().map(|p| p.to_string()).collect();
+            scan_paths(dir.path(), "main", &paths, &ScanOptions::default())
+                .into_iter()
+                .map(|todo| todo.file_path)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(scan(&["src/lib.rs"]), vec!["src/lib.rs"]);
+        assert_eq!(scan(&["src"]), vec!["src/lib.rs", "src/nested/mod.rs"]);
+        assert!(scan(&["target/out.rs", "gone.rs"]).is_empty());
+    }
+
+    #[test]
+    fn ignored_and_hidden_paths_are_not_visible() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        fs::create_dir_all(dir.path().join("target/debug")).unwrap();
+        fs::create_dir_all(dir.path().join(".git")).unwrap();
+        fs::write(dir.path().join(".gitignore"), "target/\n").unwrap();
+        fs::write(dir.path().join("src/lib.rs"), "").unwrap();
+        fs::write(dir.path().join("target/debug/out"), "").unwrap();
+        fs::write(dir.path().join(".git/HEAD"), "").unwrap();
+        let paths: Vec<String> = [
+            "src/lib.rs",
+            "src/removed.rs",
+            "target/debug/out",
+            "target/debug/removed",
+            ".git/HEAD",
+        ]
+        .iter()
+        .map(|p| p.to_string())
+        .collect();
+
+        assert_eq!(
+            visible_paths(dir.path(), &paths),
+            vec!["src/lib.rs", "src/removed.rs"]
+        );
+
+        assert_eq!(
+            visible_dirs(dir.path(), &[String::new()]),
+            vec![dir.path().to_path_buf(), dir.path().join("src")]
+        );
+        assert_eq!(visible_dirs(dir.path(), &paths), Vec::<PathBuf>::new());
+        assert_eq!(
+            visible_dirs(dir.path(), &["src".to_string(), "target".to_string()]),
+            vec![dir.path().join("src")]
+        );
+    }
+}
@@ -0,0 +1,408 @@
+//! In-memory full-text index over notes and todos, and the highlighted
+//! snippets shared by every storage's search.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use uuid::Uuid;
+
+use crate::types::{
+    Note,
+    search::{DocKind, QueryTerm, SearchField, SearchHit, SearchQuery, Snippet, tokenize},
+    tag::Tags,
+    todo::TodoItem,
+};
+
+/// Tokens shown before the first match of a snippet.
+const SNIPPET_CONTEXT: usize = 6;
+/// Maximum number of tokens in a snippet.
+const SNIPPET_TOKENS: usize = 24;
+
+/// BM25 parameters.
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+type DocId = (DocKind, Uuid);
+
+/// The searchable text of a note or an active todo.
+#[derive(Debug, Clone)]
+pub struct Document {
+    pub kind: DocKind,
+    pub id: Uuid,
+    pub project_dir: String,
+    pub tags: Tags,
+    pub fields: Vec<(SearchField, String)>,
+}
+
+impl Document {
+    pub fn from_note(note: &Note) -> Self {
+        Self {
+            kind: DocKind::Note,
+            id: note.id,
+            project_dir: note.context.project_dir.clone(),
+            tags: note.tags.clone(),
+            fields: vec![
+                (SearchField::Content, note.content.clone()),
+                (SearchField::Selection, note.context.selection.clone()),
+            ],
+        }
+    }
+
+    pub fn from_todo(todo_item: &TodoItem) -> Self {
+        Self {
+            kind: DocKind::Todo,
+            id: todo_item.id,
+            project_dir: todo_item.project_dir.clone(),
+            tags: todo_item.tags.clone(),
+            fields: vec![(SearchField::Content, todo_item.content.clone())],
+        }
+    }
+}
+
+struct Indexed {
+    doc: Document,
+    /// Tokens of each field, in the order of `doc.fields`.
+    tokens: Vec<Vec<String>>,
+    len: usize,
+}
+
+/// Inverted index used by storages without a search engine of their own.
+/// Deleted todos are not indexed.
+#[derive(Default)]
+pub struct SearchIndex {
+    docs: HashMap<DocId, Indexed>,
+    /// Token to the number of its occurrences per document, sorted so prefix
+    /// queries are a range scan.
+    postings: BTreeMap<String, HashMap<DocId, u32>>,
+    total_len: usize,
+}
+
+impl SearchIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build an index over everything in a store.
+    pub fn build(notes: &[Note], todos: &[TodoItem]) -> Self {
+        let mut index = Self::new();
+        notes.iter().for_each(|note| index.index_note(note));
+        todos.iter().for_each(|todo| index.index_todo(todo));
+
+        index
+    }
+
+    pub fn index_note(&mut self, note: &Note) {
+        self.insert(Document::from_note(note));
+    }
+
+    /// Index a todo, or drop it from the index once it's deleted.
+    pub fn index_todo(&mut self, todo_item: &TodoItem) {
+        match todo_item.deleted_at {
+            Some(_) => self.remove(DocKind::Todo, todo_item.id),
+            None => self.insert(Document::from_todo(todo_item)),
+        }
+    }
+
+    /// Add a document, replacing an earlier version of it.
+    pub fn insert(&mut self, doc: Document) {
+        let doc_id = (doc.kind, doc.id);
+        self.remove(doc.kind, doc.id);
+
+        let tokens: Vec<Vec<String>> = doc
+            .fields
+            .iter()
+            .map(|(_, text)| tokenize(text).map(|(_, token)| token).collect())
+            .collect();
+        for token in tokens.iter().flatten() {
+            *self
+                .postings
+                .entry(token.clone())
+                .or_default()
+                .entry(doc_id)
+                .or_default() += 1;
+        }
+        let len = tokens.iter().map(Vec::len).sum();
+        self.total_len += len;
+
+        self.docs.insert(doc_id, Indexed { doc, tokens, len });
+    }
+
+    pub fn remove(&mut self, kind: DocKind, id: Uuid) {
+        let Some(indexed) = self.docs.remove(&(kind, id)) else {
+            return;
+        };
+
+        self.total_len -= indexed.len;
+        for token in indexed.tokens.iter().flatten() {
+            if let Some(docs) = self.postings.get_mut(token) {
+                docs.remove(&(kind, id));
+                if docs.is_empty() {
+                    self.postings.remove(token);
+                }
+            }
+        }
+    }
+
+    /// Documents matching every term of the query, best first.
+    pub fn search(&self, query: &SearchQuery) -> Vec<SearchHit> {
+        let doc_count = self.docs.len() as f64;
+        let avg_len = self.total_len as f64 / doc_count.max(1.0);
+
+        let mut scores: Option<HashMap<DocId, f64>> = None;
+        for term in &query.terms {
+            let frequencies = self.term_frequencies(term);
+            let df = frequencies.len() as f64;
+            let idf = (1.0 + (doc_count - df + 0.5) / (df + 0.5)).ln();
+
+            let term_scores = frequencies.into_iter().map(|(doc_id, tf)| {
+                let tf = f64::from(tf);
+                let len = self.docs[&doc_id].len as f64;
+                let score = idf * tf * (K1 + 1.0) / (tf + K1 * (1.0 - B + B * len / avg_len));
+                (doc_id, score)
+            });
+            scores = Some(match scores {
+                None => term_scores.collect(),
+                Some(mut scores) => {
+                    let term_scores: HashMap<_, _> = term_scores.collect();
+                    scores.retain(|doc_id, _| term_scores.contains_key(doc_id));
+                    scores
+                        .iter_mut()
+                        .for_each(|(doc_id, score)| *score += term_scores[doc_id]);
+                    scores
+                }
+            });
+        }
+
+        let mut hits: Vec<_> = scores
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(doc_id, score)| (&self.docs[&doc_id].doc, score))
+            .filter(|(doc, _)| query.kind.is_none_or(|kind| doc.kind == kind))
+            .filter(|(doc, _)| {
+                query
+                    .project_dir
+                    .as_ref()
+                    .is_none_or(|dir| &doc.project_dir == dir)
+            })
+            .filter(|(doc, _)| query.tag.as_ref().is_none_or(|tag| doc.tags.contains(tag)))
+            .collect();
+        hits.sort_by(|(a, a_score), (b, b_score)| {
+            b_score
+                .total_cmp(a_score)
+                .then((a.kind, a.id).cmp(&(b.kind, b.id)))
+        });
+
+        hits.into_iter()
+            .take(query.limit)
+            .map(|(doc, score)| SearchHit {
+                kind: doc.kind,
+                id: doc.id,
+                score,
+                snippets: snippets(&doc.fields, &query.terms),
+            })
+            .collect()
+    }
+
+    /// Occurrences of a term per document which contains it.
+    fn term_frequencies(&self, term: &QueryTerm) -> HashMap<DocId, u32> {
+        match term {
+            QueryTerm::Word(word) => self.postings.get(word).cloned().unwrap_or_default(),
+            QueryTerm::Prefix(prefix) => {
+                let mut frequencies = HashMap::new();
+                for (_, docs) in self
+                    .postings
+                    .range(prefix.clone()..)
+                    .take_while(|(token, _)| token.starts_with(prefix.as_str()))
+                {
+                    for (doc_id, tf) in docs {
+                        *frequencies.entry(*doc_id).or_default() += tf;
+                    }
+                }
+                frequencies
+            }
+            QueryTerm::Phrase(words) => {
+                // only documents with every word can contain the phrase
+                let mut candidates: Option<HashSet<DocId>> = None;
+                for word in words {
+                    let docs: HashSet<_> = self
+                        .postings
+                        .get(word)
+                        .map(|docs| docs.keys().copied().collect())
+                        .unwrap_or_default();
+                    candidates = Some(match candidates {
+                        None => docs,
+                        Some(candidates) => &candidates & &docs,
+                    });
+                }
+
+                candidates
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|doc_id| {
+                        let count: usize = self.docs[&doc_id]
+                            .tokens
+                            .iter()
+                            .map(|tokens| phrase_starts(tokens, words).count())
+                            .sum();
+                        (count > 0).then_some((doc_id, count as u32))
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Indices in `tokens` where `words` occur in sequence.
+fn phrase_starts<'a, S: AsRef<str>>(
+    tokens: &'a [S],
+    words: &'a [String],
+) -> impl Iterator<Item = usize> + 'a {
+    tokens
+        .windows(words.len().max(1))
+        .enumerate()
+        .filter(move |(_, window)| {
+            !words.is_empty() && window.iter().zip(words).all(|(t, w)| t.as_ref() == w)
+        })
+        .map(|(start, _)| start)
+}
+
+/// A highlighted excerpt of every field with a match, centred on its first
+/// match.
+pub fn snippets(fields: &[(SearchField, String)], terms: &[QueryTerm]) -> Vec<Snippet> {
+    fields
+        .iter()
+        .filter_map(|(field, text)| snippet(*field, text, terms))
+        .collect()
+}
+
+fn snippet(field: SearchField, text: &str, terms: &[QueryTerm]) -> Option<Snippet> {
+    let tokens: Vec<_> = tokenize(text).collect();
+    let words: Vec<_> = tokens.iter().map(|(_, token)| token.as_str()).collect();
+
+    let mut matched = vec![false; tokens.len()];
+    for term in terms {
+        match term {
+            QueryTerm::Phrase(phrase) if phrase.len() > 1 => {
+                for start in phrase_starts(&words, phrase) {
+                    matched[start..start + phrase.len()].fill(true);
+                }
+            }
+            term => {
+                for (i, word) in words.iter().enumerate() {
+                    matched[i] |= term.matches_token(word);
+                }
+            }
+        }
+    }
+
+    let first = matched.iter().position(|m| *m)?;
+    let start_token = first.saturating_sub(SNIPPET_CONTEXT);
+    let end_token = (start_token + SNIPPET_TOKENS).min(tokens.len());
+    let start = if start_token == 0 {
+        0
+    } else {
+        tokens[start_token].0.0
+    };
+    let end = if end_token == tokens.len() {
+        text.len()
+    } else {
+        tokens[end_token - 1].0.1
+    };
+
+    let prefix = if start > 0 { "…" } else { "" };
+    let suffix = if end < text.len() { "…" } else { "" };
+    let highlights = (start_token..end_token)
+        .filter(|i| matched[*i])
+        .map(|i| {
+            let (from, to) = tokens[i].0;
+            (from - start + prefix.len(), to - start + prefix.len())
+        })
+        .collect();
+
+    Some(Snippet {
+        field,
+        text: format!("{prefix}{}{suffix}", &text[start..end]),
+        highlights,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use crate::{
+        fixtures,
+        search::SearchIndex,
+        types::{
+            Note,
+            search::{DocKind, SearchField, SearchQuery},
+            todo::TodoItem,
+        },
+    };
+
+    fn query(text: &str) -> SearchQuery {
+        SearchQuery {
+            terms: SearchQuery::parse_terms(text),
+            kind: None,
+            project_dir: None,
+            tag: None,
+            limit: SearchQuery::DEFAULT_LIMIT,
+        }
+    }
+
+    fn note(selection: &str, content: &str) -> Note {
+        Note::new(fixtures::new_note("src/lib.rs", selection, content))
+    }
+
+    fn todo(content: &str) -> TodoItem {
+        TodoItem::new(fixtures::new_todo("src/lib.rs", 1, content))
+    }
+
+    #[test]
+    fn words_phrases_and_prefixes_match() {
+        let parser = note(
+            "fn parse_header(input: &str)",
+            "The parser chokes on empty headers",
+        );
+        let other = note("fn main() {", "Entry point, parses the arguments");
+        let index = SearchIndex::build(&[parser.clone(), other.clone()], &[]);
+
+        let ids = |text| -> Vec<_> { index.search(&query(text)).iter().map(|h| h.id).collect() };
+        assert_eq!(ids("chokes"), vec![parser.id]);
+        assert_eq!(ids("\"empty headers\""), vec![parser.id]);
+        assert!(ids("\"headers empty\"").is_empty());
+        assert_eq!(ids("pars*").len(), 2);
+        assert_eq!(ids("parse_header"), vec![parser.id]);
+        assert!(ids("parser arguments").is_empty());
+    }
+
+    #[test]
+    fn snippets_highlight_matches() {
+        let todo = todo("TODO: handle the empty case before returning");
+        let index = SearchIndex::build(&[], std::slice::from_ref(&todo));
+
+        let hits = index.search(&query("empty"));
+
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].kind, DocKind::Todo);
+        let snippet = &hits[0].snippets[0];
+        assert_eq!(snippet.field, SearchField::Content);
+        let (start, end) = snippet.highlights[0];
+        assert_eq!(&snippet.text[start..end], "empty");
+    }
+
+    #[test]
+    fn ranking_prefers_denser_matches_and_deletes_are_dropped() {
+        let mut weak = todo("TODO: cache the lookup table somewhere, maybe in a lazy static");
+        let strong = todo("TODO: cache cache");
+        let mut index = SearchIndex::build(&[], &[weak.clone(), strong.clone()]);
+
+        let hits = index.search(&query("cache"));
+        assert_eq!(hits[0].id, strong.id);
+        assert_eq!(hits.len(), 2);
+
+        weak.deleted_at = Some(Utc::now());
+        index.index_todo(&weak);
+        assert_eq!(index.search(&query("cache")).len(), 1);
+        assert!(index.search(&query("lookup")).is_empty());
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::BTreeMap;
+
+use crate::types::todo::{NewTodoItem, TodoItem};
+
+/// The changes needed to bring the stored todos in line with a fresh scan.
+#[derive(Debug, Default)]
+pub struct Reconciliation {
+    /// Scanned todos without a stored counterpart.
+    pub added: Vec<NewTodoItem>,
+    /// Stored todos which no longer appear in the scan.
+    pub removed: Vec<TodoItem>,
+    /// Stored todos whose content survived but moved to the given line.
+    pub moved: Vec<(TodoItem, u64)>,
+    /// Stored todos found at the exact same place.
+    pub unchanged: Vec<TodoItem>,
+}
+
+/// Match a scan against the stored (not deleted) todos of the same scope.
+///
+/// Todos are matched on their content hash (see [TodoItem::compute_hash]),
+/// which covers the file and the normalised content. When a file holds several
+/// todos with the same content, the ones still on their stored line are kept
+/// first and the rest are paired up in line order.
+pub fn reconcile(stored: Vec<TodoItem>, scanned: Vec<NewTodoItem>) -> Reconciliation {
+    let mut stored_by_hash: BTreeMap<_, Vec<TodoItem>> = BTreeMap::new();
+    for todo in stored {
+        stored_by_hash
+            .entry(todo.hash.clone())
+            .or_default()
+            .push(todo);
+    }
+
+    let mut scanned_by_hash: BTreeMap<_, Vec<NewTodoItem>> = BTreeMap::new();
+    for todo in scanned {
+        scanned_by_hash
+            .entry(TodoItem::compute_hash(&todo.file_path, &todo.content))
+            .or_default()
+            .push(todo);
+    }
+
+    let mut reconciliation = Reconciliation::default();
+    for (hash, mut scanned) in scanned_by_hash {
+        let mut candidates = stored_by_hash.remove(&hash).unwrap_or_default();
+
+        scanned.retain(|new_todo| {
+            match candidates
+                .iter()
+                .position(|todo| todo.line_number == new_todo.line_number)
+            {
+                Some(pos) => {
+                    reconciliation.unchanged.push(candidates.remove(pos));
+                    false
+                }
+                None => true,
+            }
+        });
+
+        scanned.sort_by_key(|todo| todo.line_number);
+        candidates.sort_by_key(|todo| todo.line_number);
+        let mut candidates = candidates.into_iter();
+        for new_todo in scanned {
+            match candidates.next() {
+                Some(todo) => reconciliation.moved.push((todo, new_todo.line_number)),
+                None => reconciliation.added.push(new_todo),
+            }
+        }
+        reconciliation.removed.extend(candidates);
+    }
+    reconciliation
+        .removed
+        .extend(stored_by_hash.into_values().flatten());
+
+    reconciliation
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{fixtures, sync::reconcile, types::todo::TodoItem};
+
+    fn stored_todo(file_path: &str, line_number: u64, content: &str) -> TodoItem {
+        TodoItem::new(fixtures::new_todo(file_path, line_number, content))
+    }
+
+    #[test]
+    fn moved_todos_keep_their_identity() {
+        let stored = vec![
+            stored_todo("src/lib.rs", 10, "TODO: handle errors"),
+            stored_todo("src/lib.rs", 20, "FIXME: off by one"),
+            stored_todo("src/main.rs", 5, "TODO: remove me"),
+        ];
+        let ids: Vec<_> = stored.iter().map(|t| t.id).collect();
+        let scanned = vec![
+            fixtures::new_todo("src/lib.rs", 10, "TODO: handle errors"),
+            fixtures::new_todo("src/lib.rs", 24, "FIXME: off by one"),
+            fixtures::new_todo("src/lib.rs", 30, "HACK: new one"),
+        ];
+
+        let result = reconcile(stored, scanned);
+
+        assert_eq!(result.unchanged.len(), 1);
+        assert_eq!(result.unchanged[0].id, ids[0]);
+        assert_eq!(result.moved.len(), 1);
+        assert_eq!(result.moved[0].0.id, ids[1]);
+        assert_eq!(result.moved[0].1, 24);
+        assert_eq!(result.added.len(), 1);
+        assert_eq!(result.added[0].content, "HACK: new one");
+        assert_eq!(result.removed.len(), 1);
+        assert_eq!(result.removed[0].id, ids[2]);
+    }
+
+    #[test]
+    fn whitespace_changes_do_not_break_identity() {
+        let stored = vec![stored_todo("src/lib.rs", 3, "TODO:  tidy   this up")];
+        let scanned = vec![fixtures::new_todo("src/lib.rs", 3, "TODO: tidy this up ")];
+
+        let result = reconcile(stored, scanned);
+
+        assert_eq!(result.unchanged.len(), 1);
+        assert!(result.added.is_empty());
+    }
+
+    #[test]
+    fn duplicate_contents_prefer_exact_lines() {
+        let stored = vec![
+            stored_todo("src/lib.rs", 3, "TODO"),
+            stored_todo("src/lib.rs", 8, "TODO"),
+        ];
+        let ids: Vec<_> = stored.iter().map(|t| t.id).collect();
+        let scanned = vec![
+            fixtures::new_todo("src/lib.rs", 1, "TODO"),
+            fixtures::new_todo("src/lib.rs", 8, "TODO"),
+        ];
+
+        let result = reconcile(stored, scanned);
+
+        assert_eq!(result.unchanged[0].id, ids[1]);
+        assert_eq!(result.moved[0].0.id, ids[0]);
+        assert_eq!(result.moved[0].1, 1);
+        assert!(result.added.is_empty());
+        assert!(result.removed.is_empty());
+    }
+}
@@ -1,7 +1,7 @@
-use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, ReadHalf, WriteHalf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf};
 
 use crate::{
-    jsonrpc::{JsonRpcRequest, JsonRpcResponse},
+    jsonrpc::{JsonRpcMessage, JsonRpcRequest},
     transport::AsyncStream,
 };
 
@@ -26,18 +26,20 @@ pub trait Codec<Req, Res>: Copy + Send + 'static {
 #[derive(Debug, Clone, Copy)]
 pub struct JsonRpcCodec;
 
-impl Codec<JsonRpcRequest, JsonRpcResponse> for JsonRpcCodec {
+impl Codec<JsonRpcRequest, JsonRpcMessage> for JsonRpcCodec {
     fn decode(&self, bytes: &[u8]) -> Result<JsonRpcRequest, anyhow::Error> {
         serde_json::from_slice(bytes).map_err(|e| anyhow::anyhow!(e))
     }
 
-    fn encode(&self, res: &JsonRpcResponse) -> Result<Vec<u8>, anyhow::Error> {
+    fn encode(&self, res: &JsonRpcMessage) -> Result<Vec<u8>, anyhow::Error> {
         serde_json::to_vec(res).map_err(|e| anyhow::anyhow!(e))
     }
 }
 
 pub struct LengthDelimited<S> {
-    reader: BufReader<ReadHalf<S>>,
+    reader: ReadHalf<S>,
+    /// Bytes read but not yet returned as a frame.
+    buffer: Vec<u8>,
     writer: WriteHalf<S>,
 }
 
@@ -49,10 +51,53 @@ where
         let (read, write) = tokio::io::split(stream);
 
         Self {
-            reader: BufReader::new(read),
+            reader: read,
+            buffer: Vec::new(),
             writer: write,
         }
     }
+
+    /// Take the first complete frame off the buffer, if it holds one.
+    fn take_frame(&mut self) -> std::io::Result<Option<String>> {
+        use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+
+        // the header ends with an empty line, which may also be its only line
+        let header_len = if self.buffer.starts_with(b"\r\n") {
+            2
+        } else {
+            match self.buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+                Some(end) => end + 4,
+                None => return Ok(None),
+            }
+        };
+        let header = String::from_utf8_lossy(&self.buffer[..header_len]);
+
+        let content_length: usize = header
+            .lines()
+            .find_map(|l| {
+                if l.to_ascii_lowercase().starts_with("content-length") {
+                    l.split(':').nth(1).and_then(|s| s.trim().parse().ok())
+                } else {
+                    None
+                }
+            })
+            .ok_or_else(|| {
+                IoError::new(IoErrorKind::InvalidData, "Missing Content-Length header")
+            })?;
+
+        if self.buffer.len() < header_len + content_length {
+            return Ok(None);
+        }
+        let frame: Vec<u8> = self
+            .buffer
+            .drain(..header_len + content_length)
+            .skip(header_len)
+            .collect();
+
+        String::from_utf8(frame)
+            .map(Some)
+            .map_err(|_| IoError::new(IoErrorKind::InvalidData, "Invalid UTF-8 message"))
+    }
 }
 
 #[async_trait::async_trait]
@@ -68,44 +113,24 @@ where
     ///
     /// Expects a header section ending with and empty line (i.e. "\r\n") and then
     /// reads the message body based on the Content-Length header.
+    ///
+    /// Reading is cancel safe: bytes are buffered until a whole message
+    /// arrived, so a read interrupted to write a notification loses nothing.
     async fn read_frame(&mut self) -> std::io::Result<String> {
         use std::io::{Error as IoError, ErrorKind as IoErrorKind};
 
-        let mut header = String::new();
         loop {
-            let mut line = String::new();
-            let bytes_read = self.reader.read_line(&mut line).await?;
-            if bytes_read == 0 {
+            if let Some(frame) = self.take_frame()? {
+                return Ok(frame);
+            }
+
+            if self.reader.read_buf(&mut self.buffer).await? == 0 {
                 return Err(IoError::new(
                     IoErrorKind::UnexpectedEof,
                     "Connection closed",
                 ));
             }
-
-            if line.as_str() == "\r\n" {
-                break;
-            }
-            header.push_str(&line);
         }
-
-        let content_length = header
-            .lines()
-            .find_map(|l| {
-                if l.to_ascii_lowercase().starts_with("content-length") {
-                    l.split(':').nth(1).and_then(|s| s.trim().parse().ok())
-                } else {
-                    None
-                }
-            })
-            .ok_or_else(|| {
-                IoError::new(IoErrorKind::InvalidData, "Missing Content-Length header")
-            })?;
-
-        let mut buffer = vec![0u8; content_length];
-        self.reader.read_exact(&mut buffer).await?;
-
-        String::from_utf8(buffer)
-            .map_err(|_| IoError::new(IoErrorKind::InvalidData, "Invalid UTF-8 message"))
     }
 
     /// Write a single JSON-RPC message to the writer.
@@ -123,3 +148,110 @@ where
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncWriteExt, DuplexStream, duplex};
+
+    use crate::transport::codec::{Framer, LengthDelimited};
+
+    fn framer() -> (LengthDelimited<DuplexStream>, DuplexStream) {
+        let (client, server) = duplex(1024);
+        (LengthDelimited::new(server), client)
+    }
+
+    /// Write `chunks` one at a time, each arriving as a read of its own.
+    async fn send(
+        framer: &mut LengthDelimited<DuplexStream>,
+        client: &mut DuplexStream,
+        chunks: &[&str],
+    ) {
+        for chunk in chunks {
+            client.write_all(chunk.as_bytes()).await.unwrap();
+            client.flush().await.unwrap();
+            // the framer has nothing to return before the last chunk
+            assert!(framer.take_frame().unwrap().is_none());
+            tokio::io::AsyncReadExt::read_buf(&mut framer.reader, &mut framer.buffer)
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn header_split_across_reads() {
+        let (mut framer, mut client) = framer();
+
+        send(
+            &mut framer,
+            &mut client,
+            &["Content-Le", "ngth: 2\r", "\n\r"],
+        )
+        .await;
+        client.write_all(b"\n{}").await.unwrap();
+
+        assert_eq!(framer.read_frame().await.unwrap(), "{}");
+    }
+
+    #[tokio::test]
+    async fn body_split_across_reads() {
+        let (mut framer, mut client) = framer();
+
+        send(
+            &mut framer,
+            &mut client,
+            &["Content-Length: 12\r\n\r\n{\"a\":", "\"bc"],
+        )
+        .await;
+        client.write_all(b"de\"}").await.unwrap();
+
+        assert_eq!(framer.read_frame().await.unwrap(), r#"{"a":"bcde"}"#);
+    }
+
+    #[tokio::test]
+    async fn two_frames_in_one_read() {
+        let (mut framer, mut client) = framer();
+
+        client
+            .write_all(b"Content-Length: 2\r\n\r\n{}Content-Length: 4\r\n\r\n[42]")
+            .await
+            .unwrap();
+
+        assert_eq!(framer.read_frame().await.unwrap(), "{}");
+        assert_eq!(framer.read_frame().await.unwrap(), "[42]");
+        assert!(framer.buffer.is_empty());
+    }
+
+    #[tokio::test]
+    async fn missing_or_invalid_content_length_is_refused() {
+        for header in [
+            "Content-Type: application/json\r\n\r\n{}",
+            "Content-Length: two\r\n\r\n{}",
+            "\r\n{}",
+        ] {
+            let (mut framer, mut client) = framer();
+            client.write_all(header.as_bytes()).await.unwrap();
+
+            let err = framer.read_frame().await.unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData, "{header:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn cancelled_reads_lose_nothing() {
+        let (mut framer, mut client) = framer();
+
+        client
+            .write_all(b"Content-Length: 7\r\n\r\n[1,")
+            .await
+            .unwrap();
+        tokio::select! {
+            frame = framer.read_frame() => panic!("read a partial frame: {frame:?}"),
+            _ = tokio::time::sleep(Duration::from_millis(50)) => {}
+        }
+        client.write_all(b"2,3]").await.unwrap();
+
+        assert_eq!(framer.read_frame().await.unwrap(), "[1,2,3]");
+    }
+}
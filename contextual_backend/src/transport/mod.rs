@@ -1,7 +1,7 @@
 use tokio::io::{AsyncRead, AsyncWrite};
 
 use crate::{
-    jsonrpc::{JsonRpcRequest, JsonRpcResponse, ResponseError},
+    jsonrpc::{JsonRpcMessage, JsonRpcRequest, JsonRpcResponse, ResponseError},
     router::{RouterFactory, RouterService},
     service::Service,
     transport::codec::{Codec, Framer},
@@ -22,7 +22,7 @@ pub trait Transport {
     type Stream: AsyncStream + Send + 'static;
     type Framer: Framer<Self::Stream> + Send + 'static;
 
-    fn start<C: Codec<JsonRpcRequest, JsonRpcResponse>>(
+    fn start<C: Codec<JsonRpcRequest, JsonRpcMessage>>(
         self,
         server: RouterFactory,
         codec: C,
@@ -32,7 +32,7 @@ pub trait Transport {
 pub struct Server<T, C>
 where
     T: Transport,
-    C: Codec<JsonRpcRequest, JsonRpcResponse>,
+    C: Codec<JsonRpcRequest, JsonRpcMessage>,
 {
     transport: T,
     codec: C,
@@ -41,7 +41,7 @@ where
 impl<T, C> Server<T, C>
 where
     T: Transport,
-    C: Codec<JsonRpcRequest, JsonRpcResponse>,
+    C: Codec<JsonRpcRequest, JsonRpcMessage>,
 {
     pub fn new(transport: T, codec: C) -> Self {
         Self { transport, codec }
@@ -63,14 +63,30 @@ pub async fn handle_client<S, F, C>(
 where
     S: AsyncStream,
     F: Framer<S>,
-    C: Codec<JsonRpcRequest, JsonRpcResponse>,
+    C: Codec<JsonRpcRequest, JsonRpcMessage>,
 {
+    let mut notifications_open = true;
     loop {
-        let message = match framer.read_frame().await {
-            Ok(msg) => msg,
-            Err(e) => {
-                eprintln!("Error reading frame: {e}");
-                break;
+        let message = tokio::select! {
+            frame = framer.read_frame() => match frame {
+                Ok(msg) => msg,
+                Err(e) => {
+                    eprintln!("Error reading frame: {e}");
+                    break;
+                }
+            },
+            notification = server.next_notification(), if notifications_open => {
+                match notification {
+                    Some(notification) => {
+                        let notification = codec.encode(&notification.into())?;
+                        if let Err(e) = framer.write_frame(&notification).await {
+                            eprintln!("Error writing frame: {e}");
+                            break;
+                        }
+                    }
+                    None => notifications_open = false,
+                }
+                continue;
             }
         };
 
@@ -83,14 +99,11 @@ where
             },
             Err(e) => JsonRpcResponse::from_error(
                 0,
-                ResponseError {
-                    code: -32700,
-                    message: format!("Parse error: {e}"),
-                },
+                ResponseError::new(ResponseError::PARSE_ERROR, format!("Parse error: {e}")),
             ),
         };
 
-        let response = codec.encode(&response)?;
+        let response = codec.encode(&response.into())?;
         if let Err(e) = framer.write_frame(&response).await {
             eprintln!("Error writing frame: {e}");
             break;
@@ -1,7 +1,7 @@
 use tokio::io::{AsyncRead, AsyncWrite, Stdin, Stdout};
 
 use crate::{
-    jsonrpc::{JsonRpcRequest, JsonRpcResponse},
+    jsonrpc::{JsonRpcMessage, JsonRpcRequest},
     router::RouterFactory,
     transport::{
         Transport,
@@ -16,7 +16,7 @@ impl Transport for StdIoTransport {
     type Stream = CombinedStream<Stdin, Stdout>;
     type Framer = LengthDelimited<Self::Stream>;
 
-    async fn start<C: Codec<JsonRpcRequest, JsonRpcResponse> + Send>(
+    async fn start<C: Codec<JsonRpcRequest, JsonRpcMessage> + Send>(
         self,
         server: RouterFactory,
         codec: C,
@@ -1,5 +1,5 @@
 use crate::{
-    jsonrpc::{JsonRpcRequest, JsonRpcResponse},
+    jsonrpc::{JsonRpcMessage, JsonRpcRequest},
     router::RouterFactory,
     transport::{
         Transport,
@@ -32,7 +32,7 @@ impl Transport for TcpTransport {
     type Stream = tokio::net::TcpStream;
     type Framer = LengthDelimited<Self::Stream>;
 
-    async fn start<C: Codec<JsonRpcRequest, JsonRpcResponse> + 'static>(
+    async fn start<C: Codec<JsonRpcRequest, JsonRpcMessage> + 'static>(
         self,
         server: RouterFactory,
         codec: C,
@@ -1,7 +1,7 @@
 use std::path::PathBuf;
 
 use crate::{
-    jsonrpc::{JsonRpcRequest, JsonRpcResponse},
+    jsonrpc::{JsonRpcMessage, JsonRpcRequest},
     router::RouterFactory,
     transport::{
         Transport,
@@ -32,7 +32,7 @@ impl Transport for UnixTransport {
     type Stream = tokio::net::UnixStream;
     type Framer = LengthDelimited<Self::Stream>;
 
-    async fn start<C: Codec<JsonRpcRequest, JsonRpcResponse>>(
+    async fn start<C: Codec<JsonRpcRequest, JsonRpcMessage>>(
         self,
         server: RouterFactory,
         codec: C,
@@ -0,0 +1,13 @@
+use serde::{Deserialize, Serialize};
+
+/// The repository state a note or todo was created in, read by the backend
+/// rather than reported by the editor.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct GitState {
+    /// `None` on a detached HEAD.
+    pub branch: Option<String>,
+    /// The HEAD commit, `None` before the first commit.
+    pub commit: Option<String>,
+    /// Whether the file had uncommitted changes or was untracked.
+    pub dirty: bool,
+}
@@ -0,0 +1,152 @@
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::scan;
+
+/// What a todo's marker says beyond its kind, written in the conventional
+/// forms `TODO(alice): ...`, `FIXME[#123]`, `TODO(P1)` and
+/// `TODO @2026-12-01`, which may be combined: `TODO(alice, P1)[#12] @2026-12-01:`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TodoMarker {
+    pub owner: Option<String>,
+    /// An issue reference as written, `#123` or `PROJ-123`.
+    pub issue: Option<String>,
+    /// `P0` is the most urgent.
+    pub priority: Option<u8>,
+    pub due: Option<NaiveDate>,
+}
+
+impl TodoMarker {
+    /// Parse the marker of kind `kind` in `content`. A marker which isn't in
+    /// `content`, as for placeholders, parses to an empty marker.
+    pub fn parse(content: &str, kind: &str) -> Self {
+        let mut marker = Self::default();
+        let Some(start) = scan::find_word(content, kind) else {
+            return marker;
+        };
+
+        let mut rest = &content[start + kind.len()..];
+        loop {
+            if let Some((group, after)) = group(rest) {
+                for item in group.split(',') {
+                    marker.read_item(item.trim());
+                }
+                rest = after;
+            } else if let Some((due, after)) = rest.trim_start().strip_prefix('@').and_then(date) {
+                marker.due.get_or_insert(due);
+                rest = after;
+            } else {
+                return marker;
+            }
+        }
+    }
+
+    /// Read one comma separated item of a `(...)` or `[...]` group, the first
+    /// item of each sort wins.
+    fn read_item(&mut self, item: &str) {
+        if let Some(priority) = parse_priority(item) {
+            self.priority.get_or_insert(priority);
+        } else if is_issue(item) {
+            self.issue.get_or_insert_with(|| item.to_string());
+        } else if let Some((due, "")) = date(item.strip_prefix('@').unwrap_or(item)) {
+            self.due.get_or_insert(due);
+        } else if let Some(owner) = parse_owner(item) {
+            self.owner.get_or_insert_with(|| owner.to_string());
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The contents of the `(...)` or `[...]` group `text` starts with, and the
+/// text after it.
+fn group(text: &str) -> Option<(&str, &str)> {
+    let close = match text.chars().next()? {
+        '(' => ')',
+        '[' => ']',
+        _ => return None,
+    };
+    let end = text.find(close)?;
+
+    Some((&text[1..end], &text[end + 1..]))
+}
+
+/// The `YYYY-MM-DD` date `text` starts with, and the text after it.
+fn date(text: &str) -> Option<(NaiveDate, &str)> {
+    let date = text.get(..10)?;
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+
+    Some((date, &text[10..]))
+}
+
+/// `P1` or `p1`.
+fn parse_priority(item: &str) -> Option<u8> {
+    let level = item.strip_prefix(['P', 'p'])?;
+    if level.is_empty() || !level.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    level.parse().ok()
+}
+
+/// `#123`, or a tracker key like `PROJ-123`.
+fn is_issue(item: &str) -> bool {
+    let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    if let Some(number) = item.strip_prefix('#') {
+        return is_number(number);
+    }
+
+    item.split_once('-').is_some_and(|(project, number)| {
+        project.starts_with(|c: char| c.is_ascii_uppercase())
+            && project
+                .bytes()
+                .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit())
+            && is_number(number)
+    })
+}
+
+/// `alice` or `@alice`: a name without spaces.
+fn parse_owner(item: &str) -> Option<&str> {
+    let owner = item.strip_prefix('@').unwrap_or(item);
+    let valid = owner.starts_with(char::is_alphanumeric)
+        && owner
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'));
+
+    valid.then_some(owner)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use crate::types::marker::TodoMarker;
+
+    #[test]
+    fn conventional_markers_are_parsed() {
+        let date = |s| NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap();
+
+        let marker = TodoMarker::parse("// TODO(alice): cache this", "TODO");
+        assert_eq!(marker.owner.as_deref(), Some("alice"));
+
+        let marker = TodoMarker::parse("# FIXME[#123] crashes on empty input", "FIXME");
+        assert_eq!(marker.issue.as_deref(), Some("#123"));
+
+        let marker = TodoMarker::parse("-- TODO(P1, @bob)[PROJ-7] @2026-12-01: ship", "TODO");
+        assert_eq!(
+            marker,
+            TodoMarker {
+                owner: Some("bob".to_string()),
+                issue: Some("PROJ-7".to_string()),
+                priority: Some(1),
+                due: Some(date("2026-12-01")),
+            }
+        );
+
+        // only what directly follows the marker counts
+        assert!(TodoMarker::parse("// TODO: ask (alice) about P1", "TODO").is_empty());
+        assert!(TodoMarker::parse("todo!(\"later\")", "todo!").is_empty());
+    }
+}
@@ -1,15 +1,105 @@
+pub mod git;
+pub mod marker;
 pub mod note;
+pub mod position;
+pub mod search;
+pub mod tag;
 pub mod todo;
 
 pub use note::*;
 
 use anyhow::Context;
+use chrono::{DateTime, Utc};
 use serde_json::Value as JsonValue;
+use uuid::Uuid;
+
+/// Context of an error caused by one field of the params, naming the field.
+#[derive(Debug)]
+pub struct FieldError {
+    pub field: String,
+    message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: impl Into<String>) -> Self {
+        Self {
+            field: field.to_string(),
+            message: message.into(),
+        }
+    }
+
+    /// The field of the params which caused `error`, if it is known.
+    pub fn field_of(error: &anyhow::Error) -> Option<&str> {
+        error
+            .downcast_ref::<Self>()
+            .map(|error| error.field.as_str())
+    }
+}
+
+impl std::fmt::Display for FieldError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for FieldError {}
 
 fn get_str(value: &JsonValue, key: &str) -> Result<String, anyhow::Error> {
     value
         .get(key)
         .and_then(|v| v.as_str())
-        .context("{key} is required")
+        .with_context(|| FieldError::new(key, format!("{key} is required")))
         .map(String::from)
 }
+
+fn get_uuid(value: &JsonValue, key: &str) -> Result<Uuid, anyhow::Error> {
+    get_str(value, key)
+        .and_then(|id| Uuid::parse_str(&id).with_context(|| FieldError::new(key, "invalid uuid")))
+}
+
+fn get_opt_str(value: &JsonValue, key: &str) -> Option<String> {
+    value.get(key).and_then(|v| v.as_str()).map(String::from)
+}
+
+/// Read an unsigned integer which may also be sent as a numeric string.
+fn get_u64(value: &JsonValue, key: &str) -> Result<u64, anyhow::Error> {
+    match value.get(key) {
+        Some(JsonValue::Number(n)) => n
+            .as_u64()
+            .with_context(|| FieldError::new(key, format!("{key} must be positive"))),
+        Some(JsonValue::String(s)) => s
+            .parse()
+            .with_context(|| FieldError::new(key, format!("failed to parse {key}"))),
+        _ => Err(FieldError::new(key, format!("{key} is required")).into()),
+    }
+}
+
+pub(crate) fn get_opt_u64(value: &JsonValue, key: &str) -> Result<Option<u64>, anyhow::Error> {
+    match value.get(key) {
+        None | Some(JsonValue::Null) => Ok(None),
+        Some(_) => get_u64(value, key).map(Some),
+    }
+}
+
+fn get_opt_bool(value: &JsonValue, key: &str) -> Result<Option<bool>, anyhow::Error> {
+    match value.get(key) {
+        None | Some(JsonValue::Null) => Ok(None),
+        Some(v) => v
+            .as_bool()
+            .with_context(|| FieldError::new(key, format!("{key} must be a boolean")))
+            .map(Some),
+    }
+}
+
+/// Read an optional RFC 3339 timestamp.
+fn get_opt_datetime(value: &JsonValue, key: &str) -> Result<Option<DateTime<Utc>>, anyhow::Error> {
+    get_opt_str(value, key)
+        .map(|s| {
+            DateTime::parse_from_rfc3339(&s)
+                .map(|dt| dt.with_timezone(&Utc))
+                .with_context(|| {
+                    FieldError::new(key, format!("{key} must be an RFC 3339 timestamp"))
+                })
+        })
+        .transpose()
+}
@@ -3,13 +3,31 @@ use serde::{Deserialize, Serialize};
 use serde_json::{Map as JsonMap, Value as JsonValue};
 use uuid::Uuid;
 
-use crate::types::get_str;
+use crate::types::{
+    get_opt_bool, get_opt_str, get_opt_u64, get_str, get_uuid,
+    git::GitState,
+    position::{PositionEncoding, get_opt_position_encoding},
+    tag::{Tags, get_opt_tag, get_opt_tags},
+};
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
 pub struct NoteContext {
     pub filename: String,
     pub project_dir: String,
     pub selection: String,
+    /// Zero-based lines the selection spans, `end_line` inclusive.
+    pub start_line: Option<u64>,
+    pub end_line: Option<u64>,
+    /// Zero-based columns on `start_line` and `end_line`, `end_column`
+    /// exclusive as in LSP ranges.
+    pub start_column: Option<u64>,
+    pub end_column: Option<u64>,
+    /// What the columns count, they are kept as the client sent them.
+    pub position_encoding: PositionEncoding,
+    /// Lines around the selection when it was last located, used to find it
+    /// again after it changed.
+    pub context_before: Vec<String>,
+    pub context_after: Vec<String>,
 }
 
 impl TryFrom<JsonMap<String, JsonValue>> for NoteContext {
@@ -34,19 +52,62 @@ impl TryFrom<JsonMap<String, JsonValue>> for NoteContext {
             .context("Selection required")?
             .to_owned();
 
+        let value = JsonValue::Object(value);
+        let get_lines = |key| -> Result<Vec<String>, anyhow::Error> {
+            match value.get(key) {
+                None | Some(JsonValue::Null) => Ok(Vec::new()),
+                Some(lines) => serde_json::from_value(lines.clone())
+                    .with_context(|| format!("{key} must be an array of lines")),
+            }
+        };
+
+        let start_line = get_opt_u64(&value, "start_line")?;
+        let end_line = get_opt_u64(&value, "end_line")?;
+        let start_column = get_opt_u64(&value, "start_column")?;
+        let end_column = get_opt_u64(&value, "end_column")?;
+        match (start_line, end_line) {
+            (Some(start), Some(end)) if end < start => {
+                anyhow::bail!("end_line must not be before start_line")
+            }
+            (Some(start), Some(end))
+                if start == end
+                    && matches!((start_column, end_column), (Some(s), Some(e)) if e < s) =>
+            {
+                anyhow::bail!("end_column must not be before start_column")
+            }
+            (None, _) if start_column.is_some() => {
+                anyhow::bail!("start_column requires start_line")
+            }
+            (_, None) if end_column.is_some() => anyhow::bail!("end_column requires end_line"),
+            _ => {}
+        }
+
         Ok(Self {
             filename,
             project_dir,
             selection,
+            start_line,
+            end_line,
+            start_column,
+            end_column,
+            position_encoding: get_opt_position_encoding(&value, "position_encoding")?
+                .unwrap_or_default(),
+            context_before: get_lines("context_before")?,
+            context_after: get_lines("context_after")?,
         })
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct Note {
     pub id: Uuid,
     pub context: NoteContext,
     pub content: String,
+    pub tags: Tags,
+    /// Set when re-anchoring could not find the selection anymore.
+    pub orphaned: bool,
+    /// The repository state when the note was created.
+    pub git: Option<GitState>,
 }
 
 impl Note {
@@ -57,6 +118,9 @@ impl Note {
             id,
             context: new_note.context,
             content: new_note.content,
+            tags: new_note.tags,
+            orphaned: false,
+            git: new_note.git,
         }
     }
 }
@@ -65,6 +129,9 @@ impl Note {
 pub struct NewNote {
     pub context: NoteContext,
     pub content: String,
+    pub tags: Tags,
+    /// Read from the repository by the backend, never sent by clients.
+    pub git: Option<GitState>,
 }
 
 impl TryFrom<JsonValue> for NewNote {
@@ -78,7 +145,198 @@ impl TryFrom<JsonValue> for NewNote {
             .context("Invalid context")?
             .try_into()?;
         let content = get_str(&value, "content")?;
+        let tags = get_opt_tags(&value, "tags")?.unwrap_or_default();
+
+        Ok(Self {
+            context,
+            content,
+            tags,
+            git: None,
+        })
+    }
+}
+
+/// Parameters for requests which address a single note.
+#[derive(Debug)]
+pub struct NoteIdParams {
+    pub id: Uuid,
+}
+
+impl TryFrom<JsonValue> for NoteIdParams {
+    type Error = anyhow::Error;
+
+    fn try_from(value: JsonValue) -> Result<Self, Self::Error> {
+        let id = get_uuid(&value, "id")?;
+
+        Ok(Self { id })
+    }
+}
+
+/// Changes to a stored note, `None` fields are left untouched.
+#[derive(Debug, Clone, Default)]
+pub struct NotePatch {
+    pub content: Option<String>,
+    pub context: Option<NoteContext>,
+    pub add_tags: Tags,
+    pub remove_tags: Tags,
+    pub orphaned: Option<bool>,
+}
+
+impl NotePatch {
+    pub fn apply(self, note: &mut Note) {
+        if let Some(content) = self.content {
+            note.content = content;
+        }
+        if let Some(context) = self.context {
+            note.context = context;
+        }
+        if let Some(orphaned) = self.orphaned {
+            note.orphaned = orphaned;
+        }
+        note.tags.extend(self.add_tags);
+        note.tags.retain(|tag| !self.remove_tags.contains(tag));
+    }
+}
+
+impl TryFrom<JsonValue> for NotePatch {
+    type Error = anyhow::Error;
+
+    fn try_from(value: JsonValue) -> Result<Self, Self::Error> {
+        let context = match value.get("context") {
+            None | Some(JsonValue::Null) => None,
+            Some(context) => Some(
+                context
+                    .as_object()
+                    .cloned()
+                    .context("Invalid context")?
+                    .try_into()?,
+            ),
+        };
+
+        Ok(Self {
+            content: get_opt_str(&value, "content"),
+            context,
+            add_tags: get_opt_tags(&value, "add_tags")?.unwrap_or_default(),
+            remove_tags: get_opt_tags(&value, "remove_tags")?.unwrap_or_default(),
+            orphaned: get_opt_bool(&value, "orphaned")?,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct UpdateNoteParams {
+    pub id: Uuid,
+    pub patch: NotePatch,
+}
+
+impl TryFrom<JsonValue> for UpdateNoteParams {
+    type Error = anyhow::Error;
+
+    fn try_from(value: JsonValue) -> Result<Self, Self::Error> {
+        let id = get_uuid(&value, "id")?;
+        let patch = value.try_into()?;
 
-        Ok(Self { context, content })
+        Ok(Self { id, patch })
     }
 }
+
+/// Optional filters for listing notes, all notes are returned when no filter
+/// is given.
+#[derive(Debug, Default)]
+pub struct ListNotesParams {
+    pub project_dir: Option<String>,
+    pub filename: Option<String>,
+    pub tag: Option<String>,
+    /// A commit, branch or tag: only notes created at that commit or one of
+    /// its ancestors, as if the project was checked out there. Applied by the
+    /// service since it needs the repository.
+    pub as_of: Option<String>,
+}
+
+impl ListNotesParams {
+    pub fn matches(&self, note: &Note) -> bool {
+        self.project_dir
+            .as_ref()
+            .is_none_or(|dir| *dir == note.context.project_dir)
+            && self
+                .filename
+                .as_ref()
+                .is_none_or(|filename| *filename == note.context.filename)
+            && self.tag.as_ref().is_none_or(|tag| note.tags.contains(tag))
+    }
+}
+
+impl TryFrom<JsonValue> for ListNotesParams {
+    type Error = anyhow::Error;
+
+    fn try_from(value: JsonValue) -> Result<Self, Self::Error> {
+        Ok(Self {
+            project_dir: get_opt_str(&value, "project_dir"),
+            filename: get_opt_str(&value, "filename"),
+            tag: get_opt_tag(&value, "tag")?,
+            as_of: get_opt_str(&value, "as_of"),
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct NoteList {
+    pub notes: Vec<Note>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeletedNote {
+    pub id: Uuid,
+}
+
+/// Params of `contextual/note/reanchor`: the current contents of a file whose
+/// notes should be located again.
+#[derive(Debug)]
+pub struct ReanchorParams {
+    pub project_dir: String,
+    pub filename: String,
+    pub contents: String,
+}
+
+impl TryFrom<JsonValue> for ReanchorParams {
+    type Error = anyhow::Error;
+
+    fn try_from(value: JsonValue) -> Result<Self, Self::Error> {
+        Ok(Self {
+            project_dir: get_str(&value, "project_dir")?,
+            filename: get_str(&value, "filename")?,
+            contents: get_str(&value, "contents")?,
+        })
+    }
+}
+
+/// How a note was found when re-anchoring it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnchorStatus {
+    /// The selection occurs verbatim in the file.
+    Exact,
+    /// Lines similar to the selection and its context were found.
+    Fuzzy,
+    /// Nothing similar enough is left, the stored position is kept.
+    Orphaned,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReanchoredNote {
+    pub id: Uuid,
+    pub status: AnchorStatus,
+    /// From 0 to 1, how sure we are the note points at the right place.
+    pub confidence: f64,
+    /// The stored position, the previous one for orphaned notes.
+    pub start_line: Option<u64>,
+    pub end_line: Option<u64>,
+    pub start_column: Option<u64>,
+    pub end_column: Option<u64>,
+    pub position_encoding: PositionEncoding,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReanchorReport {
+    pub notes: Vec<ReanchoredNote>,
+}
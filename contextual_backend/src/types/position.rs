@@ -0,0 +1,157 @@
+use std::str::FromStr;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+/// What a column counts, as negotiated through LSP's `positionEncoding`.
+///
+/// Columns are zero-based offsets into their line, counted in bytes of
+/// UTF-8, UTF-16 code units or code points.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum PositionEncoding {
+    #[serde(rename = "utf-8")]
+    Utf8,
+    /// The LSP default, used when a client doesn't say otherwise.
+    #[default]
+    #[serde(rename = "utf-16")]
+    Utf16,
+    #[serde(rename = "utf-32")]
+    Utf32,
+}
+
+impl PositionEncoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PositionEncoding::Utf8 => "utf-8",
+            PositionEncoding::Utf16 => "utf-16",
+            PositionEncoding::Utf32 => "utf-32",
+        }
+    }
+
+    /// The column right after `prefix`, the text of a line up to a position.
+    pub fn column(&self, prefix: &str) -> u64 {
+        let units = match self {
+            PositionEncoding::Utf8 => prefix.len(),
+            PositionEncoding::Utf16 => prefix.encode_utf16().count(),
+            PositionEncoding::Utf32 => prefix.chars().count(),
+        };
+
+        units as u64
+    }
+}
+
+impl FromStr for PositionEncoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "utf-8" => Ok(PositionEncoding::Utf8),
+            "utf-16" => Ok(PositionEncoding::Utf16),
+            "utf-32" => Ok(PositionEncoding::Utf32),
+            other => anyhow::bail!("unknown position encoding: {other}"),
+        }
+    }
+}
+
+/// Params of `contextual/initialize`, shaped like LSP's `initialize` so
+/// editors can reuse their LSP client capabilities.
+#[derive(Debug, Default)]
+pub struct InitializeParams {
+    /// `capabilities.general.positionEncodings`, in the client's order of
+    /// preference. Unknown encodings are ignored.
+    pub position_encodings: Vec<PositionEncoding>,
+}
+
+impl TryFrom<JsonValue> for InitializeParams {
+    type Error = anyhow::Error;
+
+    fn try_from(value: JsonValue) -> Result<Self, Self::Error> {
+        let encodings = match value.pointer("/capabilities/general/positionEncodings") {
+            None | Some(JsonValue::Null) => Vec::new(),
+            Some(encodings) => encodings
+                .as_array()
+                .context("positionEncodings must be an array")?
+                .iter()
+                .filter_map(|encoding| encoding.as_str()?.parse().ok())
+                .collect(),
+        };
+
+        Ok(Self {
+            position_encodings: encodings,
+        })
+    }
+}
+
+impl InitializeParams {
+    /// The client's most preferred encoding, or UTF-16 when it offers none we
+    /// support, as LSP requires.
+    pub fn position_encoding(&self) -> PositionEncoding {
+        self.position_encodings.first().copied().unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InitializeResult {
+    pub capabilities: ServerCapabilities,
+    pub server_info: ServerInfo,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerCapabilities {
+    /// The encoding the client should use for columns it sends and expect in
+    /// columns it receives.
+    pub position_encoding: PositionEncoding,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ServerInfo {
+    pub name: &'static str,
+    pub version: &'static str,
+}
+
+/// Read the optional `position_encoding` of positions sent by a client.
+pub(crate) fn get_opt_position_encoding(
+    value: &JsonValue,
+    key: &str,
+) -> Result<Option<PositionEncoding>, anyhow::Error> {
+    match value.get(key) {
+        None | Some(JsonValue::Null) => Ok(None),
+        Some(encoding) => encoding
+            .as_str()
+            .with_context(|| format!("{key} must be a string"))?
+            .parse()
+            .map(Some),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::types::position::{InitializeParams, PositionEncoding};
+
+    #[test]
+    fn columns_are_counted_in_the_negotiated_encoding() {
+        let line = "let s = \"héllo 🦀\"; x";
+        let prefix = &line[..line.find('x').unwrap()];
+
+        assert_eq!(PositionEncoding::Utf8.column(prefix), 23);
+        assert_eq!(PositionEncoding::Utf16.column(prefix), 20);
+        assert_eq!(PositionEncoding::Utf32.column(prefix), 19);
+    }
+
+    #[test]
+    fn client_preference_is_honoured() {
+        let params = InitializeParams::try_from(json!({
+            "capabilities": { "general": { "positionEncodings": ["utf-7", "utf-8", "utf-16"] } }
+        }))
+        .unwrap();
+        assert_eq!(params.position_encoding(), PositionEncoding::Utf8);
+
+        let params = InitializeParams::try_from(json!({})).unwrap();
+        assert_eq!(params.position_encoding(), PositionEncoding::Utf16);
+    }
+}
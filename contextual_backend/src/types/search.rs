@@ -0,0 +1,194 @@
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use unicode_normalization::{UnicodeNormalization, char::is_combining_mark};
+use uuid::Uuid;
+
+use crate::types::{get_opt_str, get_opt_u64, get_str, tag::get_opt_tag};
+
+/// The kind of record a search hit refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DocKind {
+    Note,
+    Todo,
+}
+
+impl DocKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DocKind::Note => "note",
+            DocKind::Todo => "todo",
+        }
+    }
+}
+
+/// The searchable text fields of notes and todos.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchField {
+    /// `Note::content` or `TodoItem::content`.
+    Content,
+    /// `NoteContext::selection`.
+    Selection,
+}
+
+/// A single part of a search query, every term has to match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryTerm {
+    Word(String),
+    /// `word*`: any token starting with the prefix.
+    Prefix(String),
+    /// `"several words"`: the tokens in this order, next to each other.
+    Phrase(Vec<String>),
+}
+
+impl QueryTerm {
+    /// Whether a single token satisfies a word or prefix term.
+    pub fn matches_token(&self, token: &str) -> bool {
+        match self {
+            QueryTerm::Word(word) => token == word,
+            QueryTerm::Prefix(prefix) => token.starts_with(prefix.as_str()),
+            QueryTerm::Phrase(words) => words.len() == 1 && token == words[0],
+        }
+    }
+}
+
+/// Params of `contextual/search`.
+///
+/// `query` holds words, `prefix*` and `"quoted phrases"`; a document has to
+/// match all of them. Matching ignores case and punctuation.
+#[derive(Debug, Clone)]
+pub struct SearchQuery {
+    pub terms: Vec<QueryTerm>,
+    /// Only search notes or only todos.
+    pub kind: Option<DocKind>,
+    pub project_dir: Option<String>,
+    /// Only notes and todos with this tag.
+    pub tag: Option<String>,
+    pub limit: usize,
+}
+
+impl SearchQuery {
+    pub const DEFAULT_LIMIT: usize = 20;
+    pub const MAX_LIMIT: usize = 200;
+
+    /// Parse a query string of words, `prefix*` and `"quoted phrases"`.
+    pub fn parse_terms(query: &str) -> Vec<QueryTerm> {
+        let mut terms = Vec::new();
+        for (i, part) in query.split('"').enumerate() {
+            // every other part is inside quotes, an unbalanced quote phrases
+            // the rest of the query
+            if i % 2 == 1 {
+                let words: Vec<_> = tokenize(part).map(|(_, token)| token).collect();
+                if !words.is_empty() {
+                    terms.push(QueryTerm::Phrase(words));
+                }
+                continue;
+            }
+
+            for word in part.split_whitespace() {
+                let (word, is_prefix) = match word.strip_suffix('*') {
+                    Some(word) => (word, true),
+                    None => (word, false),
+                };
+                let mut tokens: Vec<_> = tokenize(word).map(|(_, token)| token).collect();
+                match tokens.len() {
+                    0 => {}
+                    1 if is_prefix => terms.push(QueryTerm::Prefix(tokens.remove(0))),
+                    1 => terms.push(QueryTerm::Word(tokens.remove(0))),
+                    // `foo-bar` is searched as the phrase "foo bar"
+                    _ => terms.push(QueryTerm::Phrase(tokens)),
+                }
+            }
+        }
+
+        terms
+    }
+}
+
+impl TryFrom<JsonValue> for SearchQuery {
+    type Error = anyhow::Error;
+
+    fn try_from(value: JsonValue) -> Result<Self, Self::Error> {
+        let terms = Self::parse_terms(&get_str(&value, "query")?);
+        if terms.is_empty() {
+            anyhow::bail!("query has no searchable words");
+        }
+        let kind = match get_opt_str(&value, "kind").as_deref() {
+            None => None,
+            Some("note") => Some(DocKind::Note),
+            Some("todo") => Some(DocKind::Todo),
+            Some(other) => anyhow::bail!("unknown kind: {other}"),
+        };
+        let limit = match get_opt_u64(&value, "limit")? {
+            Some(0) => anyhow::bail!("limit must be at least 1"),
+            Some(limit) => usize::try_from(limit)?.min(Self::MAX_LIMIT),
+            None => Self::DEFAULT_LIMIT,
+        };
+
+        Ok(Self {
+            terms,
+            kind,
+            project_dir: get_opt_str(&value, "project_dir"),
+            tag: get_opt_tag(&value, "tag")?,
+            limit,
+        })
+    }
+}
+
+/// Split text into alphanumeric tokens along with their byte offsets in
+/// `text`. Tokens are lowercase and without diacritics, as with the
+/// `unicode61` tokenizer of SQLite, so that `cafe` matches `Café`.
+pub fn tokenize(text: &str) -> impl Iterator<Item = ((usize, usize), String)> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(move |token| {
+            let start = token.as_ptr() as usize - text.as_ptr() as usize;
+            let folded: String = token.nfd().filter(|c| !is_combining_mark(*c)).collect();
+            ((start, start + token.len()), folded.to_lowercase())
+        })
+}
+
+/// An excerpt of a matching field, `highlights` are the byte ranges of the
+/// matched words within `text`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Snippet {
+    pub field: SearchField,
+    pub text: String,
+    pub highlights: Vec<(usize, usize)>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    pub kind: DocKind,
+    pub id: Uuid,
+    /// Higher is better, only comparable within one response.
+    pub score: f64,
+    pub snippets: Vec<Snippet>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResults {
+    pub hits: Vec<SearchHit>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::search::{QueryTerm, SearchQuery};
+
+    #[test]
+    fn query_terms_are_parsed() {
+        let terms = SearchQuery::parse_terms(r#"Parser err* "entry point" foo-bar"#);
+
+        assert_eq!(
+            terms,
+            vec![
+                QueryTerm::Word("parser".to_string()),
+                QueryTerm::Prefix("err".to_string()),
+                QueryTerm::Phrase(vec!["entry".to_string(), "point".to_string()]),
+                QueryTerm::Phrase(vec!["foo".to_string(), "bar".to_string()]),
+            ]
+        );
+        assert!(SearchQuery::parse_terms(r#" * "" -- "#).is_empty());
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::BTreeSet;
+
+use anyhow::Context;
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use uuid::Uuid;
+
+use crate::types::{FieldError, get_opt_str, get_uuid, search::DocKind};
+
+pub type Tags = BTreeSet<String>;
+
+/// Normalise a tag to its stored form: lowercase, without a leading `#`.
+/// Tags start with a letter and may contain letters, digits, `-`, `_` and
+/// `/`.
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = tag.trim();
+    let tag = tag.strip_prefix('#').unwrap_or(tag);
+
+    let mut chars = tag.chars();
+    let valid = chars.next().is_some_and(char::is_alphabetic)
+        && chars.all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '/'));
+
+    valid.then(|| tag.to_lowercase())
+}
+
+/// Tags written inline as `#tag`, e.g. `TODO(perf): cache this #perf`.
+///
+/// A tag has to follow whitespace (or start the text) and start with a
+/// letter, which leaves issue references like `#123` and attributes like
+/// `#[test]` alone.
+pub fn extract_tags(content: &str) -> Tags {
+    content
+        .split_whitespace()
+        .filter(|word| word.starts_with('#'))
+        .filter_map(|word| {
+            // trailing punctuation ends the tag: `#perf.` or `#perf,`
+            let word = word.trim_end_matches(|c: char| !c.is_alphanumeric());
+            normalize_tag(word)
+        })
+        .collect()
+}
+
+/// Read an optional array of tags, `Ok(None)` when `key` is absent.
+pub(crate) fn get_opt_tags(value: &JsonValue, key: &str) -> Result<Option<Tags>, anyhow::Error> {
+    let tags = match value.get(key) {
+        None | Some(JsonValue::Null) => return Ok(None),
+        Some(tags) => tags
+            .as_array()
+            .with_context(|| FieldError::new(key, format!("{key} must be an array of tags")))?,
+    };
+
+    tags.iter()
+        .map(|tag| {
+            let tag = tag
+                .as_str()
+                .with_context(|| FieldError::new(key, format!("{key} must be an array of tags")))?;
+            normalize_tag(tag).with_context(|| FieldError::new(key, format!("invalid tag: {tag}")))
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+/// Read an optional single tag, used to filter by tag.
+pub(crate) fn get_opt_tag(value: &JsonValue, key: &str) -> Result<Option<String>, anyhow::Error> {
+    get_opt_str(value, key)
+        .map(|tag| {
+            normalize_tag(&tag).with_context(|| FieldError::new(key, format!("invalid tag: {tag}")))
+        })
+        .transpose()
+}
+
+/// Params of `contextual/tags/add` and `contextual/tags/remove`.
+#[derive(Debug)]
+pub struct TagParams {
+    pub kind: DocKind,
+    pub id: Uuid,
+    pub tags: Tags,
+}
+
+impl TryFrom<JsonValue> for TagParams {
+    type Error = anyhow::Error;
+
+    fn try_from(value: JsonValue) -> Result<Self, Self::Error> {
+        let kind = match get_opt_str(&value, "kind").as_deref() {
+            Some("note") => DocKind::Note,
+            Some("todo") => DocKind::Todo,
+            Some(other) => anyhow::bail!("unknown kind: {other}"),
+            None => anyhow::bail!("kind is required"),
+        };
+        let id = get_uuid(&value, "id")?;
+        let tags = get_opt_tags(&value, "tags")?.context("tags is required")?;
+
+        Ok(Self { kind, id, tags })
+    }
+}
+
+/// Params of `contextual/tags/list`.
+#[derive(Debug, Default)]
+pub struct ListTagsParams {
+    pub project_dir: Option<String>,
+}
+
+impl TryFrom<JsonValue> for ListTagsParams {
+    type Error = anyhow::Error;
+
+    fn try_from(value: JsonValue) -> Result<Self, Self::Error> {
+        Ok(Self {
+            project_dir: get_opt_str(&value, "project_dir"),
+        })
+    }
+}
+
+/// How often a tag is used on notes and on active todos.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub notes: usize,
+    pub todos: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagList {
+    pub tags: Vec<TagCount>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::tag::{extract_tags, normalize_tag};
+
+    #[test]
+    fn inline_tags_are_extracted() {
+        let tags = extract_tags("TODO: cache lookups #perf, see #123 and #[cfg] #API/v2.");
+
+        assert_eq!(
+            tags.into_iter().collect::<Vec<_>>(),
+            vec!["api/v2".to_string(), "perf".to_string()]
+        );
+        assert_eq!(normalize_tag(" #Perf "), Some("perf".to_string()));
+        assert_eq!(normalize_tag("two words"), None);
+    }
+}
@@ -1,37 +1,84 @@
+use std::cmp::Ordering;
+
 use anyhow::Context;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
+use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
-use crate::types::get_str;
+use crate::{
+    scan::{self, ScanConfig},
+    types::{
+        FieldError, get_opt_bool, get_opt_datetime, get_opt_str, get_opt_u64, get_str, get_u64,
+        git::GitState,
+        marker::TodoMarker,
+        tag::{Tags, extract_tags, get_opt_tag},
+    },
+};
 
-#[allow(unused)]
-pub struct NewTodoItems(Vec<NewTodoItem>);
+/// A batch of todos: the valid ones, and why each of the others is invalid.
+#[derive(Debug, Default)]
+pub struct NewTodoItems {
+    pub todos: Vec<NewTodoItem>,
+    pub errors: Vec<TodoItemError>,
+}
 
-#[derive(Debug)]
+impl From<Vec<NewTodoItem>> for NewTodoItems {
+    fn from(todos: Vec<NewTodoItem>) -> Self {
+        Self {
+            todos,
+            errors: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct NewTodoItem {
+    pub project_dir: String,
+    /// Replaced by the checked out branch when `project_dir` is in a git
+    /// repository, only required outside of one.
     pub branch: String,
     pub file_path: String,
     pub line_number: u64,
+    /// See [TodoItem::end_line_number].
+    pub end_line_number: u64,
     pub content: String,
+    /// What marks the todo, see [TodoItem::kind].
+    pub kind: String,
+    /// Read from the repository by the backend, never sent by clients.
+    pub git: Option<GitState>,
 }
 
 impl TryFrom<JsonValue> for NewTodoItem {
     type Error = anyhow::Error;
 
     fn try_from(value: JsonValue) -> Result<Self, Self::Error> {
-        let branch = get_str(&value, "branch")?;
+        let project_dir = get_str(&value, "project_dir")?;
+        let branch = get_opt_str(&value, "branch").unwrap_or_default();
         let file_path = get_str(&value, "file_path")?;
-        let line_number = get_str(&value, "line_number")
-            .and_then(|l| l.parse().context("failed to parse line_number"))?;
+        let line_number = get_u64(&value, "line_number")?;
+        let end_line_number = get_opt_u64(&value, "end_line_number")?.unwrap_or(line_number);
+        if end_line_number < line_number {
+            return Err(FieldError::new(
+                "end_line_number",
+                "end_line_number must not be before line_number",
+            )
+            .into());
+        }
         let content = get_str(&value, "content")?;
+        let kind =
+            get_opt_str(&value, "kind").unwrap_or_else(|| TodoItem::kind_from_content(&content));
 
         Ok(Self {
+            project_dir,
             branch,
             file_path,
             line_number,
+            end_line_number,
             content,
+            kind,
+            git: None,
         })
     }
 }
@@ -39,45 +86,587 @@ impl TryFrom<JsonValue> for NewTodoItem {
 impl TryFrom<JsonValue> for NewTodoItems {
     type Error = anyhow::Error;
 
+    /// Validate every todo, keeping an error for each invalid one rather than
+    /// leaving it out.
+    fn try_from(value: JsonValue) -> Result<Self, Self::Error> {
+        let values = match value {
+            JsonValue::Array(values) => values,
+            _ => anyhow::bail!("params is expected to be an array"),
+        };
+
+        let mut todo_items = NewTodoItems::default();
+        for (index, value) in values.into_iter().enumerate() {
+            let file_path = get_opt_str(&value, "file_path");
+            let todo_item = match value.is_object() {
+                true => NewTodoItem::try_from(value),
+                false => Err(anyhow::anyhow!("todo must be an object")),
+            };
+            match todo_item {
+                Ok(todo_item) => todo_items.todos.push(todo_item),
+                Err(e) => todo_items.errors.push(TodoItemError {
+                    index,
+                    file_path,
+                    field: FieldError::field_of(&e).map(String::from),
+                    message: format!("{e:#}"),
+                }),
+            }
+        }
+
+        Ok(todo_items)
+    }
+}
+
+/// Why the todo at `index` of a batch is invalid.
+#[derive(Debug, Clone, Serialize)]
+pub struct TodoItemError {
+    pub index: usize,
+    /// The file the todo is in, when that much of it is readable.
+    pub file_path: Option<String>,
+    /// The offending field, unless the todo as a whole is invalid.
+    pub field: Option<String>,
+    pub message: String,
+}
+
+/// A full scan of a project's todos on one branch.
+///
+/// Entries in `todos` inherit `project_dir` and `branch` from the envelope, so
+/// clients only have to send the scanned `file_path`, `line_number` and
+/// `content`, plus `end_line_number` for todos spanning several lines.
+#[derive(Debug)]
+pub struct SyncTodosParams {
+    pub project_dir: String,
+    pub branch: String,
+    pub todos: NewTodoItems,
+}
+
+impl TryFrom<JsonValue> for SyncTodosParams {
+    type Error = anyhow::Error;
+
+    fn try_from(value: JsonValue) -> Result<Self, Self::Error> {
+        let project_dir = get_str(&value, "project_dir")?;
+        let branch = get_opt_str(&value, "branch").unwrap_or_default();
+        let todos = get_todos(
+            &value,
+            &[("project_dir", &project_dir), ("branch", &branch)],
+        )?;
+
+        Ok(Self {
+            todos,
+            project_dir,
+            branch,
+        })
+    }
+}
+
+/// Read the `todos` array, where each todo defaults the given fields to the
+/// values of the request.
+fn get_todos(
+    value: &JsonValue,
+    defaults: &[(&str, &String)],
+) -> Result<NewTodoItems, anyhow::Error> {
+    let todos = value
+        .get("todos")
+        .and_then(|t| t.as_array())
+        .context("todos is expected to be an array")?
+        .iter()
+        .map(|todo| {
+            let mut todo = todo.clone();
+            if let Some(obj) = todo.as_object_mut() {
+                for (key, default) in defaults {
+                    obj.entry(*key).or_insert_with(|| (*default).clone().into());
+                }
+            }
+            todo
+        })
+        .collect::<Vec<_>>();
+
+    JsonValue::Array(todos).try_into()
+}
+
+/// Params of `contextual/sync_file_todos`: reconcile the todos of one file
+/// with the stored todos, leaving the rest of the project alone.
+#[derive(Debug)]
+pub struct SyncFileTodosParams {
+    pub project_dir: String,
+    /// Only needed outside of a git repository, see [NewTodoItem::branch].
+    pub branch: String,
+    /// Relative to `project_dir`, an absolute path within it is made
+    /// relative.
+    pub file_path: String,
+    pub source: FileTodos,
+}
+
+/// Where the todos of a file synced on its own come from.
+#[derive(Debug)]
+pub enum FileTodos {
+    /// Scanned by the client, every todo must be in the synced file.
+    Todos(NewTodoItems),
+    /// The current contents of the file, scanned by the backend with the
+    /// overrides of its configured scan settings.
+    Contents { text: String, config: ScanConfig },
+}
+
+impl TryFrom<JsonValue> for SyncFileTodosParams {
+    type Error = anyhow::Error;
+
+    fn try_from(value: JsonValue) -> Result<Self, Self::Error> {
+        let project_dir = get_str(&value, "project_dir")?;
+        let branch = get_opt_str(&value, "branch").unwrap_or_default();
+        let file_path = get_str(&value, "file_path")?;
+        let file_path = match file_path.starts_with('/') {
+            true => {
+                let path = std::path::Path::new(&file_path);
+                if !path.starts_with(&project_dir) {
+                    anyhow::bail!("file_path must be within project_dir");
+                }
+                scan::relative_path(std::path::Path::new(&project_dir), path)
+            }
+            false => file_path,
+        };
+        if file_path.is_empty() {
+            anyhow::bail!("file_path must name a file");
+        }
+
+        let source = match (value.get("todos"), get_opt_str(&value, "contents")) {
+            (Some(_), None) => {
+                let todos = get_todos(
+                    &value,
+                    &[
+                        ("project_dir", &project_dir),
+                        ("branch", &branch),
+                        ("file_path", &file_path),
+                    ],
+                )?;
+                if todos.todos.iter().any(|todo| todo.file_path != file_path) {
+                    anyhow::bail!("todos must all be in file_path");
+                }
+                FileTodos::Todos(todos)
+            }
+            (None, Some(text)) => FileTodos::Contents {
+                text,
+                config: ScanConfig::try_from(&value)?,
+            },
+            _ => anyhow::bail!("either todos or contents is required"),
+        };
+
+        Ok(Self {
+            project_dir,
+            branch,
+            file_path,
+            source,
+        })
+    }
+}
+
+/// Params of `contextual/scan_todos`: scan a project on the backend, and
+/// optionally reconcile the result with the stored todos like
+/// `contextual/sync_todos` does.
+#[derive(Debug)]
+pub struct ScanTodosParams {
+    pub project_dir: String,
+    /// Only needed outside of a git repository, see [NewTodoItem::branch].
+    pub branch: String,
+    /// Overrides of the backend's configured scan settings.
+    pub config: ScanConfig,
+    pub sync: bool,
+}
+
+impl TryFrom<JsonValue> for ScanTodosParams {
+    type Error = anyhow::Error;
+
+    fn try_from(value: JsonValue) -> Result<Self, Self::Error> {
+        Ok(Self {
+            project_dir: get_str(&value, "project_dir")?,
+            branch: get_opt_str(&value, "branch").unwrap_or_default(),
+            config: ScanConfig::try_from(&value)?,
+            sync: get_opt_bool(&value, "sync")?.unwrap_or(false),
+        })
+    }
+}
+
+/// The todos found by a scan, and how the stored todos changed when the scan
+/// was synced.
+#[derive(Debug, Serialize)]
+pub struct ScannedTodos {
+    pub todos: Vec<NewTodoItem>,
+    pub sync: Option<TodoSyncReport>,
+}
+
+/// Params of `contextual/watch` and `contextual/unwatch`.
+#[derive(Debug)]
+pub struct WatchParams {
+    pub project_dir: String,
+    /// Only needed outside of a git repository, see [NewTodoItem::branch].
+    pub branch: String,
+}
+
+impl TryFrom<JsonValue> for WatchParams {
+    type Error = anyhow::Error;
+
     fn try_from(value: JsonValue) -> Result<Self, Self::Error> {
-        let todo_items = value
-            .as_array()
-            .cloned()
-            .context("params is expected to be an array")?
-            .into_iter()
-            .flat_map(NewTodoItem::try_from)
-            .collect();
+        Ok(Self {
+            project_dir: get_str(&value, "project_dir")?,
+            branch: get_opt_str(&value, "branch").unwrap_or_default(),
+        })
+    }
+}
+
+/// Outcome of reconciling a scan with the stored todos.
+#[derive(Debug, Default, Serialize)]
+pub struct TodoSyncReport {
+    pub added: Vec<Uuid>,
+    pub removed: Vec<Uuid>,
+    pub moved: Vec<MovedTodo>,
+    pub unchanged: Vec<Uuid>,
+    /// The todos of the scan which are invalid and were left out.
+    pub errors: Vec<TodoItemError>,
+}
 
-        Ok(NewTodoItems(todo_items))
+impl TodoSyncReport {
+    /// Whether the reconciliation changed any stored todo.
+    pub fn has_changes(&self) -> bool {
+        !(self.added.is_empty() && self.removed.is_empty() && self.moved.is_empty())
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Serialize)]
+pub struct MovedTodo {
+    pub id: Uuid,
+    pub from_line: u64,
+    pub to_line: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct TodoItem {
     pub id: Uuid,
     pub hash: String,
+    pub project_dir: String,
     pub branch: String,
     pub file_path: String,
     pub line_number: u64,
+    /// The last line of a todo continuing over several comment lines, equal
+    /// to `line_number` for a todo on a single line.
+    pub end_line_number: u64,
     pub content: String,
+    /// What marks the todo: the keyword of a comment like `FIXME`, or a
+    /// placeholder in code like `todo!`.
+    pub kind: String,
+    /// Owner, issue, priority and due date written with the marker.
+    #[serde(flatten)]
+    pub marker: TodoMarker,
     pub created_at: chrono::DateTime<Utc>,
     pub deleted_at: Option<DateTime<Utc>>,
+    /// Tags written inline as `#tag` plus the ones added later.
+    pub tags: Tags,
+    /// The tags added later, which stay when the content changes.
+    pub added_tags: Tags,
+    /// The repository state when the todo was first seen.
+    pub git: Option<GitState>,
 }
 
 impl TodoItem {
     pub fn new(new_todo: NewTodoItem) -> Self {
         let id = Uuid::new_v4();
-        let hash = String::new(); // TODO: compute hash
+        let hash = Self::compute_hash(&new_todo.file_path, &new_todo.content);
+        let tags = extract_tags(&new_todo.content);
+        let marker = TodoMarker::parse(&new_todo.content, &new_todo.kind);
 
         Self {
             id,
             hash,
+            project_dir: new_todo.project_dir,
             branch: new_todo.branch,
             file_path: new_todo.file_path,
             line_number: new_todo.line_number,
+            end_line_number: new_todo.end_line_number,
             content: new_todo.content,
+            kind: new_todo.kind,
+            marker,
             created_at: Utc::now(),
             deleted_at: None,
+            tags,
+            added_tags: Tags::new(),
+            git: new_todo.git,
+        }
+    }
+
+    /// The `tags` of a todo which aren't written inline in its `content`,
+    /// for todos stored before added tags were kept apart.
+    pub fn added_tags_of(tags: &Tags, content: &str) -> Tags {
+        tags.difference(&extract_tags(content)).cloned().collect()
+    }
+
+    /// Stable identity of a todo: a SHA-256 over its file path and its
+    /// whitespace-normalised content.
+    ///
+    /// The branch and line number are deliberately left out so the same todo
+    /// hashes identically across branches, line shifts and machines.
+    pub fn compute_hash(file_path: &str, content: &str) -> String {
+        let normalised = content.split_whitespace().collect::<Vec<_>>().join(" ");
+
+        let mut hasher = Sha256::new();
+        hasher.update(file_path.as_bytes());
+        hasher.update([0]);
+        hasher.update(normalised.as_bytes());
+
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Move the todo to start on `line_number`, keeping its length.
+    pub fn move_to(&mut self, line_number: u64) {
+        let length = self.end_line_number.saturating_sub(self.line_number);
+        self.line_number = line_number;
+        self.end_line_number = line_number + length;
+    }
+
+    /// The kind of a todo whose kind wasn't recorded, guessed from the
+    /// default keywords in its content.
+    pub fn kind_from_content(content: &str) -> String {
+        scan::kind_of(content, &scan::DEFAULT_KEYWORDS)
+            .unwrap_or("TODO")
+            .to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TodoOrder {
+    #[default]
+    CreatedAt,
+    /// By file path, then line number.
+    FilePath,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortDirection {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Filters, ordering and pagination for listing todos.
+///
+/// Pagination is cursor based: a page's `next_cursor` is the id of its last
+/// todo and the following page starts right after that todo in the requested
+/// order, so pages stay stable while todos are added or removed.
+#[derive(Debug, Clone)]
+pub struct TodoQuery {
+    pub project_dir: Option<String>,
+    pub branch: Option<String>,
+    pub file_path_prefix: Option<String>,
+    /// Case-insensitive substring of the content.
+    pub content_contains: Option<String>,
+    /// Only todos with this tag.
+    pub tag: Option<String>,
+    pub kind: Option<String>,
+    pub owner: Option<String>,
+    pub issue: Option<String>,
+    pub priority: Option<u8>,
+    /// Only todos due on or before this day.
+    pub due_before: Option<NaiveDate>,
+    /// Inclusive lower bound of `created_at`.
+    pub created_after: Option<DateTime<Utc>>,
+    /// Exclusive upper bound of `created_at`.
+    pub created_before: Option<DateTime<Utc>>,
+    pub include_deleted: bool,
+    pub order_by: TodoOrder,
+    pub direction: SortDirection,
+    pub cursor: Option<Uuid>,
+    pub limit: usize,
+}
+
+impl TodoQuery {
+    pub const DEFAULT_LIMIT: usize = 100;
+    pub const MAX_LIMIT: usize = 1000;
+
+    pub fn matches(&self, todo: &TodoItem) -> bool {
+        (self.include_deleted || todo.deleted_at.is_none())
+            && self
+                .project_dir
+                .as_ref()
+                .is_none_or(|dir| *dir == todo.project_dir)
+            && self
+                .branch
+                .as_ref()
+                .is_none_or(|branch| *branch == todo.branch)
+            && self
+                .file_path_prefix
+                .as_ref()
+                .is_none_or(|prefix| todo.file_path.starts_with(prefix.as_str()))
+            && self
+                .content_contains
+                .as_ref()
+                .is_none_or(|needle| todo.content.to_lowercase().contains(&needle.to_lowercase()))
+            && self.tag.as_ref().is_none_or(|tag| todo.tags.contains(tag))
+            && self.kind.as_ref().is_none_or(|kind| *kind == todo.kind)
+            && self
+                .owner
+                .as_ref()
+                .is_none_or(|owner| todo.marker.owner.as_ref() == Some(owner))
+            && self
+                .issue
+                .as_ref()
+                .is_none_or(|issue| todo.marker.issue.as_ref() == Some(issue))
+            && self
+                .priority
+                .is_none_or(|priority| todo.marker.priority == Some(priority))
+            && self
+                .due_before
+                .is_none_or(|day| todo.marker.due.is_some_and(|due| due <= day))
+            && self
+                .created_after
+                .is_none_or(|after| todo.created_at >= after)
+            && self
+                .created_before
+                .is_none_or(|before| todo.created_at < before)
+    }
+
+    /// Compare two todos in the requested order, ties are broken by id so the
+    /// order is total.
+    pub fn compare(&self, a: &TodoItem, b: &TodoItem) -> Ordering {
+        let ordering = match self.order_by {
+            TodoOrder::CreatedAt => a.created_at.cmp(&b.created_at),
+            TodoOrder::FilePath => a
+                .file_path
+                .cmp(&b.file_path)
+                .then(a.line_number.cmp(&b.line_number)),
+        }
+        .then(a.id.cmp(&b.id));
+
+        match self.direction {
+            SortDirection::Asc => ordering,
+            SortDirection::Desc => ordering.reverse(),
+        }
+    }
+}
+
+impl Default for TodoQuery {
+    fn default() -> Self {
+        Self {
+            project_dir: None,
+            branch: None,
+            file_path_prefix: None,
+            content_contains: None,
+            tag: None,
+            kind: None,
+            owner: None,
+            issue: None,
+            priority: None,
+            due_before: None,
+            created_after: None,
+            created_before: None,
+            include_deleted: false,
+            order_by: TodoOrder::default(),
+            direction: SortDirection::default(),
+            cursor: None,
+            limit: Self::DEFAULT_LIMIT,
+        }
+    }
+}
+
+impl TryFrom<JsonValue> for TodoQuery {
+    type Error = anyhow::Error;
+
+    fn try_from(value: JsonValue) -> Result<Self, Self::Error> {
+        let order_by = match get_opt_str(&value, "order_by").as_deref() {
+            None | Some("created_at") => TodoOrder::CreatedAt,
+            Some("file_path") => TodoOrder::FilePath,
+            Some(other) => anyhow::bail!("unknown order_by: {other}"),
+        };
+        let direction = match get_opt_str(&value, "direction").as_deref() {
+            None | Some("asc") => SortDirection::Asc,
+            Some("desc") => SortDirection::Desc,
+            Some(other) => anyhow::bail!("unknown direction: {other}"),
+        };
+        let cursor = get_opt_str(&value, "cursor")
+            .map(|c| Uuid::parse_str(&c).context("invalid cursor"))
+            .transpose()?;
+        let limit = match get_opt_u64(&value, "limit")? {
+            Some(0) => anyhow::bail!("limit must be at least 1"),
+            Some(limit) => usize::try_from(limit)?.min(Self::MAX_LIMIT),
+            None => Self::DEFAULT_LIMIT,
+        };
+        let priority = get_opt_u64(&value, "priority")?
+            .map(|p| u8::try_from(p).context("priority is out of range"))
+            .transpose()?;
+        let due_before = get_opt_str(&value, "due_before")
+            .map(|day| {
+                NaiveDate::parse_from_str(&day, "%Y-%m-%d")
+                    .context("due_before must be a YYYY-MM-DD date")
+            })
+            .transpose()?;
+
+        Ok(Self {
+            project_dir: get_opt_str(&value, "project_dir"),
+            branch: get_opt_str(&value, "branch"),
+            file_path_prefix: get_opt_str(&value, "file_path_prefix"),
+            content_contains: get_opt_str(&value, "content_contains"),
+            tag: get_opt_tag(&value, "tag")?,
+            kind: get_opt_str(&value, "kind"),
+            owner: get_opt_str(&value, "owner"),
+            issue: get_opt_str(&value, "issue"),
+            priority,
+            due_before,
+            created_after: get_opt_datetime(&value, "created_after")?,
+            created_before: get_opt_datetime(&value, "created_before")?,
+            include_deleted: get_opt_bool(&value, "include_deleted")?.unwrap_or(false),
+            order_by,
+            direction,
+            cursor,
+            limit,
+        })
+    }
+}
+
+/// A page of todos, `next_cursor` is set when more todos follow.
+#[derive(Debug, Serialize)]
+pub struct TodoPage {
+    pub todos: Vec<TodoItem>,
+    pub next_cursor: Option<Uuid>,
+}
+
+impl TodoPage {
+    /// Build a page from up to `limit + 1` sorted todos, the extra todo only
+    /// signals that there is a next page.
+    pub fn from_overfetched(mut todos: Vec<TodoItem>, limit: usize) -> Self {
+        let has_more = todos.len() > limit;
+        todos.truncate(limit);
+        let next_cursor = has_more.then(|| todos.last().map(|todo| todo.id)).flatten();
+
+        Self { todos, next_cursor }
+    }
+}
+
+/// Changes to a stored todo, `None` fields are left untouched.
+#[derive(Debug, Clone, Default)]
+pub struct TodoPatch {
+    pub line_number: Option<u64>,
+    /// New content, the hash is recomputed when it changes.
+    pub content: Option<String>,
+    pub file_path: Option<String>,
+    pub add_tags: Tags,
+    pub remove_tags: Tags,
+}
+
+impl TodoPatch {
+    pub fn apply(self, todo: &mut TodoItem) {
+        if let Some(line_number) = self.line_number {
+            todo.move_to(line_number);
+        }
+        if let Some(content) = self.content {
+            // inline tags which were edited out go, added tags stay
+            todo.tags = extract_tags(&content);
+            todo.tags.extend(todo.added_tags.iter().cloned());
+            todo.marker = TodoMarker::parse(&content, &todo.kind);
+            todo.content = content;
+        }
+        if let Some(file_path) = self.file_path {
+            todo.file_path = file_path;
         }
+        todo.hash = TodoItem::compute_hash(&todo.file_path, &todo.content);
+        todo.tags.extend(self.add_tags.iter().cloned());
+        todo.added_tags.extend(self.add_tags);
+        todo.tags.retain(|tag| !self.remove_tags.contains(tag));
+        todo.added_tags
+            .retain(|tag| !self.remove_tags.contains(tag));
     }
 }
@@ -0,0 +1,376 @@
+//! Keeping the stored todos of projects up to date while their files change.
+//!
+//! A watched project is scanned once in full, after which every burst of
+//! file changes is rescanned once it settled, only for the changed paths
+//! which a scan doesn't skip. A burst which switched the checked out branch
+//! is rescanned in full instead, as the empty path `""`. Clients are sent a
+//! `contextual/todos_changed` notification whenever that changed any stored
+//! todo, and a `contextual/todos_sync_failed` notification when it failed.
+//!
+//! Only the directories a scan descends into are watched, each on its own,
+//! plus `.git` for its `HEAD`. Directories are watched as they appear.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher as _};
+use serde_json::json;
+use tokio::{
+    sync::mpsc::{self, UnboundedReceiver},
+    task::JoinHandle,
+};
+
+use crate::{
+    database::{Storage, StorageError},
+    git,
+    handlers::Handler,
+    jsonrpc::Notifier,
+    scan::{self, ScanConfig},
+    types::todo::{ScanTodosParams, TodoSyncReport},
+};
+
+/// The notification sent when changes of a watched project changed its todos.
+pub const TODOS_CHANGED: &str = "contextual/todos_changed";
+/// The notification sent when syncing changes of a watched project failed.
+pub const TODOS_SYNC_FAILED: &str = "contextual/todos_sync_failed";
+
+/// The watched projects, shared by every clone.
+#[derive(Clone)]
+pub struct Watcher<DB> {
+    handler: Handler<DB>,
+    notifier: Notifier,
+    debounce: Duration,
+    watches: Arc<Mutex<HashMap<String, Watch>>>,
+}
+
+/// A watched project, which stops being watched when dropped.
+struct Watch {
+    task: JoinHandle<()>,
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl<DB> Watcher<DB>
+where
+    DB: Storage + Clone + Send + Sync + 'static,
+{
+    pub fn new(handler: Handler<DB>, notifier: Notifier, debounce: Duration) -> Self {
+        Self {
+            handler,
+            notifier,
+            debounce,
+            watches: Arc::default(),
+        }
+    }
+
+    /// Sync the todos of `project_dir` with a full scan and keep them synced
+    /// from then on. Watching a project again starts over.
+    ///
+    /// `branch` is only needed outside of a git repository, see
+    /// [crate::types::todo::NewTodoItem::branch].
+    pub async fn watch(
+        &self,
+        project_dir: String,
+        branch: String,
+    ) -> Result<TodoSyncReport, StorageError> {
+        let (sender, changes) = mpsc::unbounded_channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                if let Ok(event) = event
+                    && !matches!(event.kind, EventKind::Access(_))
+                {
+                    // the receiver is only gone once the project isn't watched
+                    let _ = sender.send(event.paths);
+                }
+            })
+            .map_err(watch_error)?;
+        let dir = PathBuf::from(&project_dir);
+        let git_dir = dir.join(".git");
+        let dirs =
+            tokio::task::spawn_blocking(move || scan::visible_dirs(&dir, &[String::new()])).await?;
+        watch_dirs(&mut watcher, &dirs)?;
+        // a worktree's `.git` is a file pointing elsewhere, its HEAD isn't
+        // followed
+        if git_dir.is_dir() {
+            watcher
+                .watch(&git_dir, RecursiveMode::NonRecursive)
+                .map_err(watch_error)?;
+        }
+
+        // changes during the full scan queue up and are rescanned after it
+        let scanned = self
+            .handler
+            .scan_todos(ScanTodosParams {
+                project_dir: project_dir.clone(),
+                branch: branch.clone(),
+                config: ScanConfig::default(),
+                sync: true,
+            })
+            .await?;
+
+        let checked_out = git::current_branch(&project_dir).await;
+        let task = tokio::spawn(self.clone().sync_changes(
+            project_dir.clone(),
+            branch,
+            checked_out,
+            watcher,
+            changes,
+        ));
+        let watch = Watch { task };
+        self.watches.lock().unwrap().insert(project_dir, watch);
+
+        Ok(scanned.sync.unwrap_or_default())
+    }
+
+    /// Stop watching `project_dir`, returning whether it was watched.
+    pub fn unwatch(&self, project_dir: &str) -> bool {
+        self.watches.lock().unwrap().remove(project_dir).is_some()
+    }
+
+    /// Rescan the paths of each burst of `changes` once no change came for
+    /// the debounce duration, or the whole project once `checked_out`, the
+    /// branch checked out when last synced, changed. New directories are
+    /// added to `watcher` before they are rescanned.
+    async fn sync_changes(
+        self,
+        project_dir: String,
+        branch: String,
+        mut checked_out: Option<String>,
+        mut watcher: RecommendedWatcher,
+        mut changes: UnboundedReceiver<Vec<PathBuf>>,
+    ) {
+        while let Some(first) = changes.recv().await {
+            let mut changed: BTreeSet<PathBuf> = first.into_iter().collect();
+            while let Ok(Some(paths)) = tokio::time::timeout(self.debounce, changes.recv()).await {
+                changed.extend(paths);
+            }
+
+            let dir = Path::new(&project_dir);
+            let head_moved = changed.iter().any(|path| path.ends_with(".git/HEAD"));
+            let changed: Vec<String> = changed
+                .iter()
+                .filter(|path| path.starts_with(dir))
+                .map(|path| scan::relative_path(dir, path))
+                .collect();
+            // bursts in ignored directories such as `target/` or `.git/`
+            // don't get as far as asking git for the state of the repository
+            let scan_dir = dir.to_path_buf();
+            let (paths, new_dirs) = tokio::task::spawn_blocking(move || {
+                let paths = scan::visible_paths(&scan_dir, &changed);
+                let new_dirs = scan::visible_dirs(&scan_dir, &paths);
+                (paths, new_dirs)
+            })
+            .await
+            .unwrap_or_default();
+            if paths.is_empty() && !head_moved {
+                continue;
+            }
+            if let Err(e) = watch_dirs(&mut watcher, &new_dirs) {
+                self.sync_failed(&project_dir, &paths, &e);
+            }
+
+            let now_checked_out = git::current_branch(&project_dir).await;
+            let (paths, synced) = if now_checked_out != checked_out {
+                checked_out = now_checked_out;
+                let scanned = self
+                    .handler
+                    .scan_todos(ScanTodosParams {
+                        project_dir: project_dir.clone(),
+                        branch: branch.clone(),
+                        config: ScanConfig::default(),
+                        sync: true,
+                    })
+                    .await;
+                let synced = scanned.map(|scanned| scanned.sync.unwrap_or_default());
+                (vec![String::new()], synced)
+            } else if paths.is_empty() {
+                continue;
+            } else {
+                let synced = self
+                    .handler
+                    .sync_paths(project_dir.clone(), branch.clone(), paths.clone())
+                    .await;
+                (paths, synced)
+            };
+            match synced {
+                Ok(report) if report.has_changes() => self.notifier.notify(
+                    TODOS_CHANGED,
+                    json!({
+                        "project_dir": project_dir,
+                        "paths": paths,
+                        "sync": report,
+                    }),
+                ),
+                Ok(_) => {}
+                Err(e) => self.sync_failed(&project_dir, &paths, &e),
+            }
+        }
+    }
+
+    fn sync_failed(&self, project_dir: &str, paths: &[String], error: &StorageError) {
+        self.notifier.notify(
+            TODOS_SYNC_FAILED,
+            json!({
+                "project_dir": project_dir,
+                "paths": paths,
+                "message": error.to_string(),
+            }),
+        );
+    }
+}
+
+/// Watch each of `dirs` without the directories within it, which are only
+/// watched when a scan descends into them.
+fn watch_dirs(watcher: &mut RecommendedWatcher, dirs: &[PathBuf]) -> Result<(), StorageError> {
+    for dir in dirs {
+        // a directory removed again before it was watched has no changes left
+        if let Err(e) = watcher.watch(dir, RecursiveMode::NonRecursive)
+            && dir.exists()
+        {
+            return Err(watch_error(e));
+        }
+    }
+
+    Ok(())
+}
+
+fn watch_error(error: notify::Error) -> StorageError {
+    StorageError::Invalid(format!("cannot watch: {error}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{sync::broadcast, time::timeout};
+
+    use crate::{
+        database::{TodoStorage, memory::MemoryDatabase},
+        git::run_git as git,
+        handlers::Handler,
+        jsonrpc::{JsonRpcNotification, Notifier},
+        watch::{TODOS_CHANGED, Watcher},
+    };
+
+    async fn next(
+        notifications: &mut broadcast::Receiver<JsonRpcNotification>,
+    ) -> JsonRpcNotification {
+        timeout(Duration::from_secs(10), notifications.recv())
+            .await
+            .expect("no notification")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn changed_files_are_rescanned() {
+        let dir = tempfile::tempdir().unwrap();
+        let project_dir = dir.path().to_str().unwrap().to_string();
+        std::fs::write(dir.path().join("a.lua"), "-- TODO: a\n").unwrap();
+        let storage = MemoryDatabase::new();
+        let notifier = Notifier::new();
+        let mut notifications = notifier.subscribe();
+        let watcher = Watcher::new(
+            Handler::new(storage.clone()),
+            notifier,
+            Duration::from_millis(50),
+        );
+
+        let report = watcher
+            .watch(project_dir.clone(), "main".to_string())
+            .await
+            .unwrap();
+        assert_eq!(report.added.len(), 1);
+
+        std::fs::write(dir.path().join("b.lua"), "-- TODO: b\n").unwrap();
+        let notification = next(&mut notifications).await;
+        assert_eq!(notification.method, TODOS_CHANGED);
+        assert_eq!(notification.params["paths"], serde_json::json!(["b.lua"]));
+        assert_eq!(
+            notification.params["sync"]["added"]
+                .as_array()
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // directories appearing later are watched as well
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        std::fs::write(dir.path().join("sub/c.lua"), "-- TODO: c\n").unwrap();
+        let notification = next(&mut notifications).await;
+        assert_eq!(
+            notification.params["paths"],
+            serde_json::json!(["sub/c.lua"])
+        );
+        std::fs::remove_dir_all(dir.path().join("sub")).unwrap();
+        let notification = next(&mut notifications).await;
+        assert_eq!(
+            notification.params["sync"]["removed"]
+                .as_array()
+                .unwrap()
+                .len(),
+            1
+        );
+
+        std::fs::remove_file(dir.path().join("a.lua")).unwrap();
+        let notification = next(&mut notifications).await;
+        assert_eq!(
+            notification.params["sync"]["removed"]
+                .as_array()
+                .unwrap()
+                .len(),
+            1
+        );
+        let live = storage.get_todos().await.unwrap();
+        let live: Vec<_> = live.iter().filter(|t| t.deleted_at.is_none()).collect();
+        assert_eq!(live.len(), 1);
+        assert_eq!(live[0].file_path, "b.lua");
+
+        assert!(watcher.unwatch(&project_dir));
+        assert!(!watcher.unwatch(&project_dir));
+    }
+
+    #[tokio::test]
+    async fn switching_branches_rescans_the_project() {
+        let dir = tempfile::tempdir().unwrap();
+        let project_dir = dir.path().to_str().unwrap().to_string();
+        git(dir.path(), &["init", "--quiet", "--initial-branch=trunk"]);
+        std::fs::write(dir.path().join(".gitignore"), "target/\n").unwrap();
+        std::fs::write(dir.path().join("a.lua"), "-- TODO: a\n").unwrap();
+        git(dir.path(), &["add", "."]);
+        git(dir.path(), &["commit", "--quiet", "-m", "first"]);
+        let storage = MemoryDatabase::new();
+        let notifier = Notifier::new();
+        let mut notifications = notifier.subscribe();
+        let watcher = Watcher::new(
+            Handler::new(storage.clone()),
+            notifier,
+            Duration::from_millis(50),
+        );
+        watcher
+            .watch(project_dir.clone(), String::new())
+            .await
+            .unwrap();
+
+        // changes in ignored directories add no todos
+        std::fs::create_dir_all(dir.path().join("target")).unwrap();
+        std::fs::write(dir.path().join("target/out.lua"), "-- TODO: out\n").unwrap();
+        git(dir.path(), &["switch", "--quiet", "--create", "feature"]);
+
+        let notification = next(&mut notifications).await;
+        assert_eq!(notification.params["paths"], serde_json::json!([""]));
+        let todos = storage.get_todos().await.unwrap();
+        let mut branches: Vec<_> = todos.iter().map(|todo| todo.branch.as_str()).collect();
+        branches.sort();
+        assert_eq!(branches, vec!["feature", "trunk"]);
+        assert!(todos.iter().all(|todo| todo.file_path == "a.lua"));
+    }
+}
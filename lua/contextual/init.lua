@@ -5,6 +5,15 @@ local M = {}
 M.setup = function(opts)
 	-- TODO: define user configurable options
 	opts = opts or {}
+
+	if opts.sync_todos_on_save then
+		vim.api.nvim_create_autocmd("BufWritePost", {
+			group = vim.api.nvim_create_augroup("contextual_sync_todos", { clear = true }),
+			callback = function(args)
+				M.sync_file_todos(args.buf)
+			end,
+		})
+	end
 end
 
 --- Retrieve the lines and columns of visually highlighted text.
@@ -21,68 +30,65 @@ local get_selection_positions = function()
 	return start_pos[2], start_pos[3], end_pos[2], end_pos[3]
 end
 
-local get_text_selection = function(opts)
+--- Range of the selection, or of the current line outside of visual mode.
+---
+--- Lines and columns are zero-based and the end column is exclusive. Columns
+--- count bytes, which is the `utf-8` position encoding of the backend.
+---@return table
+local get_selection_range = function(opts)
 	if opts.range > 0 then
 		local start_line, start_col, end_line, end_col = get_selection_positions()
-		local lines = vim.api.nvim_buf_get_lines(0, start_line - 1, end_line, false)
-
-		-- handle selection base on line count
-		if #lines == 1 then
-			-- single-line selection
-			lines[1] = lines[1]:sub(start_col, end_col)
-		else
-			-- multi line selection
-			lines[1] = lines[1]:sub(start_col)
-			lines[#lines] = lines[#lines]:sub(1, end_col)
+		local last_line = vim.fn.getline(end_line)
+		-- `'>` points at the first byte of the last character, or past the end
+		-- of the line in linewise mode
+		end_col = math.min(end_col, #last_line)
+		if end_col > 0 then
+			end_col = end_col + vim.str_utf_end(last_line, end_col)
 		end
-		return table.concat(lines, "\n")
-	end
-
-	-- not in visual mode, get current line
-	return vim.fn.getline(".")
-end
 
-local capture_note_ctx = function(opts)
-	local project_directory = vim.lsp.client.root_dir or vim.fn.getcwd()
+		return {
+			start_line = start_line - 1,
+			start_column = start_col - 1,
+			end_line = end_line - 1,
+			end_column = end_col,
+		}
+	end
 
+	local line = vim.fn.line(".")
 	return {
-		text_selection = get_text_selection(opts),
-		filename = vim.fn.expand("%:p"),
-		project_directory = project_directory,
+		start_line = line - 1,
+		start_column = 0,
+		end_line = line - 1,
+		end_column = #vim.fn.getline(line),
 	}
 end
 
-local parse_rg_output = function(output)
-	local todos = {}
-	for line in output:gmatch("[^\r\n]+") do
-		local file, line_num, content = line:match("([^:]+):(%d+):(.*)")
-		if file and line_num and content then
-			table.insert(todos, {
-				file_path = file,
-				line_number = tonumber(line_num),
-				content = content:gsub("^%s+", ""),
-			})
-		end
-	end
+local get_text_selection = function(range)
+	local lines = vim.api.nvim_buf_get_lines(0, range.start_line, range.end_line + 1, false)
 
-	return todos
+	-- cut the last line first, on a single-line selection both ends are on it
+	lines[#lines] = lines[#lines]:sub(1, range.end_column)
+	lines[1] = lines[1]:sub(range.start_column + 1)
+	return table.concat(lines, "\n")
 end
 
-local sync_scan_todos = function()
-	local result = vim.system(
-		{ "rg", "TODO|FIXME|HACK", "--line-number", "--with-filename", "--no-heading" },
-		{ text = true }
-	)
-		:wait()
-	if result.code == 0 then
-		return parse_rg_output(result.stdout)
-	elseif result.code == 1 then
-		-- ripgrep returns 1 when no matches where found (not an error)
-		return {}
-	else
-		vim.notify("ripgrep error:" .. (result.stderr or "unknown"), vim.log.levels.ERROR)
-		return {}
-	end
+--- Capture the context of a note in the shape expected by the backend's
+--- `NoteContext`.
+local capture_note_ctx = function(opts)
+	local project_dir = vim.fs.root(0, ".git") or vim.fn.getcwd()
+
+	local range = get_selection_range(opts)
+
+	return {
+		selection = get_text_selection(range),
+		filename = vim.fn.expand("%:p"),
+		project_dir = project_dir,
+		start_line = range.start_line,
+		start_column = range.start_column,
+		end_line = range.end_line,
+		end_column = range.end_column,
+		position_encoding = "utf-8",
+	}
 end
 
 local write_request_header = function(request)
@@ -136,14 +142,54 @@ local connect_to_backend = function(req, opts)
 	return client
 end
 
+local request_id = 0
+
+local next_request_id = function()
+	request_id = request_id + 1
+	return request_id
+end
+
 M.new_note = function(opts)
 	local note_ctx = capture_note_ctx(opts)
-	print(note_ctx)
+	vim.ui.input({ prompt = "Note: " }, function(content)
+		if not content or content == "" then
+			return
+		end
+
+		local req = jsonrpc.NewJsonRpcRequest(next_request_id(), "contextual/note/create", {
+			context = note_ctx,
+			content = content,
+		})
+		local client = connect_to_backend(req, {})
+		if not client then
+			vim.notify("failed to create tcp client", vim.log.levels.WARN)
+		end
+	end)
+end
+
+--- The branch checked out in `dir`. Outside of a repository and on a
+--- detached HEAD there is none, but the backend needs one to scope todos by.
+---@param dir string
+---@return string
+local current_branch = function(dir)
+	local result = vim.system({ "git", "-C", dir, "branch", "--show-current" }, { text = true }):wait()
+	local branch = result.code == 0 and vim.trim(result.stdout) or ""
+	if branch == "" then
+		return "default"
+	end
+
+	return branch
 end
 
+--- Let the backend scan the project for todos and reconcile them with the
+--- stored ones. Inside a repository the backend reads the branch itself.
 M.sync_todos = function(opts)
-	local result = sync_scan_todos()
-	local req = jsonrpc.NewJsonRpcRequest(5, "contextual/newTodo", result)
+	local project_dir = vim.fs.root(0, ".git") or vim.fn.getcwd()
+	local req = jsonrpc.NewJsonRpcRequest(next_request_id(), "contextual/scan_todos", {
+		project_dir = project_dir,
+		branch = current_branch(project_dir),
+		sync = true,
+	})
 	local client = connect_to_backend(req, {})
 	if not client then
 		vim.notify("failed to create tcp client", vim.log.levels.WARN)
@@ -151,4 +197,32 @@ M.sync_todos = function(opts)
 	end
 end
 
+--- Let the backend rescan a saved buffer and reconcile only that file's todos.
+---@param bufnr integer
+M.sync_file_todos = function(bufnr)
+	local file_path = vim.api.nvim_buf_get_name(bufnr)
+	if file_path == "" or vim.bo[bufnr].buftype ~= "" then
+		return
+	end
+
+	-- buffers outside of the project have no todos of it
+	local project_dir = vim.fs.normalize(vim.fs.root(bufnr, ".git") or vim.fn.getcwd())
+	file_path = vim.fs.normalize(file_path)
+	if file_path:sub(1, #project_dir + 1) ~= project_dir .. "/" then
+		return
+	end
+
+	local lines = vim.api.nvim_buf_get_lines(bufnr, 0, -1, false)
+	local req = jsonrpc.NewJsonRpcRequest(next_request_id(), "contextual/sync_file_todos", {
+		project_dir = project_dir,
+		branch = current_branch(project_dir),
+		file_path = file_path,
+		contents = table.concat(lines, "\n") .. "\n",
+	})
+	local client = connect_to_backend(req, {})
+	if not client then
+		vim.notify("failed to create tcp client", vim.log.levels.WARN)
+	end
+end
+
 return M
@@ -1,5 +1,5 @@
 vim.api.nvim_create_user_command("NewNote", function(opts)
-	require("contextual").create_note(opts)
+	require("contextual").new_note(opts)
 end, { range = true })
 
 vim.api.nvim_create_user_command("SyncTodos", function(opts)